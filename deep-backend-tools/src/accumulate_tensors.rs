@@ -1,3 +1,4 @@
+use crate::{Dense, Error, Result};
 use std::collections::HashMap;
 use std::ops::AddAssign;
 
@@ -15,16 +16,91 @@ impl<T> AccumulateTensors<T>
 where
     T: Default + for<'a> AddAssign<&'a T> + 'static,
 {
-    fn insert(&mut self, slot: usize, tensors: Vec<T>) {
+    /// Adds the delta of a node's state to any delta it already has.
+    ///
+    /// Fails if the node already has a delta with a different number of tensors. The shapes of
+    /// the tensors aren't known here, so use `insert_dense` to compare them too.
+    pub fn insert(&mut self, node: usize, tensors: Vec<T>) -> Result<()> {
+        self.check(node, &tensors, &|_| None)?;
+        self.insert_unchecked(node, tensors);
+        Ok(())
+    }
+
+    /// This is the same as `insert`, but it also fails if a tensor has a different shape from
+    /// the tensor it is added to.
+    pub fn insert_dense<B>(&mut self, backend: &B, node: usize, tensors: Vec<T>) -> Result<()>
+    where
+        B: Dense<Tensor = T>,
+    {
+        self.check(node, &tensors, &|tensor| Some(backend.shape(tensor)))?;
+        self.insert_unchecked(node, tensors);
+        Ok(())
+    }
+
+    /// Adds another delta to this one.
+    ///
+    /// Fails without changing anything if a node has a different number of tensors in each.
+    /// Use `add_dense` to compare the shapes of the tensors too.
+    pub fn add(&mut self, other: Self) -> Result<()> {
+        self.add_checked(other, &|_| None)
+    }
+
+    /// This is the same as `add`, but it also fails if the tensors of a node have different
+    /// shapes in each.
+    pub fn add_dense<B>(&mut self, backend: &B, other: Self) -> Result<()>
+    where
+        B: Dense<Tensor = T>,
+    {
+        self.add_checked(other, &|tensor| Some(backend.shape(tensor)))
+    }
+
+    fn add_checked(&mut self, other: Self, shape: &dyn Fn(&T) -> Option<Vec<usize>>) -> Result<()> {
+        for (&node, tensors) in &other.table {
+            self.check(node, tensors, shape)?;
+        }
+        for (node, tensors) in other.table {
+            self.insert_unchecked(node, tensors);
+        }
+        Ok(())
+    }
+
+    /// Checks that `tensors` can be added to the delta of `node`, getting the shape of each
+    /// tensor from `shape` if it is known.
+    fn check(
+        &self,
+        node: usize,
+        tensors: &[T],
+        shape: &dyn Fn(&T) -> Option<Vec<usize>>,
+    ) -> Result<()> {
+        let existing = match self.table.get(&node) {
+            Some(existing) => existing,
+            None => return Ok(()),
+        };
+        if existing.len() != tensors.len() {
+            return Err(Error::DeltaMismatch {
+                node,
+                expected: existing.len(),
+                found: tensors.len(),
+            });
+        }
+        for (a, b) in existing.iter().zip(tensors) {
+            if let (Some(expected), Some(found)) = (shape(a), shape(b)) {
+                if expected != found {
+                    return Err(Error::DeltaShapeMismatch {
+                        node,
+                        expected,
+                        found,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_unchecked(&mut self, node: usize, tensors: Vec<T>) {
         use std::collections::hash_map::Entry;
-        match self.table.entry(slot) {
+        match self.table.entry(node) {
             Entry::Occupied(mut o) => {
-                assert_eq!(
-                    o.get().len(),
-                    tensors.len(),
-                    "AccumulateTensors contains different number of tensors \
-                     than what is being added to it for a given op"
-                );
                 for (at, bt) in o.get_mut().iter_mut().zip(tensors) {
                     *at += &bt;
                 }
@@ -40,16 +116,117 @@ impl<T> Extend<(usize, Vec<T>)> for AccumulateTensors<T>
 where
     T: Default + for<'a> AddAssign<&'a T> + 'static,
 {
+    /// Adds the deltas with `insert`.
+    ///
+    /// Panics if a node is given a different number of tensors than it already has.
     fn extend<I>(&mut self, iter: I)
     where
         I: IntoIterator<Item = (usize, Vec<T>)>,
     {
-        for (slot, tensors) in iter {
-            self.insert(slot, tensors);
+        for (node, tensors) in iter {
+            self.insert(node, tensors)
+                .unwrap_or_else(|e| panic!("unable to extend delta: {}", e));
+        }
+    }
+}
+
+impl<T> AccumulateTensors<T>
+where
+    for<'a> &'a T: IntoIterator<Item = &'a f32>,
+    for<'a> &'a mut T: IntoIterator<Item = &'a mut f32>,
+{
+    /// Multiplies every element of the delta by `scale`.
+    pub fn scale(&mut self, scale: f32) {
+        self.map_elements(|n| *n *= scale);
+    }
+
+    /// Computes the L2 norm of the delta of each node.
+    pub fn norms(&self) -> HashMap<usize, f32> {
+        self.table
+            .iter()
+            .map(|(&node, tensors)| (node, squared_norm(tensors).sqrt()))
+            .collect()
+    }
+
+    /// Computes the L2 norm of the whole delta, as if every tensor was flattened into one vector.
+    pub fn global_norm(&self) -> f32 {
+        self.table
+            .values()
+            .map(|tensors| squared_norm(tensors))
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    /// Clamps every element of the delta between `min` and `max`.
+    pub fn clip_by_value(&mut self, min: f32, max: f32) {
+        self.map_elements(|n| *n = n.max(min).min(max));
+    }
+
+    /// Scales the delta of each node with an L2 norm above `max_norm` down to `max_norm`.
+    pub fn clip_by_norm(&mut self, max_norm: f32) {
+        for tensors in self.table.values_mut() {
+            let norm = squared_norm(tensors).sqrt();
+            if norm > max_norm {
+                scale_tensors(tensors, max_norm / norm);
+            }
+        }
+    }
+
+    /// Scales the whole delta down so that its global norm is at most `max_norm`.
+    ///
+    /// Returns the global norm from before clipping.
+    pub fn clip_by_global_norm(&mut self, max_norm: f32) -> f32 {
+        let norm = self.global_norm();
+        if norm > max_norm {
+            self.scale(max_norm / norm);
+        }
+        norm
+    }
+
+    fn map_elements(&mut self, mut f: impl FnMut(&mut f32)) {
+        for tensor in self.table.values_mut().flatten() {
+            tensor.into_iter().for_each(&mut f);
         }
     }
 }
 
+fn squared_norm<T>(tensors: &[T]) -> f32
+where
+    for<'a> &'a T: IntoIterator<Item = &'a f32>,
+{
+    tensors.iter().flatten().map(|n| n * n).sum()
+}
+
+fn scale_tensors<T>(tensors: &mut [T], scale: f32)
+where
+    for<'a> &'a mut T: IntoIterator<Item = &'a mut f32>,
+{
+    for n in tensors.iter_mut().flatten() {
+        *n *= scale;
+    }
+}
+
+/// Collects the deltas of trainable state during backprop.
+pub trait Accumulate<T> {
+    /// Adds the delta of a node's state to any delta it already has, using `backend` to check
+    /// that the tensors match.
+    fn accumulate<B>(&mut self, backend: &B, node: usize, tensors: Vec<T>) -> Result<()>
+    where
+        B: Dense<Tensor = T>;
+}
+
+impl<T> Accumulate<T> for AccumulateTensors<T>
+where
+    T: Default + for<'a> AddAssign<&'a T> + 'static,
+{
+    fn accumulate<B>(&mut self, backend: &B, node: usize, tensors: Vec<T>) -> Result<()>
+    where
+        B: Dense<Tensor = T>,
+    {
+        self.insert_dense(backend, node, tensors)
+    }
+}
+
 impl<T> Default for AccumulateTensors<T> {
     fn default() -> Self {
         Self {
@@ -0,0 +1,306 @@
+//! Dense derivatives built on `Backend::forward`/`backward` and forward mode `Tape`s.
+//!
+//! Derivatives can be taken with respect to an input from the feed dict (`Input::Feed`) or
+//! a parameter node whose value is its first state tensor, such as `Op::TrainConst`
+//! (`Input::Internal`).
+
+use crate::{AccumulateTensors, Feed, Immediate, Propogate, Result, Tangent, Tape};
+use deep::*;
+use std::collections::HashMap;
+
+/// Converts tensors to and from their elements in row-major order, so that derivatives can be
+/// assembled into dense Jacobians.
+pub trait Dense: Backend {
+    /// Gets the shape of a tensor.
+    fn shape(&self, tensor: &Self::Tensor) -> Vec<usize>;
+
+    /// Gets all of the elements of a tensor in row-major order.
+    fn to_vec(&self, tensor: &Self::Tensor) -> Vec<f32>;
+
+    /// Creates a tensor from its elements in row-major order.
+    fn tensor_from_vec(&self, shape: &[usize], elements: Vec<f32>) -> Self::Tensor;
+}
+
+/// The backends that the helpers in this module work with.
+pub trait Differentiable<T>:
+    Backend<
+        Inputs = HashMap<String, T>,
+        Internal = Tape<Self>,
+        Tensor = T,
+        Delta = AccumulateTensors<T>,
+        State = Vec<Vec<T>>,
+        Error = crate::Error,
+    > + Dense
+    + Immediate
+    + Propogate
+    + Tangent
+    + Feed
+    + Sized
+{
+}
+
+impl<B, T> Differentiable<T> for B where
+    B: Backend<
+            Inputs = HashMap<String, T>,
+            Internal = Tape<B>,
+            Tensor = T,
+            Delta = AccumulateTensors<T>,
+            State = Vec<Vec<T>>,
+            Error = crate::Error,
+        > + Dense
+        + Immediate
+        + Propogate
+        + Tangent
+        + Feed
+{
+}
+
+/// Computes the Jacobian of `output` with respect to `wrt`.
+///
+/// The shape of the Jacobian is the shape of `output` followed by the shape of `wrt`.
+/// Reverse mode is used when `output` has fewer elements than `wrt`, and forward mode otherwise,
+/// since each needs one pass per element of `output` or `wrt` respectively.
+pub fn jacobian<B, T>(
+    backend: &B,
+    graph: &Graph,
+    state: &Vec<Vec<T>>,
+    inputs: &HashMap<String, T>,
+    output: Input,
+    wrt: Input,
+) -> Result<T>
+where
+    B: Differentiable<T>,
+    T: Clone,
+{
+    let (value, _) = backend.forward(graph, state, inputs, output.clone())?;
+    let output_len = len(&backend.shape(&value));
+    let wrt_len = len(&backend.shape(&value_of(backend, state, inputs, &wrt)?));
+    if output_len <= wrt_len {
+        jacobian_reverse(backend, graph, state, inputs, output, wrt)
+    } else {
+        jacobian_forward(backend, graph, state, inputs, output, wrt)
+    }
+}
+
+/// Computes the Jacobian of `output` with respect to `wrt` with one backward pass per element
+/// of `output`.
+pub fn jacobian_reverse<B, T>(
+    backend: &B,
+    graph: &Graph,
+    state: &Vec<Vec<T>>,
+    inputs: &HashMap<String, T>,
+    output: Input,
+    wrt: Input,
+) -> Result<T>
+where
+    B: Differentiable<T>,
+    T: Clone,
+{
+    let (value, internal) = backend.forward(graph, state, inputs, output.clone())?;
+    let output_shape = backend.shape(&value);
+    let wrt_shape = backend.shape(&value_of(backend, state, inputs, &wrt)?);
+
+    let mut elements = Vec::with_capacity(len(&output_shape) * len(&wrt_shape));
+    for index in 0..len(&output_shape) {
+        let output_delta = basis(backend, &output_shape, index);
+        let (delta, input_deltas) = backend.backward_inputs(
+            graph,
+            state,
+            &internal,
+            inputs,
+            output.clone(),
+            output_delta,
+        )?;
+        let row = match &wrt {
+            Input::Feed(name) => input_deltas.get(name).cloned(),
+            Input::Internal(internal) => delta
+                .table
+                .get(&internal.node)
+                .and_then(|tensors| tensors.first().cloned()),
+        };
+        match row {
+            Some(row) => elements.extend(backend.to_vec(&row)),
+            // The output doesn't depend on `wrt`.
+            None => elements.extend(vec![0.0; len(&wrt_shape)]),
+        }
+    }
+    Ok(backend.tensor_from_vec(&[&output_shape[..], &wrt_shape[..]].concat(), elements))
+}
+
+/// Computes the Jacobian of `output` with respect to `wrt` with one forward mode pass per element
+/// of `wrt`.
+pub fn jacobian_forward<B, T>(
+    backend: &B,
+    graph: &Graph,
+    state: &Vec<Vec<T>>,
+    inputs: &HashMap<String, T>,
+    output: Input,
+    wrt: Input,
+) -> Result<T>
+where
+    B: Differentiable<T>,
+    T: Clone,
+{
+    let wrt_shape = backend.shape(&value_of(backend, state, inputs, &wrt)?);
+    let columns = (0..len(&wrt_shape))
+        .map(|index| {
+            let tangent = basis(backend, &wrt_shape, index);
+            jvp(backend, graph, state, inputs, output.clone(), &wrt, tangent)
+                .map(|(_, tangent)| tangent)
+        })
+        .collect::<Result<Vec<T>>>()?;
+
+    let (value, _) = backend.forward(graph, state, inputs, output)?;
+    let output_shape = backend.shape(&value);
+    let columns: Vec<Vec<f32>> = columns.iter().map(|c| backend.to_vec(c)).collect();
+    let elements = (0..len(&output_shape))
+        .flat_map(|row| columns.iter().map(move |column| column[row]))
+        .collect();
+    Ok(backend.tensor_from_vec(&[&output_shape[..], &wrt_shape[..]].concat(), elements))
+}
+
+/// Computes the Hessian of the sum of `output` with respect to `wrt`.
+///
+/// The shape of the Hessian is the shape of `wrt` twice.
+pub fn hessian<B, T>(
+    backend: &B,
+    graph: &Graph,
+    state: &[Vec<T>],
+    inputs: &HashMap<String, T>,
+    output: Input,
+    wrt: Input,
+) -> Result<T>
+where
+    B: Differentiable<T>,
+    T: Clone,
+{
+    match gradient_graph(graph, state, output, &wrt) {
+        Some((graph, state, gradient)) => jacobian(backend, &graph, &state, inputs, gradient, wrt),
+        None => {
+            let shape = backend.shape(&value_of(backend, state, inputs, &wrt)?);
+            let shape = [&shape[..], &shape[..]].concat();
+            Ok(backend.tensor_from_vec(&shape, vec![0.0; len(&shape)]))
+        }
+    }
+}
+
+/// Computes the product of the Hessian of the sum of `output` with respect to `wrt` and the
+/// vector `v`, which has the same shape as `wrt`.
+///
+/// This takes a single forward mode pass over the gradient, so the Hessian is never formed.
+pub fn hessian_vector_product<B, T>(
+    backend: &B,
+    graph: &Graph,
+    state: &[Vec<T>],
+    inputs: &HashMap<String, T>,
+    output: Input,
+    wrt: Input,
+    v: T,
+) -> Result<T>
+where
+    B: Differentiable<T>,
+    T: Clone,
+{
+    match gradient_graph(graph, state, output, &wrt) {
+        Some((graph, state, gradient)) => {
+            jvp(backend, &graph, &state, inputs, gradient, &wrt, v).map(|(_, tangent)| tangent)
+        }
+        None => Ok(backend.zeros_like(&v)),
+    }
+}
+
+/// Pushes `tangent` forward from `wrt` to `output`, giving back the value and tangent of `output`.
+fn jvp<B, T>(
+    backend: &B,
+    graph: &Graph,
+    state: &[Vec<T>],
+    inputs: &HashMap<String, T>,
+    output: Input,
+    wrt: &Input,
+    tangent: T,
+) -> Result<(T, T)>
+where
+    B: Differentiable<T>,
+    T: Clone,
+{
+    let mut input_tangents = HashMap::new();
+    let mut state_tangents = HashMap::new();
+    match wrt {
+        Input::Feed(name) => {
+            input_tangents.insert(name.clone(), tangent);
+        }
+        Input::Internal(internal) => {
+            let mut tangents: Vec<T> = state[internal.node]
+                .iter()
+                .map(|s| backend.zeros_like(s))
+                .collect();
+            tangents[0] = tangent;
+            state_tangents.insert(internal.node, tangents);
+        }
+    }
+    Tape::new().solve_tangent(
+        backend,
+        graph,
+        state,
+        inputs,
+        output,
+        &input_tangents,
+        &state_tangents,
+    )
+}
+
+/// Builds the gradient of `output` with respect to `wrt` into a copy of the graph.
+///
+/// The ops of the gradient have no state, so the state is extended with empty state for them.
+fn gradient_graph<T>(
+    graph: &Graph,
+    state: &[Vec<T>],
+    output: Input,
+    wrt: &Input,
+) -> Option<(Graph, Vec<Vec<T>>, Input)>
+where
+    T: Clone,
+{
+    let mut graph = graph.clone();
+    let gradient = graph
+        .gradients(output, std::slice::from_ref(wrt))
+        .pop()
+        .unwrap()?;
+    let mut state = state.to_vec();
+    state.resize_with(graph.ops.len(), Vec::new);
+    Some((graph, state, gradient))
+}
+
+/// Gets the current value of `wrt`.
+fn value_of<B, T>(
+    backend: &B,
+    state: &[Vec<T>],
+    inputs: &HashMap<String, T>,
+    wrt: &Input,
+) -> Result<T>
+where
+    B: Differentiable<T>,
+    T: Clone,
+{
+    match wrt {
+        Input::Feed(name) => backend
+            .feed(inputs, name)
+            .ok_or(crate::Error::InputNotProvided { name: name.clone() }),
+        Input::Internal(internal) => Ok(state[internal.node][0].clone()),
+    }
+}
+
+/// Creates a tensor that is zero everywhere except for a one at `index` in row-major order.
+fn basis<B>(backend: &B, shape: &[usize], index: usize) -> B::Tensor
+where
+    B: Dense,
+{
+    let mut elements = vec![0.0; len(shape)];
+    elements[index] = 1.0;
+    backend.tensor_from_vec(shape, elements)
+}
+
+/// Gets the number of elements in a tensor of a given shape.
+fn len(shape: &[usize]) -> usize {
+    shape.iter().product()
+}
@@ -1,10 +1,37 @@
+// The `Fail` derive generates its impls inside of a const block.
+#![allow(non_local_definitions)]
+
 mod accumulate_tensors;
+pub mod jacobian;
+mod memory;
+mod micro_batch;
+mod named_state;
+mod optimizer;
+mod parallel;
+mod plan;
+pub mod schedule;
+mod tangent;
+mod trainer;
 
-pub use accumulate_tensors::AccumulateTensors;
+pub use accumulate_tensors::{Accumulate, AccumulateTensors};
+pub use jacobian::Dense;
+pub use memory::{MemoryReport, Retention};
+pub use micro_batch::train_micro_batches;
+pub use named_state::{NamedState, NamedStateIter, StateMap};
+pub use optimizer::{Optimizer, Sgd};
+pub use parallel::ThreadPool;
+pub use plan::Plan;
+pub use schedule::Schedule;
+pub use tangent::Tangent;
+pub use trainer::{
+    BatchMetrics, Callback, DataSource, EpochMetrics, Flow, History, MetricLogger, Trainer,
+};
 
 use deep::*;
 use failure::Fail;
-use std::collections::{hash_map::Entry, HashMap};
+use std::cell::{Cell, RefCell};
+use std::collections::{btree_map, hash_map::Entry, BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Debug, Fail)]
 pub enum Error {
@@ -17,6 +44,37 @@ pub enum Error {
     InternalNotComputed { node: usize, ty: Option<OpTy> },
     #[fail(display = "no handler for \"{:?}\"", ty)]
     OpHasNoHandler { ty: OpTy },
+    #[fail(display = "no custom gradient is named \"{}\"", name)]
+    GradientNotFound { name: String },
+    #[fail(display = "no node is named \"{}\"", name)]
+    NameNotFound { name: String },
+    #[fail(
+        display = "node \"{}\" has {} state tensors, but {} were provided",
+        name, expected, found
+    )]
+    StateMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[fail(
+        display = "delta of node {} has {} tensors, but {} were added to it",
+        node, expected, found
+    )]
+    DeltaMismatch {
+        node: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[fail(
+        display = "delta of node {} has shape {:?}, but a tensor with shape {:?} was added to it",
+        node, expected, found
+    )]
+    DeltaShapeMismatch {
+        node: usize,
+        expected: Vec<usize>,
+        found: Vec<usize>,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -54,10 +112,54 @@ pub trait Propogate: Backend {
         state: &[Self::Tensor],
         output_delta: (usize, Self::Tensor),
     ) -> Option<(ImOp<Self>, Vec<Self::Tensor>)>;
+
+    /// This is used for `Op::CustomGradient` in place of the gradient of the subgraph that
+    /// computed `output`. It is given the values of the op's `inputs`, its `output`, and the
+    /// delta of the output, and it must produce one delta per input.
+    ///
+    /// Returns `None` if no custom gradient is registered with the backend under `name`.
+    fn custom_gradient(
+        &self,
+        _name: &str,
+        _inputs: &[Self::Tensor],
+        _output: &Self::Tensor,
+        _output_delta: Self::Tensor,
+    ) -> Option<Vec<Self::Tensor>> {
+        None
+    }
+
+    /// This adds `delta` into `sum`, which are deltas of the same output that have the same
+    /// shape. Backprop uses this to sum the deltas that flow into a node from each of its users.
+    fn add_delta(&self, sum: &mut Self::Tensor, delta: &Self::Tensor);
+
+    /// This says what `propogate` reads of the inputs of `op`, which lets a `Tape` drop the
+    /// values that it doesn't read.
+    ///
+    /// By default every value is read, except by the ops that backprop never propogates through.
+    fn reads(&self, op: &Op) -> Reads {
+        match Reads::of(op) {
+            Reads::Nothing => Reads::Nothing,
+            _ => Reads::Values,
+        }
+    }
 }
 
 pub struct Tape<B: Backend> {
     solved: HashMap<Internal, Vec<B::Tensor>>,
+    /// The tangents of solved nodes, which are only present in forward mode differentiation.
+    tangents: HashMap<Internal, Vec<B::Tensor>>,
+    /// The shapes of the outputs of nodes whose values were dropped because backprop only
+    /// reads their shapes, as per `Propogate::reads`.
+    shapes: HashMap<usize, Vec<Vec<usize>>>,
+    /// The most memory that values recomputed for checkpoints used at once during the last
+    /// backprop through the tape, in bytes.
+    recomputed_peak: AtomicUsize,
+    /// The most memory that the forward pass used at once, in bytes, if it was more than the
+    /// values that the tape still has.
+    forward_peak: Option<usize>,
+    /// The memory used by the deltas of the trainable state from the last backprop through the
+    /// tape, in bytes.
+    delta_bytes: AtomicUsize,
 }
 
 impl<B, T> Default for Tape<B>
@@ -68,6 +170,11 @@ where
     fn default() -> Self {
         Self {
             solved: Default::default(),
+            tangents: Default::default(),
+            shapes: Default::default(),
+            recomputed_peak: Default::default(),
+            forward_peak: None,
+            delta_bytes: Default::default(),
         }
     }
 }
@@ -81,6 +188,27 @@ where
         Self::default()
     }
 
+    /// Records the outputs of a node that were computed outside of the tape, such as by a
+    /// fused kernel, so that they are used rather than being computed again.
+    pub fn insert(&mut self, node: usize, outputs: Vec<B::Tensor>) {
+        for output in 0..outputs.len() {
+            self.solved
+                .insert(Internal { node, output }, outputs.clone());
+        }
+    }
+
+    /// Records the shapes of the outputs of a node whose values aren't kept, since backprop only
+    /// reads their shapes. Backprop is given tensors of zeros with these shapes in their place.
+    pub fn insert_shapes(&mut self, node: usize, shapes: Vec<Vec<usize>>) {
+        self.shapes.insert(node, shapes);
+    }
+
+    /// Gets the most memory that the values of checkpointed nodes used at once while they were
+    /// recomputed during the last backprop through the tape, in bytes.
+    pub fn recomputed_peak(&self) -> usize {
+        self.recomputed_peak.load(Ordering::Relaxed)
+    }
+
     pub fn input(
         &self,
         backend: &B,
@@ -94,7 +222,7 @@ where
         match input {
             Input::Feed(name) => backend
                 .feed(inputs, &name)
-                .ok_or_else(|| Error::InputNotProvided { name }),
+                .ok_or(Error::InputNotProvided { name }),
             Input::Internal(internal) => self
                 .solved
                 .get(&internal)
@@ -121,33 +249,51 @@ where
         match input {
             Input::Feed(name) => backend
                 .feed(inputs, &name)
-                .ok_or_else(|| Error::InputNotProvided { name }),
+                .ok_or(Error::InputNotProvided { name }),
             Input::Internal(internal) => {
                 let op = match self.solved.entry(internal) {
                     Entry::Occupied(o) => return Ok(o.get()[internal.output].clone()),
                     Entry::Vacant(_) => graph.ops[internal.node].clone(),
                 };
-                let ty = (&op).into();
-                ImOp::solve(op, self, backend, graph, state, inputs).and_then(|imop| {
-                    backend
-                        .solve(imop, &state[internal.node][..])
-                        .map(|solutions| {
-                            let output = solutions[internal.output].clone();
-                            self.solved.insert(internal, solutions);
-                            output
-                        })
-                        .ok_or_else(|| Error::OpHasNoHandler { ty })
-                })
+                let imop = ImOp::solve(op, self, backend, graph, state, inputs)?;
+                let solutions = imop.compute(backend, &state[internal.node][..])?;
+                let output = solutions[internal.output].clone();
+                self.solved.insert(internal, solutions);
+                Ok(output)
             }
         }
     }
 
+    /// Drops the values of checkpointed nodes other than `outputs`, which backprop computes
+    /// again if it needs them.
+    ///
+    /// The memory used before they are dropped is kept as the peak of the forward pass.
+    pub fn discard_checkpointed(&mut self, backend: &B, graph: &Graph, outputs: &[Input])
+    where
+        B: Dense,
+    {
+        if graph.checkpointed.is_empty() {
+            return;
+        }
+        let bytes = self.bytes(backend);
+        self.forward_peak = Some(self.forward_peak.map_or(bytes, |peak| peak.max(bytes)));
+        self.solved.retain(|internal, _| {
+            !graph.is_checkpointed(internal.node)
+                || outputs
+                    .iter()
+                    .any(|o| matches!(o, Input::Internal(o) if o.node == internal.node))
+        });
+    }
+
     /// Propogates the output from `output_delta` to all of the pieces that contributed to
     /// the output specified by `input`.
     ///
     /// This process will produce the `Backend::Delta` that can be used to train the state.
+    /// Frozen nodes don't recieve a delta, and parts of the graph that contain no trainable
+    /// state are skipped entirely.
     ///
-    /// This delta is accumulated in the `deltas` parameter utilising its `Extend` impl.
+    /// This delta is accumulated in the `deltas` parameter utilising its `Accumulate` impl.
+    #[allow(clippy::too_many_arguments)]
     pub fn backprop<E>(
         &self,
         backend: &B,
@@ -159,44 +305,516 @@ where
         deltas: E,
     ) -> Result<E>
     where
-        B: Propogate + Feed,
-        E: Extend<(usize, Vec<B::Tensor>)>,
+        B: Propogate + Immediate + Feed + Dense,
+        E: Accumulate<B::Tensor>,
+    {
+        self.backprop_many(
+            backend,
+            graph,
+            state,
+            inputs,
+            vec![(input, output_delta)],
+            deltas,
+        )
+    }
+
+    /// This is the same as `backprop`, but it propogates the deltas of several outputs at once.
+    ///
+    /// The deltas that flow into a node from every output are summed before they are
+    /// propogated through it, so each node is only visited once.
+    pub fn backprop_many<E>(
+        &self,
+        backend: &B,
+        graph: &Graph,
+        state: &[Vec<B::Tensor>],
+        inputs: &B::Inputs,
+        output_deltas: Vec<(Input, B::Tensor)>,
+        deltas: E,
+    ) -> Result<E>
+    where
+        B: Propogate + Immediate + Feed + Dense,
+        E: Accumulate<B::Tensor>,
+    {
+        let sinks = Sinks {
+            deltas,
+            feed_deltas: Discard,
+        };
+        let outputs: Vec<Input> = output_deltas.iter().map(|(o, _)| o.clone()).collect();
+        Backprop::new(self, backend, graph, state, inputs, &outputs, false)
+            .run(output_deltas, sinks)
+            .map(|sinks| sinks.deltas)
+    }
+
+    /// This is the same as `backprop`, but it also propogates the delta to the inputs from
+    /// the feed dict, such as to find the gradient of the loss with respect to the inputs.
+    ///
+    /// The delta of each feed is given to `feed_deltas` once for each place it is used, so
+    /// the deltas of a feed used more than once must be summed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn backprop_feeds<E, F>(
+        &self,
+        backend: &B,
+        graph: &Graph,
+        state: &[Vec<B::Tensor>],
+        inputs: &B::Inputs,
+        input: Input,
+        output_delta: B::Tensor,
+        deltas: E,
+        feed_deltas: F,
+    ) -> Result<(E, F)>
+    where
+        B: Propogate + Immediate + Feed + Dense,
+        E: Accumulate<B::Tensor>,
+        F: Extend<(String, B::Tensor)>,
+    {
+        let sinks = Sinks {
+            deltas,
+            feed_deltas,
+        };
+        Backprop::new(
+            self,
+            backend,
+            graph,
+            state,
+            inputs,
+            std::slice::from_ref(&input),
+            true,
+        )
+        .run(vec![(input, output_delta)], sinks)
+        .map(|sinks| (sinks.deltas, sinks.feed_deltas))
+    }
+}
+
+/// Finds out which nodes that `outputs` depend on lead to trainable state (or to the feeds if
+/// `feeds` is set), since only those need a delta during backprop.
+fn required<T>(graph: &Graph, state: &[Vec<T>], outputs: &[Input], feeds: bool) -> Vec<bool> {
+    fn require<T>(
+        graph: &Graph,
+        state: &[Vec<T>],
+        input: &Input,
+        feeds: bool,
+        visited: &mut [bool],
+        required: &mut [bool],
+    ) -> bool {
+        let node = match input {
+            Input::Feed(_) => return feeds,
+            Input::Internal(internal) => internal.node,
+        };
+        if visited[node] {
+            return required[node];
+        }
+        visited[node] = true;
+        let mut require_node =
+            !graph.is_frozen(node) && state.get(node).is_some_and(|state| !state.is_empty());
+        for input in graph.ops[node].delta_inputs() {
+            require_node |= require(graph, state, input, feeds, visited, required);
+        }
+        required[node] = require_node;
+        require_node
+    }
+
+    let mut visited = vec![false; graph.ops.len()];
+    let mut required = vec![false; graph.ops.len()];
+    for output in outputs {
+        require(graph, state, output, feeds, &mut visited, &mut required);
+    }
+    required
+}
+
+/// What backprop reads of the inputs of an op.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reads {
+    /// The values of the inputs are read.
+    Values,
+    /// Only the shapes of the inputs are read, so tensors of zeros can stand in for them.
+    Shapes,
+    /// Nothing is propogated through the op.
+    Nothing,
+}
+
+impl Reads {
+    /// Finds what the standard gradient of `op` reads of its inputs.
+    ///
+    /// Backends that propogate through `op` some other way must not say this from
+    /// `Propogate::reads`, since a tape would drop values that their gradient reads.
+    pub fn of(op: &Op) -> Self {
+        match op {
+            Op::Add(..)
+            | Op::Sub(..)
+            | Op::Scale(..)
+            | Op::Sum(_)
+            | Op::Transpose(_)
+            | Op::Unbroadcast(..) => Reads::Shapes,
+            Op::StopGradient(_) | Op::OnesLike(_) | Op::Sign(_) => Reads::Nothing,
+            _ => Reads::Values,
+        }
+    }
+}
+
+/// Recieves the deltas of trainable state along with the deltas of inputs from the feed dict.
+trait Deltas<T>: Accumulate<T> + Extend<(String, T)> {}
+
+impl<T, E> Deltas<T> for E where E: Accumulate<T> + Extend<(String, T)> {}
+
+/// Sends the deltas of trainable state and of feeds to different places.
+struct Sinks<E, F> {
+    deltas: E,
+    feed_deltas: F,
+}
+
+impl<T, E, F> Accumulate<T> for Sinks<E, F>
+where
+    E: Accumulate<T>,
+{
+    fn accumulate<B>(&mut self, backend: &B, node: usize, tensors: Vec<T>) -> Result<()>
+    where
+        B: Dense<Tensor = T>,
+    {
+        self.deltas.accumulate(backend, node, tensors)
+    }
+}
+
+impl<T, E, F> Extend<(String, T)> for Sinks<E, F>
+where
+    F: Extend<(String, T)>,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (String, T)>,
+    {
+        self.feed_deltas.extend(iter);
+    }
+}
+
+/// Throws away anything it is extended with.
+struct Discard;
+
+impl<A> Extend<A> for Discard {
+    fn extend<I>(&mut self, _: I)
+    where
+        I: IntoIterator<Item = A>,
+    {
+    }
+}
+
+/// Everything that stays the same while propogating deltas through a graph.
+struct Backprop<'a, B: Backend> {
+    tape: &'a Tape<B>,
+    backend: &'a B,
+    graph: &'a Graph,
+    state: &'a [Vec<B::Tensor>],
+    inputs: &'a B::Inputs,
+    /// Whether deltas are propogated to the inputs from the feed dict.
+    feeds: bool,
+    /// Whether a delta must be propogated to each node.
+    ///
+    /// This is only `true` for nodes that lead to trainable state (or feeds if requested).
+    required: Vec<bool>,
+    /// The outputs of checkpointed nodes that were computed again and are still read later.
+    recomputed: RefCell<HashMap<usize, Vec<B::Tensor>>>,
+    /// The memory used by `recomputed` now and at its peak, in bytes.
+    recomputed_bytes: Cell<usize>,
+    recomputed_peak: Cell<usize>,
+    /// The summed deltas of the nodes that haven't been propogated through yet.
+    pending: RefCell<BTreeMap<Internal, B::Tensor>>,
+    /// The memory used by the deltas of each node with trainable state, in bytes.
+    delta_bytes: RefCell<HashMap<usize, usize>>,
+}
+
+impl<'a, B, T> Backprop<'a, B>
+where
+    B: Backend<Tensor = T> + Propogate + Immediate + Feed + Dense,
+    T: Clone,
+{
+    fn new(
+        tape: &'a Tape<B>,
+        backend: &'a B,
+        graph: &'a Graph,
+        state: &'a [Vec<B::Tensor>],
+        inputs: &'a B::Inputs,
+        outputs: &[Input],
+        feeds: bool,
+    ) -> Self {
+        Self {
+            tape,
+            backend,
+            graph,
+            state,
+            inputs,
+            feeds,
+            required: required(graph, state, outputs, feeds),
+            recomputed: RefCell::new(HashMap::new()),
+            recomputed_bytes: Cell::new(0),
+            recomputed_peak: Cell::new(0),
+            pending: RefCell::new(BTreeMap::new()),
+            delta_bytes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Propogates the deltas of `outputs` back through the graph.
+    ///
+    /// Nodes are visited in reverse topological order, so every delta that flows into a node is
+    /// summed before it is propogated through the node. Checkpointed nodes are computed again
+    /// when a delta first reaches a node that reads them, and dropped once every node that
+    /// reads them has been visited.
+    fn run<E>(&self, outputs: Vec<(Input, T)>, mut deltas: E) -> Result<E>
+    where
+        E: Deltas<T>,
+    {
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        for (output, _) in &outputs {
+            if let Input::Internal(internal) = output {
+                self.order(internal.node, &mut visited, &mut order);
+            }
+        }
+        order.reverse();
+        let mut last_read = HashMap::new();
+        for (position, &node) in order.iter().enumerate() {
+            for input in self.graph.ops[node].inputs() {
+                if let Input::Internal(internal) = input {
+                    last_read.insert(internal.node, position);
+                }
+            }
+        }
+
+        for (output, output_delta) in outputs {
+            deltas = self.backprop(output, output_delta, deltas)?;
+        }
+        for (position, &node) in order.iter().enumerate() {
+            let node_deltas: Vec<(Internal, T)> = {
+                let mut pending = self.pending.borrow_mut();
+                let later = pending.split_off(&Internal {
+                    node: node + 1,
+                    output: 0,
+                });
+                let at = pending.split_off(&Internal { node, output: 0 });
+                pending.extend(later);
+                at.into_iter().collect()
+            };
+            for (internal, output_delta) in node_deltas {
+                let op = self.graph.ops[node].clone();
+                deltas = ImOp::backprop(op, internal, self, output_delta, deltas)?;
+            }
+
+            let mut recomputed = self.recomputed.borrow_mut();
+            let done: Vec<usize> = recomputed
+                .keys()
+                .copied()
+                .filter(|node| last_read.get(node).is_none_or(|&last| last <= position))
+                .collect();
+            for node in done {
+                let solutions = recomputed.remove(&node).unwrap();
+                self.recomputed_bytes
+                    .set(self.recomputed_bytes.get() - self.bytes(&solutions));
+            }
+        }
+        self.tape
+            .recomputed_peak
+            .store(self.recomputed_peak.get(), Ordering::Relaxed);
+        self.tape
+            .delta_bytes
+            .store(self.delta_bytes.borrow().values().sum(), Ordering::Relaxed);
+        Ok(deltas)
+    }
+
+    /// Adds the required nodes that `node` depends on to `order` after the nodes they
+    /// depend on.
+    fn order(&self, node: usize, visited: &mut HashSet<usize>, order: &mut Vec<usize>) {
+        if !self.required[node] || !visited.insert(node) {
+            return;
+        }
+        for input in self.graph.ops[node].delta_inputs() {
+            if let Input::Internal(internal) = input {
+                self.order(internal.node, visited, order);
+            }
+        }
+        order.push(node);
+    }
+
+    /// Gets the memory used by some tensors, in bytes.
+    fn bytes(&self, tensors: &[T]) -> usize {
+        tensors
+            .iter()
+            .map(|tensor| self.backend.shape(tensor).iter().product::<usize>())
+            .sum::<usize>()
+            * std::mem::size_of::<f32>()
+    }
+
+    /// Gets the value of an input for backprop, which is a tensor of zeros with the right shape
+    /// if only its shape was kept.
+    fn value(&self, input: Input) -> Result<T> {
+        if let Input::Internal(internal) = &input {
+            if let (false, Some(shapes)) = (
+                self.tape.solved.contains_key(internal),
+                self.tape.shapes.get(&internal.node),
+            ) {
+                let shape = &shapes[internal.output];
+                let zeros = vec![0.0; shape.iter().product()];
+                return Ok(self.backend.tensor_from_vec(shape, zeros));
+            }
+        }
+        self.solution(input)
+    }
+
+    /// Gets the value of an input from the tape, recomputing it if it was checkpointed.
+    fn solution(&self, input: Input) -> Result<T> {
+        let internal = match input {
+            Input::Internal(internal)
+                if self.graph.is_checkpointed(internal.node)
+                    && !self.tape.solved.contains_key(&internal) =>
+            {
+                internal
+            }
+            input => {
+                return self
+                    .tape
+                    .input(self.backend, self.inputs, self.graph, input)
+            }
+        };
+        if let Some(solutions) = self.recomputed.borrow().get(&internal.node) {
+            return Ok(solutions[internal.output].clone());
+        }
+        let op = &self.graph.ops[internal.node];
+        let solutions = ImOp::build(op, |input| self.solution(input.clone()))?
+            .compute(self.backend, &self.state[internal.node][..])?;
+        let output = solutions[internal.output].clone();
+        let bytes = self.recomputed_bytes.get() + self.bytes(&solutions);
+        self.recomputed_bytes.set(bytes);
+        self.recomputed_peak
+            .set(self.recomputed_peak.get().max(bytes));
+        self.recomputed
+            .borrow_mut()
+            .insert(internal.node, solutions);
+        Ok(output)
+    }
+
+    /// Records the deltas of a node's trainable state unless it is frozen.
+    fn record<E>(&self, node: usize, train_gradients: Vec<T>, deltas: &mut E) -> Result<()>
+    where
+        E: Deltas<T>,
+    {
+        if self.graph.is_frozen(node) {
+            return Ok(());
+        }
+        self.delta_bytes
+            .borrow_mut()
+            .insert(node, self.bytes(&train_gradients));
+        deltas.accumulate(self.backend, node, train_gradients)
+    }
+
+    /// Propogates the delta of an `Op::CustomGradient` with the backend's custom gradient.
+    fn custom_gradient<E>(
+        &self,
+        output: Input,
+        inputs: Vec<Input>,
+        name: String,
+        output_delta: T,
+        deltas: E,
+    ) -> Result<E>
+    where
+        E: Deltas<T>,
+    {
+        let tensor = |input| self.value(input);
+        let output = tensor(output)?;
+        let values = inputs
+            .iter()
+            .cloned()
+            .map(tensor)
+            .collect::<Result<Vec<_>>>()?;
+        let input_deltas = self
+            .backend
+            .custom_gradient(&name, &values, &output, output_delta)
+            .ok_or(Error::GradientNotFound { name })?;
+        assert_eq!(
+            input_deltas.len(),
+            inputs.len(),
+            "custom gradient must produce one delta per input"
+        );
+        inputs
+            .into_iter()
+            .zip(input_deltas)
+            .try_fold(deltas, |deltas, (input, delta)| {
+                self.backprop(input, delta, deltas)
+            })
+    }
+
+    fn backprop<E>(&self, input: Input, output_delta: T, mut deltas: E) -> Result<E>
+    where
+        E: Deltas<T>,
     {
         match input {
-            Input::Feed(_) => Ok(deltas),
-            Input::Internal(internal) => {
-                let op = graph
-                    .ops
-                    .get(internal.node)
-                    .expect("node requested in backprop but does not exist");
-                ImOp::backprop(
-                    op.clone(),
-                    internal,
-                    self,
-                    backend,
-                    graph,
-                    state,
-                    inputs,
-                    output_delta,
-                    deltas,
-                )
+            Input::Feed(name) if self.feeds => {
+                deltas.extend(std::iter::once((name, output_delta)));
+                Ok(deltas)
+            }
+            // The delta is propogated through the node once every delta that flows into it
+            // has been summed.
+            Input::Internal(internal) if self.required[internal.node] => {
+                match self.pending.borrow_mut().entry(internal) {
+                    btree_map::Entry::Occupied(mut o) => {
+                        self.backend.add_delta(o.get_mut(), &output_delta)
+                    }
+                    btree_map::Entry::Vacant(v) => {
+                        v.insert(output_delta);
+                    }
+                }
+                Ok(deltas)
             }
+            _ => Ok(deltas),
         }
     }
 }
 
-#[derive(Clone)]
 pub enum ImOp<B: Backend + ?Sized> {
     Add(B::Tensor, B::Tensor),
     Sub(B::Tensor, B::Tensor),
     Square(B::Tensor),
+    Mul(B::Tensor, B::Tensor),
+    Scale(B::Tensor, f64),
+    Sum(B::Tensor),
+    OnesLike(B::Tensor),
+    Abs(B::Tensor),
+    Sign(B::Tensor),
+    MatMul(B::Tensor, B::Tensor),
+    Transpose(B::Tensor),
+    Conv2d(B::Tensor, B::Tensor),
+    Conv2dInputDelta(B::Tensor, B::Tensor),
+    Conv2dKernelDelta(B::Tensor, B::Tensor),
+    Unbroadcast(B::Tensor, B::Tensor),
     TrainConst,
+    StopGradient(B::Tensor),
+    CustomGradient(B::Tensor, Vec<B::Tensor>),
 }
 
 impl<B> ImOp<B>
 where
     B: Backend,
 {
+    /// Gets the tensors of the op in order.
+    pub fn tensors(&self) -> Vec<&B::Tensor> {
+        match self {
+            ImOp::Add(a, b)
+            | ImOp::Sub(a, b)
+            | ImOp::Mul(a, b)
+            | ImOp::MatMul(a, b)
+            | ImOp::Conv2d(a, b)
+            | ImOp::Conv2dInputDelta(a, b)
+            | ImOp::Conv2dKernelDelta(a, b)
+            | ImOp::Unbroadcast(a, b) => vec![a, b],
+            ImOp::Square(a)
+            | ImOp::Scale(a, _)
+            | ImOp::Sum(a)
+            | ImOp::OnesLike(a)
+            | ImOp::Abs(a)
+            | ImOp::Sign(a)
+            | ImOp::Transpose(a)
+            | ImOp::StopGradient(a) => vec![a],
+            ImOp::TrainConst => vec![],
+            ImOp::CustomGradient(a, inputs) => std::iter::once(a).chain(inputs).collect(),
+        }
+    }
+
     pub fn add(self) -> SResult<(B::Tensor, B::Tensor), Self> {
         if let ImOp::Add(a, b) = self {
             Ok((a, b))
@@ -220,6 +838,86 @@ where
             Err(self)
         }
     }
+
+    pub fn mul(self) -> SResult<(B::Tensor, B::Tensor), Self> {
+        if let ImOp::Mul(a, b) = self {
+            Ok((a, b))
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn scale(self) -> SResult<B::Tensor, Self> {
+        if let ImOp::Scale(a, _) = self {
+            Ok(a)
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn sum(self) -> SResult<B::Tensor, Self> {
+        if let ImOp::Sum(a) = self {
+            Ok(a)
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn abs(self) -> SResult<B::Tensor, Self> {
+        if let ImOp::Abs(a) = self {
+            Ok(a)
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn matmul(self) -> SResult<(B::Tensor, B::Tensor), Self> {
+        if let ImOp::MatMul(a, b) = self {
+            Ok((a, b))
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn transpose(self) -> SResult<B::Tensor, Self> {
+        if let ImOp::Transpose(a) = self {
+            Ok(a)
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn conv2d(self) -> SResult<(B::Tensor, B::Tensor), Self> {
+        if let ImOp::Conv2d(a, b) = self {
+            Ok((a, b))
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn conv2d_input_delta(self) -> SResult<(B::Tensor, B::Tensor), Self> {
+        if let ImOp::Conv2dInputDelta(a, b) = self {
+            Ok((a, b))
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn conv2d_kernel_delta(self) -> SResult<(B::Tensor, B::Tensor), Self> {
+        if let ImOp::Conv2dKernelDelta(a, b) = self {
+            Ok((a, b))
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn unbroadcast(self) -> SResult<(B::Tensor, B::Tensor), Self> {
+        if let ImOp::Unbroadcast(a, b) = self {
+            Ok((a, b))
+        } else {
+            Err(self)
+        }
+    }
 }
 
 impl<B, T> ImOp<B>
@@ -227,9 +925,9 @@ where
     B: Backend<Tensor = T>,
     T: Clone,
 {
-    fn solve<'a>(
+    fn solve(
         op: Op,
-        tape: &'a mut Tape<B>,
+        tape: &mut Tape<B>,
         backend: &B,
         graph: &Graph,
         state: &[Vec<B::Tensor>],
@@ -238,7 +936,27 @@ where
     where
         B: Feed + Immediate,
     {
-        let mut tensor = |input| tape.solve(backend, graph, state, inputs, input);
+        Self::build(&op, |input| {
+            tape.solve(backend, graph, state, inputs, input.clone())
+        })
+    }
+
+    /// Computes the outputs of the op with the backend.
+    fn compute(self, backend: &B, state: &[T]) -> Result<Vec<T>>
+    where
+        B: Immediate,
+    {
+        let ty = (&self).into();
+        match self {
+            // These only change how deltas propogate, so the backend doesn't see them.
+            ImOp::StopGradient(a) | ImOp::CustomGradient(a, _) => Some(vec![a]),
+            imop => backend.solve(imop, state),
+        }
+        .ok_or(Error::OpHasNoHandler { ty })
+    }
+
+    /// Builds the `ImOp` of an op, getting the value of each input from `tensor`.
+    fn build(op: &Op, mut tensor: impl FnMut(&Input) -> Result<T>) -> Result<Self> {
         let mut double = |a, b, f: fn(B::Tensor, B::Tensor) -> Self| {
             tensor(a).and_then(|a| tensor(b).map(|b| f(a, b)))
         };
@@ -246,55 +964,84 @@ where
             Op::Add(a, b) => double(a, b, ImOp::Add),
             Op::Sub(a, b) => double(a, b, ImOp::Sub),
             Op::Square(a) => tensor(a).map(ImOp::Square),
+            Op::Mul(a, b) => double(a, b, ImOp::Mul),
+            Op::Scale(a, scale) => tensor(a).map(|a| ImOp::Scale(a, *scale)),
+            Op::Sum(a) => tensor(a).map(ImOp::Sum),
+            Op::OnesLike(a) => tensor(a).map(ImOp::OnesLike),
+            Op::Abs(a) => tensor(a).map(ImOp::Abs),
+            Op::Sign(a) => tensor(a).map(ImOp::Sign),
+            Op::MatMul(a, b) => double(a, b, ImOp::MatMul),
+            Op::Transpose(a) => tensor(a).map(ImOp::Transpose),
+            Op::Conv2d(a, b) => double(a, b, ImOp::Conv2d),
+            Op::Conv2dInputDelta(a, b) => double(a, b, ImOp::Conv2dInputDelta),
+            Op::Conv2dKernelDelta(a, b) => double(a, b, ImOp::Conv2dKernelDelta),
+            Op::Unbroadcast(a, like) => double(a, like, ImOp::Unbroadcast),
             Op::TrainConst(..) => Ok(ImOp::TrainConst),
+            Op::StopGradient(a) => tensor(a).map(ImOp::StopGradient),
+            Op::CustomGradient(a, inputs, _) => {
+                let output = tensor(a)?;
+                inputs
+                    .iter()
+                    .map(tensor)
+                    .collect::<Result<_>>()
+                    .map(|inputs| ImOp::CustomGradient(output, inputs))
+            }
         }
     }
 
     /// This takes the output delta of a particular output from the op and propogates it backwards to the inputs.
-    fn backprop<'a, E>(
+    #[allow(clippy::type_complexity)]
+    fn backprop<E>(
         op: Op,
         internal: Internal,
-        tape: &Tape<B>,
-        backend: &B,
-        graph: &Graph,
-        state: &[Vec<B::Tensor>],
-        inputs: &B::Inputs,
+        context: &Backprop<B>,
         output_delta: B::Tensor,
         deltas: E,
     ) -> Result<E>
     where
-        B: Propogate + Feed,
-        E: Extend<(usize, Vec<B::Tensor>)>,
+        B: Propogate + Immediate + Feed + Dense,
+        E: Deltas<B::Tensor>,
     {
+        let op = match op {
+            // Nothing is propogated through these ops.
+            Op::StopGradient(_) | Op::OnesLike(_) | Op::Sign(_) => return Ok(deltas),
+            // The user-supplied gradient replaces the gradient of the whole subgraph.
+            Op::CustomGradient(a, inputs, name) => {
+                return context.custom_gradient(a, inputs, name, output_delta, deltas)
+            }
+            op => op,
+        };
+
         // Get the op type.
         let ty = (&op).into();
 
         // Get one tensor that is either an input or has been precomputed.
         // Anything else is an error.
-        let tensor = |input, tape: &Tape<B>| tape.input(backend, inputs, graph, input);
+        let tensor = |input| context.value(input);
 
         // This calls backend.propogate to invoke the actual implementation of the backprop for this op.
         let gradients = |imop| {
-            backend
+            context
+                .backend
                 .propogate(
                     imop,
-                    state
+                    context
+                        .state
                         .get(internal.node)
                         .expect("operation doesn't have any state")
                         .as_slice(),
                     (internal.output, output_delta),
                 )
-                .ok_or_else(|| Error::OpHasNoHandler { ty })
+                .ok_or(Error::OpHasNoHandler { ty })
         };
 
         // This is to appease the borrow checker because I was getting moved closure errors.
         let gradients1 = gradients.clone();
         let gradients2 = gradients.clone();
+        let gradients3 = gradients.clone();
 
         // This recursively backprops to send the gradient to a new graph node.
-        let backprop = |input, output_delta, tape: &Tape<B>, deltas| {
-            tape.backprop(backend, graph, state, inputs, input, output_delta, deltas)
-        };
+        let backprop = |input, output_delta, deltas| context.backprop(input, output_delta, deltas);
 
         // This performs the backprop for an op with two parameters.
         // It will update the delta for this op and recursively backprop to its inputs.
@@ -305,12 +1052,12 @@ where
                       fimop: fn(B::Tensor, B::Tensor) -> Self,
                       fundo: fn(ImOp<B>) -> SResult<(B::Tensor, B::Tensor), Self>,
                       mut deltas: E| {
-            tensor(ia.clone(), tape)
-                .and_then(|a| tensor(ib.clone(), tape).map(|b| fimop(a, b)))
+            tensor(ia.clone())
+                .and_then(|a| tensor(ib.clone()).map(|b| fimop(a, b)))
                 .and_then(gradients2)
-                .map(|(input_gradients, train_gradients)| {
-                    deltas.extend(std::iter::once((internal.node, train_gradients)));
-                    input_gradients
+                .and_then(|(input_gradients, train_gradients)| {
+                    context.record(internal.node, train_gradients, &mut deltas)?;
+                    Ok(input_gradients)
                 })
                 .map(|imop| {
                     fundo(imop).unwrap_or_else(|imop| {
@@ -319,8 +1066,8 @@ where
                     })
                 })
                 .and_then(|(ta, tb)| {
-                    let deltas = backprop(ia, ta, tape, deltas)?;
-                    backprop(ib, tb, tape, deltas)
+                    let deltas = backprop(ia, ta, deltas)?;
+                    backprop(ib, tb, deltas)
                 })
         };
 
@@ -329,15 +1076,15 @@ where
         // This requires the input, a function to turn the input into an ImOp, and a function to decompose the
         // ImOp into its tensor to pass the gradient backwards.
         let unary = |ia: Input,
-                     fimop: fn(B::Tensor) -> Self,
+                     fimop: &dyn Fn(B::Tensor) -> Self,
                      fundo: fn(ImOp<B>) -> SResult<B::Tensor, Self>,
                      mut deltas: E| {
-            tensor(ia.clone(), tape)
+            tensor(ia.clone())
                 .map(fimop)
                 .and_then(gradients1)
-                .map(|(input_gradients, train_gradients)| {
-                    deltas.extend(std::iter::once((internal.node, train_gradients)));
-                    input_gradients
+                .and_then(|(input_gradients, train_gradients)| {
+                    context.record(internal.node, train_gradients, &mut deltas)?;
+                    Ok(input_gradients)
                 })
                 .map(|imop| {
                     fundo(imop).unwrap_or_else(|imop| {
@@ -345,22 +1092,85 @@ where
                         panic!("op \"{:?}\" gave back ImOp type \"{:?}\"", ty, imop_ty);
                     })
                 })
-                .and_then(|ta| backprop(ia, ta, tape, deltas))
+                .and_then(|ta| backprop(ia, ta, deltas))
         };
 
         // This updates the delta for this op only. It has no runtime inputs, so it does not recurse.
         let nullary = |imop: Self, mut deltas: E| {
-            gradients(imop).map(|(_, train_gradients)| {
-                deltas.extend(std::iter::once((internal.node, train_gradients)));
-                deltas
-            })
+            let (_, train_gradients) = gradients(imop)?;
+            context.record(internal.node, train_gradients, &mut deltas)?;
+            Ok(deltas)
         };
 
         match op {
             Op::Add(a, b) => binary(a, b, ImOp::Add, ImOp::add, deltas),
             Op::Sub(a, b) => binary(a, b, ImOp::Sub, ImOp::sub, deltas),
-            Op::Square(a) => unary(a, ImOp::Square, ImOp::square, deltas),
+            Op::Square(a) => unary(a, &ImOp::Square, ImOp::square, deltas),
+            Op::Mul(a, b) => binary(a, b, ImOp::Mul, ImOp::mul, deltas),
+            Op::Scale(a, scale) => unary(a, &|a| ImOp::Scale(a, scale), ImOp::scale, deltas),
+            Op::Sum(a) => unary(a, &ImOp::Sum, ImOp::sum, deltas),
+            Op::Abs(a) => unary(a, &ImOp::Abs, ImOp::abs, deltas),
+            Op::MatMul(a, b) => binary(a, b, ImOp::MatMul, ImOp::matmul, deltas),
+            Op::Transpose(a) => unary(a, &ImOp::Transpose, ImOp::transpose, deltas),
+            Op::Conv2d(a, b) => binary(a, b, ImOp::Conv2d, ImOp::conv2d, deltas),
+            Op::Conv2dInputDelta(a, b) => binary(
+                a,
+                b,
+                ImOp::Conv2dInputDelta,
+                ImOp::conv2d_input_delta,
+                deltas,
+            ),
+            Op::Conv2dKernelDelta(a, b) => binary(
+                a,
+                b,
+                ImOp::Conv2dKernelDelta,
+                ImOp::conv2d_kernel_delta,
+                deltas,
+            ),
+            // The second input only gives the shape, so it doesn't recieve a delta.
+            Op::Unbroadcast(a, like) => {
+                let imop = ImOp::Unbroadcast(tensor(a.clone())?, tensor(like)?);
+                let (input_gradients, _) = gradients3(imop)?;
+                let (ta, _) = input_gradients.unbroadcast().unwrap_or_else(|imop| {
+                    let imop_ty: OpTy = (&imop).into();
+                    panic!("op \"{:?}\" gave back ImOp type \"{:?}\"", ty, imop_ty);
+                });
+                backprop(a, ta, deltas)
+            }
             Op::TrainConst(..) => nullary(ImOp::TrainConst, deltas),
+            Op::StopGradient(_) | Op::OnesLike(_) | Op::Sign(_) | Op::CustomGradient(..) => {
+                unreachable!()
+            }
+        }
+    }
+}
+
+// This isn't derived, since that would require the backend itself to be `Clone`.
+impl<B> Clone for ImOp<B>
+where
+    B: Backend,
+    B::Tensor: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            ImOp::Add(a, b) => ImOp::Add(a.clone(), b.clone()),
+            ImOp::Sub(a, b) => ImOp::Sub(a.clone(), b.clone()),
+            ImOp::Square(a) => ImOp::Square(a.clone()),
+            ImOp::Mul(a, b) => ImOp::Mul(a.clone(), b.clone()),
+            ImOp::Scale(a, scale) => ImOp::Scale(a.clone(), *scale),
+            ImOp::Sum(a) => ImOp::Sum(a.clone()),
+            ImOp::OnesLike(a) => ImOp::OnesLike(a.clone()),
+            ImOp::Abs(a) => ImOp::Abs(a.clone()),
+            ImOp::Sign(a) => ImOp::Sign(a.clone()),
+            ImOp::MatMul(a, b) => ImOp::MatMul(a.clone(), b.clone()),
+            ImOp::Transpose(a) => ImOp::Transpose(a.clone()),
+            ImOp::Conv2d(a, b) => ImOp::Conv2d(a.clone(), b.clone()),
+            ImOp::Conv2dInputDelta(a, b) => ImOp::Conv2dInputDelta(a.clone(), b.clone()),
+            ImOp::Conv2dKernelDelta(a, b) => ImOp::Conv2dKernelDelta(a.clone(), b.clone()),
+            ImOp::Unbroadcast(a, like) => ImOp::Unbroadcast(a.clone(), like.clone()),
+            ImOp::TrainConst => ImOp::TrainConst,
+            ImOp::StopGradient(a) => ImOp::StopGradient(a.clone()),
+            ImOp::CustomGradient(a, inputs) => ImOp::CustomGradient(a.clone(), inputs.clone()),
         }
     }
 }
@@ -374,7 +1184,21 @@ where
             ImOp::Add(..) => OpTy::Add,
             ImOp::Sub(..) => OpTy::Sub,
             ImOp::Square(..) => OpTy::Square,
+            ImOp::Mul(..) => OpTy::Mul,
+            ImOp::Scale(..) => OpTy::Scale,
+            ImOp::Sum(..) => OpTy::Sum,
+            ImOp::OnesLike(..) => OpTy::OnesLike,
+            ImOp::Abs(..) => OpTy::Abs,
+            ImOp::Sign(..) => OpTy::Sign,
+            ImOp::MatMul(..) => OpTy::MatMul,
+            ImOp::Transpose(..) => OpTy::Transpose,
+            ImOp::Conv2d(..) => OpTy::Conv2d,
+            ImOp::Conv2dInputDelta(..) => OpTy::Conv2dInputDelta,
+            ImOp::Conv2dKernelDelta(..) => OpTy::Conv2dKernelDelta,
+            ImOp::Unbroadcast(..) => OpTy::Unbroadcast,
             ImOp::TrainConst => OpTy::TrainConst,
+            ImOp::StopGradient(..) => OpTy::StopGradient,
+            ImOp::CustomGradient(..) => OpTy::CustomGradient,
         }
     }
 }
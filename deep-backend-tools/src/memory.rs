@@ -0,0 +1,243 @@
+//! Liveness analysis that lets a `Tape` drop values that nothing will read again.
+
+use crate::{required, Dense, Feed, ImOp, Immediate, Propogate, Reads, Result, Tape};
+use deep::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+
+/// Which values a `Tape` keeps after the forward pass.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Retention {
+    /// Every value is kept.
+    #[default]
+    All,
+    /// Values are dropped as soon as the last op that uses them has run, so only the output is
+    /// kept and the tape can't be used for backprop.
+    Inference,
+    /// Only the values that backprop reads on the way to trainable state are kept, along with
+    /// the shapes of values that it only needs the shapes of. The tape can't be used to
+    /// propogate deltas back to the feeds.
+    Training,
+    /// Like `Training`, but also keeps the values needed to propogate deltas back to the feeds,
+    /// as `Backend::backward_inputs` does.
+    TrainingInputs,
+}
+
+/// The memory used by the values of a `Tape`, in bytes.
+///
+/// Every value is counted as its own allocation, even if it shares memory with another tensor
+/// (such as a parameter that shares memory with the state). Feeds and state aren't counted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The most memory that was used at once.
+    pub peak: usize,
+    /// The memory still used by the values that were kept.
+    pub retained: usize,
+}
+
+impl<B, T> Tape<B>
+where
+    B: Backend<Tensor = T>,
+    T: Clone,
+{
+    /// Solves `outputs` like `solve`, but drops the values that `retention` doesn't keep as
+    /// soon as the last op that uses them has run, keeping track of how much memory was used.
+    ///
+    /// The outputs share the values they depend on, so each of them is only computed once.
+    #[allow(clippy::too_many_arguments)]
+    pub fn solve_planned(
+        &mut self,
+        backend: &B,
+        graph: &Graph,
+        state: &[Vec<T>],
+        inputs: &B::Inputs,
+        outputs: &[Input],
+        retention: Retention,
+    ) -> Result<Vec<T>>
+    where
+        B: Dense + Immediate + Feed + Propogate,
+    {
+        let output_nodes: HashSet<usize> = outputs.iter().filter_map(internal_node).collect();
+
+        // The nodes left to solve, which come after the nodes they use.
+        let solved: HashSet<usize> = self.solved.keys().map(|internal| internal.node).collect();
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        for output in outputs.iter().filter_map(internal_node) {
+            unsolved(graph, output, &solved, &mut visited, &mut order);
+        }
+        let mut last_use = HashMap::new();
+        for (position, &node) in order.iter().enumerate() {
+            for input in internal_inputs(&graph.ops[node]) {
+                last_use.insert(input, position);
+            }
+        }
+        let mut kept = HashSet::new();
+        let mut shaped = HashSet::new();
+        for output in outputs {
+            let (values, shapes) = retained(backend, graph, state, output, retention);
+            kept.extend(values);
+            shaped.extend(shapes);
+        }
+
+        let mut live = self.bytes(backend);
+        let mut peak = live;
+        for (position, &node) in order.iter().enumerate() {
+            let op = &graph.ops[node];
+            let solutions = ImOp::build(op, |input| {
+                self.input(backend, inputs, graph, input.clone())
+            })?
+            .compute(backend, &state[node][..])?;
+            live += bytes(backend, &solutions);
+            peak = peak.max(live);
+            self.insert(node, solutions);
+
+            for input in internal_inputs(&graph.ops[node]) {
+                if last_use[&input] == position
+                    && !output_nodes.contains(&input)
+                    && !kept.contains(&input)
+                {
+                    if let Some(solutions) = self.remove(input) {
+                        live -= bytes(backend, &solutions);
+                        if shaped.contains(&input) {
+                            let shapes = solutions.iter().map(|s| backend.shape(s)).collect();
+                            self.insert_shapes(input, shapes);
+                        }
+                    }
+                }
+            }
+        }
+        self.forward_peak = Some(peak);
+        outputs
+            .iter()
+            .map(|output| self.solve(backend, graph, state, inputs, output.clone()))
+            .collect()
+    }
+
+    /// Gets the memory used by the values of the tape, including the deltas and the values
+    /// recomputed for checkpoints during the last backprop through it.
+    pub fn memory_report(&self, backend: &B) -> MemoryReport
+    where
+        B: Dense,
+    {
+        let retained = self.bytes(backend);
+        let backward = retained + self.delta_bytes.load(Ordering::Relaxed) + self.recomputed_peak();
+        MemoryReport {
+            peak: self.forward_peak.unwrap_or(retained).max(backward),
+            retained,
+        }
+    }
+
+    /// Gets the memory used by every value in the tape.
+    pub fn bytes(&self, backend: &B) -> usize
+    where
+        B: Dense,
+    {
+        let mut counted = HashSet::new();
+        self.solved
+            .iter()
+            .filter(|(internal, _)| counted.insert(internal.node))
+            .map(|(_, solutions)| bytes(backend, solutions))
+            .sum()
+    }
+
+    /// Removes the outputs of a node, giving them back if it had been solved.
+    fn remove(&mut self, node: usize) -> Option<Vec<T>> {
+        let solutions = self.solved.remove(&Internal { node, output: 0 })?;
+        for output in 1..solutions.len() {
+            self.solved.remove(&Internal { node, output });
+        }
+        Some(solutions)
+    }
+}
+
+impl MemoryReport {
+    /// A report for a tape that keeps everything it uses.
+    pub fn new(bytes: usize) -> Self {
+        Self {
+            peak: bytes,
+            retained: bytes,
+        }
+    }
+}
+
+/// Adds the nodes that `node` depends on to `order` after their own dependencies, stopping at
+/// nodes that are already solved.
+fn unsolved(
+    graph: &Graph,
+    node: usize,
+    solved: &HashSet<usize>,
+    visited: &mut HashSet<usize>,
+    order: &mut Vec<usize>,
+) {
+    if solved.contains(&node) || !visited.insert(node) {
+        return;
+    }
+    for input in internal_inputs(&graph.ops[node]) {
+        unsolved(graph, input, solved, visited, order);
+    }
+    order.push(node);
+}
+
+/// The nodes whose values `retention` keeps even after their last use in the forward pass,
+/// along with the nodes whose shapes it keeps instead.
+fn retained<B>(
+    backend: &B,
+    graph: &Graph,
+    state: &[Vec<B::Tensor>],
+    output: &Input,
+    retention: Retention,
+) -> (HashSet<usize>, HashSet<usize>)
+where
+    B: Propogate,
+{
+    let feeds = match retention {
+        Retention::All => return ((0..graph.ops.len()).collect(), HashSet::new()),
+        Retention::Inference => return (HashSet::new(), HashSet::new()),
+        Retention::Training => false,
+        Retention::TrainingInputs => true,
+    };
+    let mut values = HashSet::new();
+    let mut shapes = HashSet::new();
+    for (node, required) in required(graph, state, std::slice::from_ref(output), feeds)
+        .into_iter()
+        .enumerate()
+    {
+        if !required {
+            continue;
+        }
+        match backend.reads(&graph.ops[node]) {
+            Reads::Values => values.extend(internal_inputs(&graph.ops[node])),
+            Reads::Shapes => shapes.extend(internal_inputs(&graph.ops[node])),
+            Reads::Nothing => {}
+        }
+    }
+    // Checkpointed nodes are computed again from their inputs during backprop.
+    for &node in &graph.checkpointed {
+        values.extend(internal_inputs(&graph.ops[node]));
+    }
+    shapes.retain(|node| !values.contains(node));
+    (values, shapes)
+}
+
+fn internal_inputs(op: &Op) -> Vec<usize> {
+    op.inputs().into_iter().filter_map(internal_node).collect()
+}
+
+fn internal_node(input: &Input) -> Option<usize> {
+    match input {
+        Input::Internal(internal) => Some(internal.node),
+        Input::Feed(_) => None,
+    }
+}
+
+fn bytes<B>(backend: &B, tensors: &[B::Tensor]) -> usize
+where
+    B: Dense,
+{
+    tensors
+        .iter()
+        .map(|tensor| backend.shape(tensor).iter().product::<usize>())
+        .sum::<usize>()
+        * std::mem::size_of::<f32>()
+}
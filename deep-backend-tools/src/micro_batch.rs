@@ -0,0 +1,42 @@
+use crate::{AccumulateTensors, Result};
+use deep::*;
+use std::ops::AddAssign;
+
+/// Trains the graph with `loss` as a loss function over several micro-batches as if they were
+/// one large batch, so that a batch never has to fit in memory all at once.
+///
+/// A forward and backward pass is run for each of the inputs in `micro_batches` using
+/// `output_delta` as per `Tensor::delta`. The deltas are merged, averaged over the number of
+/// micro-batches and then applied with a single call to `Backend::train`. If `loss` is the
+/// mean over its batch and the micro-batches are the same size, this is the same as training
+/// on the whole batch at once.
+///
+/// Returns what was extracted by `output_delta` for each micro-batch, along with the `Delta`
+/// that was applied. Nothing is applied if there are no micro-batches.
+pub fn train_micro_batches<B, T, L>(
+    backend: &B,
+    loss: &Tensor,
+    state: &mut B::State,
+    micro_batches: impl IntoIterator<Item = B::Inputs>,
+    mut output_delta: impl FnMut(T) -> (L, T),
+) -> Result<(Vec<L>, AccumulateTensors<T>)>
+where
+    B: Backend<Tensor = T, Delta = AccumulateTensors<T>, Error = crate::Error>,
+    T: Default + for<'a> AddAssign<&'a T> + 'static,
+    for<'a> &'a T: IntoIterator<Item = &'a f32>,
+    for<'a> &'a mut T: IntoIterator<Item = &'a mut f32>,
+{
+    let mut extracted = vec![];
+    let mut merged = AccumulateTensors::new();
+    for inputs in micro_batches {
+        let (value, delta) = loss.delta(backend, state, &inputs, &mut output_delta)?;
+        extracted.push(value);
+        merged.add(delta)?;
+    }
+
+    if !extracted.is_empty() {
+        merged.scale(1.0 / extracted.len() as f32);
+        backend.train(&loss.graph(), state, &merged)?;
+    }
+    Ok((extracted, merged))
+}
@@ -0,0 +1,104 @@
+use crate::{Error, Result};
+use deep::Graph;
+use std::collections::{btree_map, BTreeMap};
+
+/// A map from node names to their state tensors.
+///
+/// Unlike the positional state, this stays valid when the graph is edited, so it can be
+/// used for checkpoints and to transfer state between graphs that share parameter names.
+pub type StateMap<T> = BTreeMap<String, Vec<T>>;
+
+/// Accesses a positional state (one `Vec` of tensors per node) by the names in a `Graph`.
+pub trait NamedState<T> {
+    /// Gets the state of the node with the given name.
+    fn get_named(&self, graph: &Graph, name: &str) -> Option<&[T]>;
+
+    /// Replaces the state of the node with the given name.
+    ///
+    /// The number of tensors must match the existing state of the node.
+    fn set_named(&mut self, graph: &Graph, name: &str, tensors: Vec<T>) -> Result<()>;
+
+    /// Iterates over the state of every named node in name order.
+    fn iter_named<'a>(&'a self, graph: &'a Graph) -> NamedStateIter<'a, T>;
+
+    /// Copies the state of every named node into a `StateMap`.
+    fn state_map(&self, graph: &Graph) -> StateMap<T>;
+
+    /// Loads every entry of `map` whose name exists in `graph`, ignoring the rest.
+    ///
+    /// Returns the number of nodes that were loaded.
+    fn load_state_map(&mut self, graph: &Graph, map: &StateMap<T>) -> Result<usize>;
+}
+
+impl<T> NamedState<T> for Vec<Vec<T>>
+where
+    T: Clone,
+{
+    fn get_named(&self, graph: &Graph, name: &str) -> Option<&[T]> {
+        graph
+            .node(name)
+            .and_then(|node| self.get(node))
+            .map(Vec::as_slice)
+    }
+
+    fn set_named(&mut self, graph: &Graph, name: &str, tensors: Vec<T>) -> Result<()> {
+        let state = graph
+            .node(name)
+            .and_then(|node| self.get_mut(node))
+            .ok_or_else(|| Error::NameNotFound {
+                name: name.to_owned(),
+            })?;
+        if state.len() != tensors.len() {
+            return Err(Error::StateMismatch {
+                name: name.to_owned(),
+                expected: state.len(),
+                found: tensors.len(),
+            });
+        }
+        *state = tensors;
+        Ok(())
+    }
+
+    fn iter_named<'a>(&'a self, graph: &'a Graph) -> NamedStateIter<'a, T> {
+        NamedStateIter {
+            names: graph.names.iter(),
+            state: self,
+        }
+    }
+
+    fn state_map(&self, graph: &Graph) -> StateMap<T> {
+        self.iter_named(graph)
+            .map(|(name, tensors)| (name.to_owned(), tensors.to_vec()))
+            .collect()
+    }
+
+    fn load_state_map(&mut self, graph: &Graph, map: &StateMap<T>) -> Result<usize> {
+        let mut loaded = 0;
+        for (name, tensors) in map {
+            if graph.node(name).is_some() {
+                self.set_named(graph, name, tensors.clone())?;
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+/// Iterates over the names in a graph along with their state.
+pub struct NamedStateIter<'a, T> {
+    names: btree_map::Iter<'a, String, usize>,
+    state: &'a [Vec<T>],
+}
+
+impl<'a, T> Iterator for NamedStateIter<'a, T> {
+    type Item = (&'a str, &'a [T]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let state = self.state;
+        self.names.find_map(|(name, &node)| {
+            state
+                .get(node)
+                .map(|tensors| (name.as_str(), tensors.as_slice()))
+        })
+    }
+}
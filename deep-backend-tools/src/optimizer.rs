@@ -0,0 +1,138 @@
+use crate::{AccumulateTensors, Schedule};
+use deep::*;
+use std::collections::BTreeSet;
+
+/// Turns the gradient of a loss into a delta and applies it to the state.
+pub trait Optimizer<B: Backend> {
+    /// Takes one step of training using `gradient`, which is the `Delta` produced by
+    /// propogating the gradient of the loss (not its negation) back through the graph.
+    ///
+    /// Returns the `Delta` that was applied to the state.
+    fn step(
+        &mut self,
+        backend: &B,
+        graph: &Graph,
+        state: &mut B::State,
+        gradient: B::Delta,
+    ) -> Result<B::Delta, B::Error>;
+
+    /// Informs the optimizer of a metric measured after the last step, such as the validation
+    /// loss, so that its learning-rate schedule can adapt.
+    fn observe(&mut self, _metric: f32) {}
+
+    /// Computes the gradient of `loss` and takes a step with it.
+    ///
+    /// `output_delta` is given the value of `loss` and must give back anything it wants to
+    /// extract from it along with the gradient to propogate, which is usually all ones.
+    ///
+    /// Returns what was extracted along with the `Delta` that was applied.
+    fn minimize<L>(
+        &mut self,
+        backend: &B,
+        loss: &Tensor,
+        state: &mut B::State,
+        inputs: &B::Inputs,
+        output_delta: impl FnOnce(B::Tensor) -> (L, B::Tensor),
+    ) -> Result<(L, B::Delta), B::Error> {
+        let (extracted, gradient) = loss.delta(backend, state, inputs, output_delta)?;
+        let delta = self.step(backend, &loss.graph(), state, gradient)?;
+        Ok((extracted, delta))
+    }
+}
+
+/// Stochastic gradient descent with a learning-rate schedule.
+///
+/// It can also apply weight decay, which is decoupled from the gradient: every parameter that is
+/// trained shrinks by `learning_rate * weight_decay` times itself each step. Only parameters in
+/// the gradient are trained, so those that the loss doesn't depend on aren't decayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sgd<S> {
+    pub schedule: S,
+    /// The number of steps taken so far.
+    pub step: usize,
+    pub weight_decay: f32,
+    /// The names of the nodes whose state isn't decayed, such as biases. A name also excludes
+    /// every node named under it, as per `Graph::scope_nodes`.
+    pub no_decay: BTreeSet<String>,
+}
+
+impl<S> Sgd<S>
+where
+    S: Schedule,
+{
+    pub fn new(schedule: S) -> Self {
+        Self {
+            schedule,
+            step: 0,
+            weight_decay: 0.0,
+            no_decay: BTreeSet::new(),
+        }
+    }
+
+    /// Sets the weight decay.
+    pub fn weight_decay(self, weight_decay: f32) -> Self {
+        Self {
+            weight_decay,
+            ..self
+        }
+    }
+
+    /// Excludes the state of the nodes named `scopes` or named under them from weight decay.
+    pub fn no_decay<I>(mut self, scopes: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.no_decay.extend(scopes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Gets the learning rate that the next step will use.
+    pub fn learning_rate(&self) -> f32 {
+        self.schedule.learning_rate(self.step)
+    }
+}
+
+impl<B, T, S> Optimizer<B> for Sgd<S>
+where
+    B: Backend<Tensor = T, Delta = AccumulateTensors<T>, State = Vec<Vec<T>>>,
+    for<'a> &'a T: IntoIterator<Item = &'a f32>,
+    for<'a> &'a mut T: IntoIterator<Item = &'a mut f32>,
+    S: Schedule,
+{
+    fn step(
+        &mut self,
+        backend: &B,
+        graph: &Graph,
+        state: &mut B::State,
+        mut gradient: AccumulateTensors<T>,
+    ) -> Result<AccumulateTensors<T>, B::Error> {
+        let learning_rate = self.learning_rate();
+        gradient.scale(-learning_rate);
+        if self.weight_decay != 0.0 {
+            let decay = -learning_rate * self.weight_decay;
+            let excluded: BTreeSet<usize> = self
+                .no_decay
+                .iter()
+                .flat_map(|scope| graph.scope_nodes(scope))
+                .collect();
+            for (node, deltas) in &mut gradient.table {
+                if excluded.contains(node) {
+                    continue;
+                }
+                for (delta, tensor) in deltas.iter_mut().zip(&state[*node]) {
+                    for (d, &n) in delta.into_iter().zip(tensor) {
+                        *d += decay * n;
+                    }
+                }
+            }
+        }
+        backend.train(graph, state, &gradient)?;
+        self.step += 1;
+        Ok(gradient)
+    }
+
+    fn observe(&mut self, metric: f32) {
+        self.schedule.observe(self.step, metric);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{Error, Feed, ImOp, Immediate, Result, Tape};
+use deep::*;
+use std::any::Any;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, RwLock};
+
+/// The threads that `Tape::solve_parallel` runs nodes on.
+///
+/// The threads are started once and reused by every call, since starting them for each
+/// forward pass can take longer than the pass itself.
+pub struct ThreadPool(rayon::ThreadPool);
+
+impl ThreadPool {
+    /// Starts a pool of `threads` threads.
+    pub fn new(threads: usize) -> Self {
+        Self(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads.max(1))
+                .thread_name(|index| format!("deep-executor-{}", index))
+                .build()
+                .expect("unable to start the executor threads"),
+        )
+    }
+
+    /// Gets the number of threads in the pool.
+    pub fn threads(&self) -> usize {
+        self.0.current_num_threads()
+    }
+}
+
+impl<B, T> Tape<B>
+where
+    B: Backend<Tensor = T>,
+    T: Clone,
+{
+    /// Solves `input` like `solve`, but nodes whose inputs are ready are run at the same time on
+    /// the threads of `pool`.
+    ///
+    /// Every node is computed from the same inputs as it would be by `solve`, so the results are
+    /// the same no matter what order the nodes finish in.
+    ///
+    /// If a handler panics, the other threads stop once their current node is done and the
+    /// panic is resumed on the calling thread.
+    pub fn solve_parallel(
+        &mut self,
+        backend: &B,
+        graph: &Graph,
+        state: &[Vec<T>],
+        inputs: &B::Inputs,
+        input: Input,
+        pool: &ThreadPool,
+    ) -> Result<T>
+    where
+        B: Immediate + Feed + Sync,
+        B::Inputs: Sync,
+        T: Send + Sync,
+    {
+        let internal = match &input {
+            Input::Feed(_) => return self.solve(backend, graph, state, inputs, input),
+            Input::Internal(internal) => *internal,
+        };
+        let schedule = Schedule::new(graph, self, internal.node);
+        let threads = pool.threads().min(schedule.remaining).max(1);
+
+        let executor = Executor {
+            backend,
+            graph,
+            state,
+            inputs,
+            tape: &*self,
+            schedule: Mutex::new(schedule),
+            ready: Condvar::new(),
+            solved: RwLock::new(HashMap::new()),
+        };
+        pool.0.scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|_| executor.work());
+            }
+        });
+
+        let Executor {
+            schedule, solved, ..
+        } = executor;
+        let schedule = schedule.into_inner().unwrap();
+        if let Some(payload) = schedule.panic {
+            panic::resume_unwind(payload);
+        }
+        if let Some(e) = schedule.error {
+            return Err(e);
+        }
+        for (node, solutions) in solved.into_inner().unwrap() {
+            for output in 0..solutions.len() {
+                self.solved
+                    .insert(Internal { node, output }, solutions.clone());
+            }
+        }
+        self.input(backend, inputs, graph, input)
+    }
+}
+
+/// Which nodes are left to solve and which of them are ready.
+struct Schedule {
+    ready: VecDeque<usize>,
+    /// The number of unsolved nodes that each node is waiting on.
+    waiting: HashMap<usize, usize>,
+    /// The nodes that use each node.
+    dependents: HashMap<usize, Vec<usize>>,
+    remaining: usize,
+    error: Option<Error>,
+    /// The payload of the first panic while solving a node.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl Schedule {
+    /// Finds the nodes that need to be solved to get `node` which aren't already on the tape.
+    fn new<B: Backend>(graph: &Graph, tape: &Tape<B>, node: usize) -> Self {
+        let solved: HashSet<usize> = tape.solved.keys().map(|internal| internal.node).collect();
+        let mut schedule = Self {
+            ready: VecDeque::new(),
+            waiting: HashMap::new(),
+            dependents: HashMap::new(),
+            remaining: 0,
+            error: None,
+            panic: None,
+        };
+        let mut stack = vec![node];
+        let mut visited = HashSet::new();
+        while let Some(node) = stack.pop() {
+            if solved.contains(&node) || !visited.insert(node) {
+                continue;
+            }
+            let dependencies: HashSet<usize> = graph.ops[node]
+                .inputs()
+                .into_iter()
+                .filter_map(|input| match input {
+                    Input::Internal(internal) if !solved.contains(&internal.node) => {
+                        Some(internal.node)
+                    }
+                    _ => None,
+                })
+                .collect();
+            if dependencies.is_empty() {
+                schedule.ready.push_back(node);
+            }
+            schedule.waiting.insert(node, dependencies.len());
+            for &dependency in &dependencies {
+                schedule
+                    .dependents
+                    .entry(dependency)
+                    .or_default()
+                    .push(node);
+                stack.push(dependency);
+            }
+            schedule.remaining += 1;
+        }
+        schedule
+    }
+
+    /// Marks a node as solved, making ready any nodes that were only waiting on it.
+    fn finish(&mut self, node: usize) {
+        self.remaining -= 1;
+        for dependent in self.dependents.remove(&node).unwrap_or_default() {
+            let waiting = self.waiting.get_mut(&dependent).unwrap();
+            *waiting -= 1;
+            if *waiting == 0 {
+                self.ready.push_back(dependent);
+            }
+        }
+    }
+}
+
+/// Everything shared between the threads solving a graph.
+struct Executor<'a, B: Backend> {
+    backend: &'a B,
+    graph: &'a Graph,
+    state: &'a [Vec<B::Tensor>],
+    inputs: &'a B::Inputs,
+    /// The nodes that were already solved beforehand.
+    tape: &'a Tape<B>,
+    schedule: Mutex<Schedule>,
+    /// Signalled whenever a node becomes ready or there is nothing left to do.
+    ready: Condvar,
+    solved: RwLock<HashMap<usize, Vec<B::Tensor>>>,
+}
+
+impl<'a, B, T> Executor<'a, B>
+where
+    B: Backend<Tensor = T> + Immediate + Feed,
+    T: Clone,
+{
+    /// Solves ready nodes until every node is solved or one of them fails or panics.
+    fn work(&self) {
+        loop {
+            let node = {
+                let mut schedule = self.schedule.lock().unwrap();
+                loop {
+                    if schedule.remaining == 0
+                        || schedule.error.is_some()
+                        || schedule.panic.is_some()
+                    {
+                        return;
+                    }
+                    if let Some(node) = schedule.ready.pop_front() {
+                        break node;
+                    }
+                    schedule = self.ready.wait(schedule).unwrap();
+                }
+            };
+
+            // A panic is caught so that the threads waiting on this node are woken up to stop,
+            // rather than waiting forever.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.solve(node)));
+            let mut schedule = self.schedule.lock().unwrap();
+            match result {
+                Ok(Ok(solutions)) => {
+                    self.solved.write().unwrap().insert(node, solutions);
+                    schedule.finish(node);
+                }
+                Ok(Err(e)) => {
+                    schedule.error.get_or_insert(e);
+                }
+                Err(payload) => {
+                    schedule.panic.get_or_insert(payload);
+                }
+            }
+            self.ready.notify_all();
+        }
+    }
+
+    fn solve(&self, node: usize) -> Result<Vec<T>> {
+        let imop = ImOp::build(&self.graph.ops[node], |input| match input {
+            Input::Feed(name) => self
+                .backend
+                .feed(self.inputs, name)
+                .ok_or_else(|| Error::InputNotProvided { name: name.clone() }),
+            Input::Internal(internal) => match self.solved.read().unwrap().get(&internal.node) {
+                Some(solutions) => Ok(solutions[internal.output].clone()),
+                None => self
+                    .tape
+                    .input(self.backend, self.inputs, self.graph, input.clone()),
+            },
+        })?;
+        imop.compute(self.backend, &self.state[node][..])
+    }
+}
@@ -0,0 +1,177 @@
+//! Graphs compiled into plans that can be run over and over without walking the graph.
+
+use crate::{Error, Feed, ImOp, Immediate, Result};
+use deep::*;
+
+/// Where a step gets one of its inputs.
+#[derive(Copy, Clone, Debug)]
+enum Source {
+    /// One of the plan's feeds.
+    Feed(usize),
+    /// One of the outputs of an earlier step.
+    Step { step: usize, output: usize },
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    node: usize,
+    op: Op,
+    /// The source of each input of `op`.
+    sources: Vec<(Input, Source)>,
+    /// The earlier steps whose outputs aren't needed after this step.
+    free: Vec<usize>,
+}
+
+/// The ops needed to solve some outputs of a graph, compiled into the order they run in.
+///
+/// Values are stored in slots indexed by step rather than looked up by node, and each value is
+/// dropped as soon as the last step that uses it has run.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    steps: Vec<Step>,
+    feeds: Vec<String>,
+    outputs: Vec<Source>,
+}
+
+impl Plan {
+    /// Compiles the plan for solving `outputs`.
+    pub fn compile(graph: &Graph, outputs: &[Input]) -> Self {
+        let mut order = vec![];
+        let mut visited = vec![false; graph.ops.len()];
+        for output in outputs {
+            visit(graph, output, &mut visited, &mut order);
+        }
+
+        let mut step_of = vec![None; graph.ops.len()];
+        for (step, &node) in order.iter().enumerate() {
+            step_of[node] = Some(step);
+        }
+        let mut feeds: Vec<String> = vec![];
+        let mut source = |input: &Input| match input {
+            Input::Feed(name) => Source::Feed(
+                feeds
+                    .iter()
+                    .position(|feed| feed == name)
+                    .unwrap_or_else(|| {
+                        feeds.push(name.clone());
+                        feeds.len() - 1
+                    }),
+            ),
+            Input::Internal(internal) => Source::Step {
+                step: step_of[internal.node].unwrap(),
+                output: internal.output,
+            },
+        };
+        let mut steps: Vec<Step> = order
+            .iter()
+            .map(|&node| {
+                let op = graph.ops[node].clone();
+                Step {
+                    node,
+                    sources: op
+                        .inputs()
+                        .into_iter()
+                        .map(|input| (input.clone(), source(input)))
+                        .collect(),
+                    op,
+                    free: vec![],
+                }
+            })
+            .collect();
+        let outputs: Vec<Source> = outputs.iter().map(source).collect();
+
+        // The last step that uses each step, unless it is an output.
+        let mut last_use: Vec<Option<usize>> = vec![None; steps.len()];
+        for (step, s) in steps.iter().enumerate() {
+            for (_, source) in &s.sources {
+                if let Source::Step { step: used, .. } = *source {
+                    last_use[used] = Some(step);
+                }
+            }
+        }
+        for output in &outputs {
+            if let Source::Step { step, .. } = *output {
+                last_use[step] = None;
+            }
+        }
+        for (used, last_use) in last_use.into_iter().enumerate() {
+            if let Some(step) = last_use {
+                steps[step].free.push(used);
+            }
+        }
+
+        Self {
+            steps,
+            feeds,
+            outputs,
+        }
+    }
+
+    /// Gets the names of the feeds that the plan needs.
+    pub fn feeds(&self) -> &[String] {
+        &self.feeds
+    }
+
+    /// Gets the nodes that the plan runs in the order they are run in.
+    pub fn nodes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.steps.iter().map(|step| step.node)
+    }
+
+    /// Solves the outputs that the plan was compiled for.
+    pub fn run<B, T>(&self, backend: &B, state: &[Vec<T>], inputs: &B::Inputs) -> Result<Vec<T>>
+    where
+        B: Backend<Tensor = T> + Immediate + Feed,
+        T: Clone,
+    {
+        let feeds = self
+            .feeds
+            .iter()
+            .map(|name| {
+                backend
+                    .feed(inputs, name)
+                    .ok_or_else(|| Error::InputNotProvided { name: name.clone() })
+            })
+            .collect::<Result<Vec<T>>>()?;
+        let mut slots: Vec<Option<Vec<T>>> = vec![None; self.steps.len()];
+        let value = |slots: &[Option<Vec<T>>], source: Source| match source {
+            Source::Feed(feed) => feeds[feed].clone(),
+            Source::Step { step, output } => slots[step]
+                .as_ref()
+                .expect("value was used after it was freed")[output]
+                .clone(),
+        };
+
+        for (step, s) in self.steps.iter().enumerate() {
+            let imop = ImOp::build(&s.op, |input| {
+                let (_, source) = s
+                    .sources
+                    .iter()
+                    .find(|(i, _)| i == input)
+                    .expect("input has no source");
+                Ok(value(&slots, *source))
+            })?;
+            slots[step] = Some(imop.compute(backend, &state[s.node][..])?);
+            for &free in &s.free {
+                slots[free] = None;
+            }
+        }
+        Ok(self
+            .outputs
+            .iter()
+            .map(|&output| value(&slots, output))
+            .collect())
+    }
+}
+
+/// Adds the nodes that `input` depends on to `order` after their own dependencies.
+fn visit(graph: &Graph, input: &Input, visited: &mut [bool], order: &mut Vec<usize>) {
+    if let Input::Internal(internal) = input {
+        if !visited[internal.node] {
+            visited[internal.node] = true;
+            for input in graph.ops[internal.node].inputs() {
+                visit(graph, input, visited, order);
+            }
+            order.push(internal.node);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Learning-rate schedules.
+//!
+//! Schedules are plain data, so checkpointing one (along with the step of the optimizer
+//! using it) is just a matter of cloning it or saving its fields.
+
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+
+/// Gives the learning rate to use at each step of training.
+pub trait Schedule {
+    /// Gets the learning rate to use at `step`, counting from zero.
+    fn learning_rate(&self, step: usize) -> f32;
+
+    /// Informs the schedule of a metric measured after `step`, such as the validation loss.
+    ///
+    /// Only schedules that adapt to training, like `ReduceOnPlateau`, make use of this.
+    fn observe(&mut self, _step: usize, _metric: f32) {}
+}
+
+/// A constant learning rate.
+impl Schedule for f32 {
+    fn learning_rate(&self, _step: usize) -> f32 {
+        *self
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every `step_size` steps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepDecay {
+    pub initial: f32,
+    pub gamma: f32,
+    /// A step size of zero is treated as one.
+    pub step_size: usize,
+}
+
+impl StepDecay {
+    /// Creates a schedule which starts with `initial` and decays by `gamma`.
+    pub fn new(initial: f32, gamma: f32, step_size: usize) -> Self {
+        Self {
+            initial,
+            gamma,
+            step_size,
+        }
+    }
+}
+
+impl Schedule for StepDecay {
+    fn learning_rate(&self, step: usize) -> f32 {
+        self.initial * self.gamma.powi(exponent(step / self.step_size.max(1)))
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exponential {
+    pub initial: f32,
+    pub gamma: f32,
+}
+
+impl Schedule for Exponential {
+    fn learning_rate(&self, step: usize) -> f32 {
+        self.initial * self.gamma.powi(exponent(step))
+    }
+}
+
+/// Anneals the learning rate from `max` to `min` along a cosine over `period` steps, then
+/// restarts at `max`. Each period is `period_mult` times as long as the one before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CosineAnnealing {
+    pub max: f32,
+    pub min: f32,
+    /// A period of zero is treated as one.
+    pub period: usize,
+    pub period_mult: usize,
+}
+
+impl CosineAnnealing {
+    /// Creates a schedule which anneals from `max` to `min` and restarts every `period` steps.
+    pub fn new(max: f32, min: f32, period: usize) -> Self {
+        Self {
+            max,
+            min,
+            period,
+            period_mult: 1,
+        }
+    }
+
+    /// Makes each period `period_mult` times as long as the one before it.
+    pub fn period_mult(self, period_mult: usize) -> Self {
+        Self {
+            period_mult,
+            ..self
+        }
+    }
+}
+
+impl Schedule for CosineAnnealing {
+    fn learning_rate(&self, step: usize) -> f32 {
+        // Find how far into the current period the step is.
+        let mut start = 0;
+        let mut period = self.period.max(1);
+        while step - start >= period {
+            start += period;
+            period = period.saturating_mul(self.period_mult.max(1));
+        }
+        cosine(self.max, self.min, (step - start) as f32 / period as f32)
+    }
+}
+
+/// Ramps the learning rate linearly up to that of `schedule` over `warmup` steps, then
+/// follows `schedule` starting from its first step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearWarmup<S> {
+    pub warmup: usize,
+    pub schedule: S,
+}
+
+impl<S> Schedule for LinearWarmup<S>
+where
+    S: Schedule,
+{
+    fn learning_rate(&self, step: usize) -> f32 {
+        if step < self.warmup {
+            self.schedule.learning_rate(0) * (step + 1) as f32 / self.warmup as f32
+        } else {
+            self.schedule.learning_rate(step - self.warmup)
+        }
+    }
+
+    fn observe(&mut self, step: usize, metric: f32) {
+        if step >= self.warmup {
+            self.schedule.observe(step - self.warmup, metric);
+        }
+    }
+}
+
+/// The one-cycle policy over `total` steps.
+///
+/// The learning rate rises along a cosine from `max / div` to `max` over the first `warmup`
+/// fraction of the steps, then falls along a cosine to `max / div / final_div`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OneCycle {
+    pub max: f32,
+    pub total: usize,
+    pub warmup: f32,
+    pub div: f32,
+    pub final_div: f32,
+}
+
+impl OneCycle {
+    /// Creates a one-cycle policy with the usual parameters.
+    pub fn new(max: f32, total: usize) -> Self {
+        Self {
+            max,
+            total,
+            warmup: 0.3,
+            div: 25.0,
+            final_div: 1e4,
+        }
+    }
+}
+
+impl Schedule for OneCycle {
+    fn learning_rate(&self, step: usize) -> f32 {
+        let initial = self.max / self.div;
+        let warmup = (self.warmup * self.total as f32).max(1.0);
+        let step = step as f32;
+        if step < warmup {
+            cosine(initial, self.max, step / warmup)
+        } else {
+            let remaining = (self.total as f32 - warmup).max(1.0);
+            let progress = ((step - warmup) / remaining).min(1.0);
+            cosine(self.max, initial / self.final_div, progress)
+        }
+    }
+}
+
+/// Multiplies the learning rate by `factor` whenever the observed metric hasn't improved on
+/// the best so far by more than `threshold` for more than `patience` observations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReduceOnPlateau {
+    pub learning_rate: f32,
+    pub factor: f32,
+    pub patience: usize,
+    pub threshold: f32,
+    /// The learning rate is never reduced below this.
+    pub min: f32,
+    /// The best metric observed so far.
+    pub best: Option<f32>,
+    /// The number of observations since the metric last improved.
+    pub bad_observations: usize,
+}
+
+impl ReduceOnPlateau {
+    /// Creates a schedule which starts with `learning_rate` and reduces it by `factor`.
+    pub fn new(learning_rate: f32, factor: f32, patience: usize) -> Self {
+        Self {
+            learning_rate,
+            factor,
+            patience,
+            threshold: 0.0,
+            min: 0.0,
+            best: None,
+            bad_observations: 0,
+        }
+    }
+}
+
+impl Schedule for ReduceOnPlateau {
+    fn learning_rate(&self, _step: usize) -> f32 {
+        self.learning_rate
+    }
+
+    fn observe(&mut self, _step: usize, metric: f32) {
+        match self.best {
+            Some(best) if metric >= best - self.threshold => {
+                self.bad_observations += 1;
+                if self.bad_observations > self.patience {
+                    self.learning_rate = (self.learning_rate * self.factor).max(self.min);
+                    self.bad_observations = 0;
+                }
+            }
+            _ => {
+                self.best = Some(metric);
+                self.bad_observations = 0;
+            }
+        }
+    }
+}
+
+/// Converts a number of decays to an exponent, saturating rather than wrapping.
+fn exponent(decays: usize) -> i32 {
+    i32::try_from(decays).unwrap_or(i32::MAX)
+}
+
+/// Interpolates from `from` to `to` along half of a cosine as `progress` goes from 0 to 1.
+fn cosine(from: f32, to: f32, progress: f32) -> f32 {
+    to + (from - to) * (1.0 + (PI * progress).cos()) / 2.0
+}
@@ -0,0 +1,232 @@
+use crate::{Error, Feed, ImOp, Immediate, Result, Tape};
+use deep::*;
+use std::collections::HashMap;
+
+pub trait Tangent: Backend {
+    /// This is the forward mode counterpart of `Propogate::propogate`.
+    ///
+    /// It is given an operation with inputs specified in `imop` (as per the original `solve`),
+    /// internal state specified in `state`, the tangents of each input in `tangents` (as the same
+    /// kind of `ImOp`), and the tangents of the state in `state_tangents`. It must produce the
+    /// tangent of every output of the op. Returning `None` should only be done if the operation
+    /// was not registered with the backend.
+    fn tangent(
+        &self,
+        imop: ImOp<Self>,
+        state: &[Self::Tensor],
+        tangents: ImOp<Self>,
+        state_tangents: &[Self::Tensor],
+    ) -> Option<Vec<Self::Tensor>>;
+
+    /// Creates a tensor of zeros with the same shape as `tensor`.
+    ///
+    /// This is the tangent of any input or state that wasn't given one.
+    fn zeros_like(&self, tensor: &Self::Tensor) -> Self::Tensor;
+}
+
+impl<B, T> Tape<B>
+where
+    B: Backend<Tensor = T>,
+    T: Clone,
+{
+    /// Solves `input` like `solve`, but also pushes tangents forward through the graph
+    /// alongside the values to get the Jacobian-vector product of `input`.
+    ///
+    /// The tangents of the inputs from the feed dict are given in `input_tangents`, and the
+    /// tangents of the state of each node are given in `state_tangents`. Anything without a
+    /// tangent has a tangent of zero.
+    ///
+    /// Returns the value of `input` along with its tangent.
+    #[allow(clippy::too_many_arguments)]
+    pub fn solve_tangent(
+        &mut self,
+        backend: &B,
+        graph: &Graph,
+        state: &[Vec<B::Tensor>],
+        inputs: &B::Inputs,
+        input: Input,
+        input_tangents: &B::Inputs,
+        state_tangents: &HashMap<usize, Vec<B::Tensor>>,
+    ) -> Result<(B::Tensor, B::Tensor)>
+    where
+        B: Immediate + Feed + Tangent,
+    {
+        let forward = Forward {
+            backend,
+            graph,
+            state,
+            inputs,
+            input_tangents,
+            state_tangents,
+        };
+        forward.solve(self, input)
+    }
+}
+
+/// Everything that stays the same while pushing tangents through a graph.
+struct Forward<'a, B: Backend> {
+    backend: &'a B,
+    graph: &'a Graph,
+    state: &'a [Vec<B::Tensor>],
+    inputs: &'a B::Inputs,
+    input_tangents: &'a B::Inputs,
+    state_tangents: &'a HashMap<usize, Vec<B::Tensor>>,
+}
+
+impl<'a, B, T> Forward<'a, B>
+where
+    B: Backend<Tensor = T> + Immediate + Feed + Tangent,
+    T: Clone,
+{
+    fn solve(&self, tape: &mut Tape<B>, input: Input) -> Result<(T, T)> {
+        let internal = match input {
+            Input::Feed(name) => {
+                let value = self
+                    .backend
+                    .feed(self.inputs, &name)
+                    .ok_or(Error::InputNotProvided { name: name.clone() })?;
+                let tangent = self
+                    .backend
+                    .feed(self.input_tangents, &name)
+                    .unwrap_or_else(|| self.backend.zeros_like(&value));
+                return Ok((value, tangent));
+            }
+            Input::Internal(internal) => internal,
+        };
+
+        if let Some(tangents) = tape.tangents.get(&internal) {
+            let tangent = tangents[internal.output].clone();
+            let value = tape.input(self.backend, self.inputs, self.graph, input)?;
+            return Ok((value, tangent));
+        }
+
+        let op = self.graph.ops[internal.node].clone();
+        let ty = (&op).into();
+        let state = &self.state[internal.node][..];
+        let (imop, tangent_imop) = self.imops(tape, op)?;
+
+        let tangents = match tangent_imop {
+            // No delta can flow backwards through this op, so the same goes for tangents.
+            ImOp::StopGradient(t) => vec![self.backend.zeros_like(&t)],
+            // Custom gradients only replace the reverse mode, so the tangent passes through.
+            ImOp::CustomGradient(t, _) => vec![t],
+            tangent_imop => {
+                let state_tangents = self
+                    .state_tangents
+                    .get(&internal.node)
+                    .cloned()
+                    .unwrap_or_else(|| state.iter().map(|s| self.backend.zeros_like(s)).collect());
+                self.backend
+                    .tangent(imop.clone(), state, tangent_imop, &state_tangents)
+                    .ok_or(Error::OpHasNoHandler { ty })?
+            }
+        };
+
+        let values = match tape.solved.get(&internal) {
+            Some(values) => values.clone(),
+            None => match imop {
+                ImOp::StopGradient(a) | ImOp::CustomGradient(a, _) => vec![a],
+                imop => self
+                    .backend
+                    .solve(imop, state)
+                    .ok_or(Error::OpHasNoHandler { ty })?,
+            },
+        };
+
+        let output = (
+            values[internal.output].clone(),
+            tangents[internal.output].clone(),
+        );
+        tape.solved.insert(internal, values);
+        tape.tangents.insert(internal, tangents);
+        Ok(output)
+    }
+
+    /// Solves the inputs of an op, giving back the `ImOp` of the values and of the tangents.
+    fn imops(&self, tape: &mut Tape<B>, op: Op) -> Result<(ImOp<B>, ImOp<B>)> {
+        let mut solve = |input| self.solve(tape, input);
+        Ok(match op {
+            Op::Add(a, b) => {
+                let ((a, ta), (b, tb)) = (solve(a)?, solve(b)?);
+                (ImOp::Add(a, b), ImOp::Add(ta, tb))
+            }
+            Op::Sub(a, b) => {
+                let ((a, ta), (b, tb)) = (solve(a)?, solve(b)?);
+                (ImOp::Sub(a, b), ImOp::Sub(ta, tb))
+            }
+            Op::Square(a) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::Square(a), ImOp::Square(ta))
+            }
+            Op::Mul(a, b) => {
+                let ((a, ta), (b, tb)) = (solve(a)?, solve(b)?);
+                (ImOp::Mul(a, b), ImOp::Mul(ta, tb))
+            }
+            Op::Scale(a, scale) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::Scale(a, scale), ImOp::Scale(ta, scale))
+            }
+            Op::Sum(a) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::Sum(a), ImOp::Sum(ta))
+            }
+            Op::OnesLike(a) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::OnesLike(a), ImOp::OnesLike(ta))
+            }
+            Op::Abs(a) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::Abs(a), ImOp::Abs(ta))
+            }
+            Op::Sign(a) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::Sign(a), ImOp::Sign(ta))
+            }
+            Op::MatMul(a, b) => {
+                let ((a, ta), (b, tb)) = (solve(a)?, solve(b)?);
+                (ImOp::MatMul(a, b), ImOp::MatMul(ta, tb))
+            }
+            Op::Transpose(a) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::Transpose(a), ImOp::Transpose(ta))
+            }
+            Op::Conv2d(a, b) => {
+                let ((a, ta), (b, tb)) = (solve(a)?, solve(b)?);
+                (ImOp::Conv2d(a, b), ImOp::Conv2d(ta, tb))
+            }
+            Op::Conv2dInputDelta(a, b) => {
+                let ((a, ta), (b, tb)) = (solve(a)?, solve(b)?);
+                (ImOp::Conv2dInputDelta(a, b), ImOp::Conv2dInputDelta(ta, tb))
+            }
+            Op::Conv2dKernelDelta(a, b) => {
+                let ((a, ta), (b, tb)) = (solve(a)?, solve(b)?);
+                (
+                    ImOp::Conv2dKernelDelta(a, b),
+                    ImOp::Conv2dKernelDelta(ta, tb),
+                )
+            }
+            Op::Unbroadcast(a, like) => {
+                let ((a, ta), (like, tlike)) = (solve(a)?, solve(like)?);
+                (ImOp::Unbroadcast(a, like), ImOp::Unbroadcast(ta, tlike))
+            }
+            Op::TrainConst(..) => (ImOp::TrainConst, ImOp::TrainConst),
+            Op::StopGradient(a) => {
+                let (a, ta) = solve(a)?;
+                (ImOp::StopGradient(a), ImOp::StopGradient(ta))
+            }
+            Op::CustomGradient(a, inputs, _) => {
+                let (a, ta) = solve(a)?;
+                let (values, tangents) = inputs
+                    .into_iter()
+                    .map(solve)
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .unzip();
+                (
+                    ImOp::CustomGradient(a, values),
+                    ImOp::CustomGradient(ta, tangents),
+                )
+            }
+        })
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{Dense, Optimizer};
+use deep::*;
+use std::io::{self, Write};
+
+/// Gives the batches of inputs to train or validate on.
+pub trait DataSource<I> {
+    /// Gets the batches for one epoch.
+    fn batches(&mut self) -> Box<dyn Iterator<Item = I> + '_>;
+}
+
+/// The same batches are used every epoch.
+impl<I> DataSource<I> for Vec<I>
+where
+    I: Clone,
+{
+    fn batches(&mut self) -> Box<dyn Iterator<Item = I> + '_> {
+        Box::new(self.iter().cloned())
+    }
+}
+
+/// Whether training should keep going after a callback.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+/// The metrics of a single batch of training.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchMetrics {
+    pub epoch: usize,
+    pub batch: usize,
+    pub loss: f32,
+}
+
+/// The metrics of an epoch of training.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpochMetrics {
+    pub epoch: usize,
+    /// The mean loss of the batches trained on.
+    pub loss: f32,
+    /// The mean loss of the validation batches, if there were any.
+    pub validation_loss: Option<f32>,
+}
+
+impl EpochMetrics {
+    /// Gets the metric that early stopping and checkpointing go by, which is the validation loss
+    /// if there is one and the training loss otherwise.
+    pub fn monitored(&self) -> f32 {
+        self.validation_loss.unwrap_or(self.loss)
+    }
+}
+
+/// Hooks into a `Trainer` as it trains.
+pub trait Callback<B: Backend> {
+    /// Called after each batch is trained on.
+    fn on_batch_end(&mut self, _metrics: &BatchMetrics, _state: &B::State) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called after each epoch, once validation is done.
+    fn on_epoch_end(&mut self, _metrics: &EpochMetrics, _state: &B::State) -> Flow {
+        Flow::Continue
+    }
+}
+
+/// A user-defined metric, computed from the metrics and state at the end of each epoch.
+type Metric<'a, B> = Box<dyn FnMut(&EpochMetrics, &<B as Backend>::State) -> f32 + 'a>;
+
+/// A callback that logs the metrics of each epoch to `writer` as CSV, such as to a file or to
+/// `std::io::stdout()`.
+///
+/// The columns are the epoch, the loss and the validation loss (empty if there isn't one),
+/// followed by any metrics added with `metric`. The header is written before the first epoch.
+///
+/// If writing fails, training is stopped and the error can be found with `finish`.
+pub struct MetricLogger<'a, B: Backend, W> {
+    writer: W,
+    metrics: Vec<(String, Metric<'a, B>)>,
+    wrote_header: bool,
+    error: Option<io::Error>,
+}
+
+impl<'a, B, W> MetricLogger<'a, B, W>
+where
+    B: Backend,
+    W: Write,
+{
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            metrics: vec![],
+            wrote_header: false,
+            error: None,
+        }
+    }
+
+    /// Adds a column named `name` which is computed by `metric` at the end of each epoch.
+    pub fn metric(
+        mut self,
+        name: &str,
+        metric: impl FnMut(&EpochMetrics, &B::State) -> f32 + 'a,
+    ) -> Self {
+        self.metrics.push((name.to_owned(), Box::new(metric)));
+        self
+    }
+
+    /// Flushes the writer and gives it back, or gives back the first error that occurred.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_row(&mut self, metrics: &EpochMetrics, state: &B::State) -> io::Result<()> {
+        if !self.wrote_header {
+            write!(self.writer, "epoch,loss,validation_loss")?;
+            for (name, _) in &self.metrics {
+                write!(self.writer, ",{}", name)?;
+            }
+            writeln!(self.writer)?;
+            self.wrote_header = true;
+        }
+        write!(self.writer, "{},{}", metrics.epoch, metrics.loss)?;
+        match metrics.validation_loss {
+            Some(validation_loss) => write!(self.writer, ",{}", validation_loss)?,
+            None => write!(self.writer, ",")?,
+        }
+        for (_, metric) in &mut self.metrics {
+            write!(self.writer, ",{}", metric(metrics, state))?;
+        }
+        writeln!(self.writer)
+    }
+}
+
+impl<B, W> Callback<B> for MetricLogger<'_, B, W>
+where
+    B: Backend,
+    W: Write,
+{
+    fn on_epoch_end(&mut self, metrics: &EpochMetrics, state: &B::State) -> Flow {
+        if self.error.is_some() {
+            return Flow::Stop;
+        }
+        match self.write_row(metrics, state) {
+            Ok(()) => Flow::Continue,
+            Err(e) => {
+                self.error = Some(e);
+                Flow::Stop
+            }
+        }
+    }
+}
+
+impl<B, W> Callback<B> for &mut MetricLogger<'_, B, W>
+where
+    B: Backend,
+    W: Write,
+{
+    fn on_epoch_end(&mut self, metrics: &EpochMetrics, state: &B::State) -> Flow {
+        (**self).on_epoch_end(metrics, state)
+    }
+}
+
+/// What happened over the course of `Trainer::fit`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct History {
+    /// The metrics of every epoch that finished.
+    pub epochs: Vec<EpochMetrics>,
+    /// The epoch with the best monitored metric.
+    pub best_epoch: Option<usize>,
+    /// Whether training ended early due to early stopping or a callback.
+    pub stopped_early: bool,
+}
+
+/// Drives the training of a loss over a number of epochs with an optimizer.
+///
+/// The loss of a batch is the sum of the elements of the loss tensor, and the gradient is
+/// propogated from a tensor of ones.
+pub struct Trainer<'a, B: Backend, O> {
+    backend: &'a B,
+    loss: Tensor,
+    optimizer: O,
+    epochs: usize,
+    patience: Option<usize>,
+    keep_best: bool,
+    callbacks: Vec<Box<dyn Callback<B> + 'a>>,
+}
+
+impl<'a, B, O> Trainer<'a, B, O>
+where
+    B: Backend + Dense,
+    B::State: Clone,
+    O: Optimizer<B>,
+{
+    /// Creates a trainer that trains for a single epoch.
+    pub fn new(backend: &'a B, loss: Tensor, optimizer: O) -> Self {
+        Self {
+            backend,
+            loss,
+            optimizer,
+            epochs: 1,
+            patience: None,
+            keep_best: false,
+            callbacks: vec![],
+        }
+    }
+
+    /// Sets the maximum number of epochs to train for.
+    pub fn epochs(self, epochs: usize) -> Self {
+        Self { epochs, ..self }
+    }
+
+    /// Stops training once the monitored metric hasn't improved for `patience` epochs.
+    pub fn early_stopping(self, patience: usize) -> Self {
+        Self {
+            patience: Some(patience),
+            ..self
+        }
+    }
+
+    /// Restores the state from the epoch with the best monitored metric once training ends.
+    pub fn keep_best(self) -> Self {
+        Self {
+            keep_best: true,
+            ..self
+        }
+    }
+
+    /// Adds a callback.
+    pub fn callback(mut self, callback: impl Callback<B> + 'a) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Gets the optimizer, such as to checkpoint it.
+    pub fn optimizer(&self) -> &O {
+        &self.optimizer
+    }
+
+    /// Trains on `train` each epoch and evaluates the loss on `validation` if it is given.
+    pub fn fit(
+        &mut self,
+        state: &mut B::State,
+        train: &mut dyn DataSource<B::Inputs>,
+        mut validation: Option<&mut dyn DataSource<B::Inputs>>,
+    ) -> Result<History, B::Error> {
+        let mut history = History {
+            epochs: vec![],
+            best_epoch: None,
+            stopped_early: false,
+        };
+        let mut best: Option<f32> = None;
+        let mut checkpoint = None;
+
+        'epochs: for epoch in 0..self.epochs {
+            let mut total = 0.0;
+            let mut batches = 0;
+            for inputs in train.batches() {
+                let backend = self.backend;
+                let (loss, _) =
+                    self.optimizer
+                        .minimize(backend, &self.loss, state, &inputs, |output| {
+                            let shape = backend.shape(&output);
+                            let loss = backend.to_vec(&output).into_iter().sum::<f32>();
+                            let ones = vec![1.0; shape.iter().product()];
+                            (loss, backend.tensor_from_vec(&shape, ones))
+                        })?;
+                total += loss;
+                let metrics = BatchMetrics {
+                    epoch,
+                    batch: batches,
+                    loss,
+                };
+                batches += 1;
+                if self.notify(|c| c.on_batch_end(&metrics, state)) == Flow::Stop {
+                    history.stopped_early = true;
+                    break 'epochs;
+                }
+            }
+
+            let validation_loss = match &mut validation {
+                Some(validation) => Some(self.evaluate(state, &mut **validation)?),
+                None => None,
+            };
+            let metrics = EpochMetrics {
+                epoch,
+                loss: total / batches.max(1) as f32,
+                validation_loss,
+            };
+            let monitored = metrics.monitored();
+            self.optimizer.observe(monitored);
+
+            if best.is_none_or(|best| monitored < best) {
+                best = Some(monitored);
+                history.best_epoch = Some(epoch);
+                if self.keep_best {
+                    checkpoint = Some(state.clone());
+                }
+            }
+
+            let flow = self.notify(|c| c.on_epoch_end(&metrics, state));
+            history.epochs.push(metrics);
+            if flow == Flow::Stop {
+                history.stopped_early = true;
+                break;
+            }
+            if let (Some(patience), Some(best_epoch)) = (self.patience, history.best_epoch) {
+                if epoch - best_epoch >= patience {
+                    history.stopped_early = true;
+                    break;
+                }
+            }
+        }
+
+        if let Some(checkpoint) = checkpoint {
+            *state = checkpoint;
+        }
+        Ok(history)
+    }
+
+    /// Computes the mean loss over the batches of `data` without training.
+    pub fn evaluate(
+        &self,
+        state: &B::State,
+        data: &mut dyn DataSource<B::Inputs>,
+    ) -> Result<f32, B::Error> {
+        let mut total = 0.0;
+        let mut batches = 0;
+        for inputs in data.batches() {
+            let output = self.loss.eval(self.backend, state, &inputs)?;
+            total += self.backend.to_vec(&output).into_iter().sum::<f32>();
+            batches += 1;
+        }
+        Ok(total / batches.max(1) as f32)
+    }
+
+    /// Calls every callback, stopping if any of them asks to.
+    fn notify(&mut self, mut f: impl FnMut(&mut dyn Callback<B>) -> Flow) -> Flow {
+        let mut flow = Flow::Continue;
+        for callback in &mut self.callbacks {
+            if f(&mut **callback) == Flow::Stop {
+                flow = Flow::Stop;
+            }
+        }
+        flow
+    }
+}
@@ -0,0 +1,75 @@
+//! Compares the single-threaded kernels against the parallel ones.
+//!
+//! Run with `cargo bench -p deep-native --features parallel`.
+
+use deep_native::kernels::{parallel, serial, PARALLEL_THRESHOLD};
+use ndarray::Array2;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+
+/// Gets the mean time of running `f`.
+fn time(mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn report(name: &str, len: usize, serial: Duration, parallel: Duration) {
+    println!(
+        "{:<8} {:>10} elements: serial {:>10.3?} parallel {:>10.3?} speedup {:.2}x",
+        name,
+        len,
+        serial,
+        parallel,
+        serial.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
+
+fn main() {
+    for &len in &[PARALLEL_THRESHOLD, 1 << 20, 1 << 24] {
+        let a: Vec<f32> = (0..len).map(|n| n as f32 * 1e-3).collect();
+        let b: Vec<f32> = (0..len).map(|n| (n % 7) as f32).collect();
+        let mut out = vec![0.0; len];
+
+        let f = |n: f32| n.tanh();
+        report(
+            "map",
+            len,
+            time(|| serial::map(&a, &mut out, f)),
+            time(|| parallel::map(&a, &mut out, f)),
+        );
+
+        let f = |a: f32, b: f32| a * b + a;
+        report(
+            "zip_map",
+            len,
+            time(|| serial::zip_map(&a, &b, &mut out, f)),
+            time(|| parallel::zip_map(&a, &b, &mut out, f)),
+        );
+
+        let mut total = 0.0;
+        report(
+            "sum",
+            len,
+            time(|| total += serial::sum(&a)),
+            time(|| total += parallel::sum(&a)),
+        );
+        assert!(total.is_finite());
+    }
+
+    // Square matrices whose products take at least as many multiplications as the threshold.
+    for &size in &[41, 128, 512] {
+        let a = Array2::from_shape_fn((size, size), |(i, j)| (i * size + j) as f32 * 1e-3);
+        let b = Array2::from_shape_fn((size, size), |(i, j)| ((i + j) % 7) as f32);
+        report(
+            "matmul",
+            size * size,
+            time(|| drop(serial::matmul(a.view(), b.view()))),
+            time(|| drop(parallel::matmul(a.view(), b.view()))),
+        );
+    }
+}
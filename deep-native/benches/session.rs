@@ -0,0 +1,47 @@
+//! Compares evaluating a graph of many small ops with `Tensor::eval` and with a `Session`.
+//!
+//! Run with `cargo bench -p deep-native --bench session`.
+
+use deep::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 1000;
+
+fn main() {
+    let backend = Native::new().handlers(handlers::standard());
+    let mut y = Tensor::from("x");
+    for layer in 0..100 {
+        let w = Tensor::train_const(vec![], 1.0 + layer as f64 * 1e-3);
+        y = (y * w).abs().scale(0.99);
+    }
+    let state = y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]) };
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        y.eval(&backend, &state, &feed).expect("unable to eval");
+    }
+    let eval = start.elapsed() / ITERATIONS;
+
+    let session = backend
+        .session(&y.graph(), &[y.input().clone()])
+        .expect("unable to compile");
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        session.run(&state, &feed).expect("unable to run");
+    }
+    let run = start.elapsed() / ITERATIONS;
+
+    println!(
+        "{} ops: eval {:.3?} session {:.3?} speedup {:.2}x",
+        y.graph().ops.len(),
+        eval,
+        run,
+        eval.as_secs_f64() / run.as_secs_f64()
+    );
+}
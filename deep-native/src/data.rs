@@ -0,0 +1,283 @@
+//! Batching of datasets into feed dicts.
+
+use crate::Tsor;
+use deep_backend_tools::DataSource;
+use ndarray::{Axis, IxDyn};
+use rand_core::RngCore;
+use std::collections::HashMap;
+use std::panic;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/// A named set of tensors, such as a single example or a batch of examples.
+pub type Example = HashMap<String, Tsor>;
+
+/// A collection of examples that can be accessed by index.
+pub trait Dataset {
+    /// Gets the number of examples.
+    fn len(&self) -> usize;
+
+    /// Gets an example, which must have the same names and shapes as every other example.
+    fn get(&self, index: usize) -> Example;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Dataset for Vec<Example> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, index: usize) -> Example {
+        self[index].clone()
+    }
+}
+
+/// A dataset of named tensors whose first axis indexes the examples, such as all of the images
+/// and labels of a dataset loaded from files.
+#[derive(Clone, Debug)]
+pub struct TensorDataset {
+    tensors: Example,
+    len: usize,
+}
+
+impl TensorDataset {
+    /// Panics if the tensors don't all have the same length along their first axis.
+    pub fn new(tensors: Example) -> Self {
+        let mut lens = tensors.iter().map(|(name, tensor)| {
+            assert!(
+                tensor.ndim() > 0,
+                "tensor \"{}\" has no examples axis",
+                name
+            );
+            tensor.len_of(Axis(0))
+        });
+        let len = lens.next().unwrap_or(0);
+        assert!(
+            lens.all(|l| l == len),
+            "tensors in a TensorDataset must have the same number of examples"
+        );
+        Self { tensors, len }
+    }
+}
+
+impl Dataset for TensorDataset {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> Example {
+        self.tensors
+            .iter()
+            .map(|(name, tensor)| {
+                let example = tensor.index_axis(Axis(0), index).to_owned().into_shared();
+                (name.clone(), example)
+            })
+            .collect()
+    }
+}
+
+/// What to do with the last batch of an epoch when there aren't enough examples to fill it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LastBatch {
+    /// The last batch is smaller than the others.
+    Keep,
+    /// The last batch is skipped.
+    Drop,
+    /// The last batch is filled out with examples that are all zeros.
+    Pad,
+}
+
+/// Batches the examples of a dataset into feed dicts.
+///
+/// Each tensor of a batch stacks the tensors of the same name from its examples along a new
+/// first axis.
+pub struct DataLoader<D> {
+    dataset: Arc<D>,
+    batch_size: usize,
+    last_batch: LastBatch,
+    rng: Option<Box<dyn RngCore + Send>>,
+    prefetch: usize,
+}
+
+impl<D> DataLoader<D>
+where
+    D: Dataset + Send + Sync + 'static,
+{
+    /// Creates a loader that gives the examples in order without prefetching.
+    pub fn new(dataset: D, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be at least one");
+        Self {
+            dataset: Arc::new(dataset),
+            batch_size,
+            last_batch: LastBatch::Keep,
+            rng: None,
+            prefetch: 0,
+        }
+    }
+
+    /// Shuffles the examples every epoch using `rng`, which should be seeded for reproducibility.
+    pub fn shuffle(self, rng: impl RngCore + Send + 'static) -> Self {
+        Self {
+            rng: Some(Box::new(rng)),
+            ..self
+        }
+    }
+
+    /// Sets what to do with the last batch when it can't be filled.
+    pub fn last_batch(self, last_batch: LastBatch) -> Self {
+        Self { last_batch, ..self }
+    }
+
+    /// Assembles up to `batches` batches ahead of time on a background thread.
+    ///
+    /// With zero, which is the default, batches are assembled as they are iterated.
+    pub fn prefetch(self, batches: usize) -> Self {
+        Self {
+            prefetch: batches,
+            ..self
+        }
+    }
+
+    /// Gets the dataset.
+    pub fn dataset(&self) -> &D {
+        &self.dataset
+    }
+
+    /// Gets the number of batches in an epoch.
+    pub fn len(&self) -> usize {
+        match self.last_batch {
+            LastBatch::Drop => self.dataset.len() / self.batch_size,
+            LastBatch::Keep | LastBatch::Pad => self.dataset.len().div_ceil(self.batch_size),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the batches of one epoch.
+    pub fn iter(&mut self) -> Batches {
+        let mut order: Vec<usize> = (0..self.dataset.len()).collect();
+        if let Some(rng) = &mut self.rng {
+            shuffle(&mut order, &mut **rng);
+        }
+        let chunks: Vec<Vec<usize>> = order
+            .chunks(self.batch_size)
+            .take(self.len())
+            .map(<[usize]>::to_vec)
+            .collect();
+
+        let dataset = self.dataset.clone();
+        let padded_size = match self.last_batch {
+            LastBatch::Pad => Some(self.batch_size),
+            LastBatch::Keep | LastBatch::Drop => None,
+        };
+        let batches = chunks
+            .into_iter()
+            .map(move |indices| assemble(&*dataset, &indices, padded_size));
+
+        if self.prefetch == 0 {
+            return Batches(Box::new(batches));
+        }
+        let (sender, receiver) = mpsc::sync_channel(self.prefetch);
+        let worker = thread::spawn(move || {
+            for batch in batches {
+                // The receiver is gone once the `Batches` are dropped.
+                if sender.send(batch).is_err() {
+                    break;
+                }
+            }
+        });
+        Batches(Box::new(Prefetched {
+            receiver,
+            worker: Some(worker),
+        }))
+    }
+}
+
+/// Batches assembled on a background thread.
+struct Prefetched {
+    receiver: mpsc::Receiver<Example>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl Iterator for Prefetched {
+    type Item = Example;
+
+    fn next(&mut self) -> Option<Example> {
+        match self.receiver.recv() {
+            Ok(batch) => Some(batch),
+            // The channel also closes if assembling a batch panicked, which mustn't look like
+            // the end of the epoch.
+            Err(_) => {
+                if let Some(Err(payload)) = self.worker.take().map(thread::JoinHandle::join) {
+                    panic::resume_unwind(payload);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<D> DataSource<Example> for DataLoader<D>
+where
+    D: Dataset + Send + Sync + 'static,
+{
+    fn batches(&mut self) -> Box<dyn Iterator<Item = Example> + '_> {
+        Box::new(self.iter())
+    }
+}
+
+/// The batches of an epoch from a `DataLoader`.
+pub struct Batches(Box<dyn Iterator<Item = Example>>);
+
+impl Iterator for Batches {
+    type Item = Example;
+
+    fn next(&mut self) -> Option<Example> {
+        self.0.next()
+    }
+}
+
+/// Stacks the examples at `indices` into a batch, padding it with zeros up to `padded_size`.
+fn assemble<D>(dataset: &D, indices: &[usize], padded_size: Option<usize>) -> Example
+where
+    D: Dataset + ?Sized,
+{
+    let examples: Vec<Example> = indices.iter().map(|&index| dataset.get(index)).collect();
+    let padding = padded_size.map_or(0, |size| size - indices.len());
+    examples[0]
+        .iter()
+        .map(|(name, first)| {
+            let zeros = Tsor::zeros(first.shape());
+            let views: Vec<_> = examples
+                .iter()
+                .map(|example| {
+                    example
+                        .get(name)
+                        .unwrap_or_else(|| panic!("example is missing \"{}\"", name))
+                        .view()
+                })
+                .chain(std::iter::repeat_n(zeros.view(), padding))
+                .map(|view| view.insert_axis(Axis(0)))
+                .collect();
+            let batch = ndarray::stack(Axis(0), &views)
+                .unwrap_or_else(|_| panic!("examples of \"{}\" have different shapes", name));
+            (
+                name.clone(),
+                batch.into_shared().into_dimensionality::<IxDyn>().unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// Shuffles `items` with the Fisher-Yates shuffle.
+fn shuffle(items: &mut [usize], rng: &mut dyn RngCore) {
+    for i in (1..items.len()).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
@@ -0,0 +1,367 @@
+//! Fusion of chains of elementwise ops into a single loop.
+//!
+//! Every op of a fused group is applied to a small chunk of elements at a time, so the data is
+//! passed over once rather than once per op, and values that nothing reads are never stored.
+//!
+//! With the `parallel` feature, groups over at least `PARALLEL_THRESHOLD` elements are split
+//! into one block per thread of rayon's global pool, like the kernels of unfused ops.
+//!
+//! Groups are solved before the rest of the graph, so the values they depend on are solved one
+//! at a time on the calling thread and are all kept, rather than going through the executor of
+//! `Native::threads` or the liveness analysis of `Native::retention`.
+
+use crate::kernels::serial::{map, zip_map};
+#[cfg(feature = "parallel")]
+use crate::kernels::{parallel::chunk_len, PARALLEL_THRESHOLD};
+use crate::{handlers::sign, Native, Tsor};
+use deep::*;
+use deep_backend_tools::*;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+/// The number of elements that each op of a group is applied to at a time.
+const CHUNK: usize = 256;
+
+/// Which values of fused groups are kept in the `Tape`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Fusion {
+    /// Ops are run one at a time.
+    #[default]
+    Off,
+    /// Only the output of each group is kept, so backprop can't pass through a group.
+    Inference,
+    /// Every value that backprop reads is kept, along with the shapes of the values that it only
+    /// needs the shapes of.
+    Training,
+}
+
+/// A chain of elementwise ops that is computed in one loop.
+#[derive(Clone, Debug)]
+pub struct Group {
+    /// The nodes of the group, which come after the nodes they use. The last is the output.
+    pub nodes: Vec<usize>,
+    /// The inputs of the group's nodes from outside of the group.
+    pub inputs: Vec<Input>,
+    /// The nodes whose values are kept, which always includes the output.
+    pub kept: Vec<usize>,
+    /// The nodes whose shapes are kept without their values, since backprop only reads their
+    /// shapes.
+    pub shaped: Vec<usize>,
+}
+
+impl Group {
+    /// The node whose value is used outside of the group.
+    pub fn output(&self) -> usize {
+        *self.nodes.last().unwrap()
+    }
+}
+
+/// The groups of elementwise ops to fuse when solving an output of a graph.
+#[derive(Clone, Debug, Default)]
+pub struct FusionPlan {
+    /// The groups, which come after the groups they use.
+    pub groups: Vec<Group>,
+}
+
+impl FusionPlan {
+    /// Finds the chains of elementwise ops that `outputs` depend on.
+    ///
+    /// An elementwise op whose handler in `backend` is `Handler::fusable` joins the group of the
+    /// op that uses it if nothing else uses it and it isn't one of `outputs`. What each group keeps for backprop is decided by what the handlers
+    /// of `backend` read.
+    pub fn new(backend: &Native, graph: &Graph, outputs: &[Input], fusion: Fusion) -> Self {
+        if fusion == Fusion::Off {
+            return Self::default();
+        }
+        // The nodes that use each node that `outputs` depend on.
+        let mut users: HashMap<usize, HashSet<usize>> = HashMap::new();
+        let outputs: HashSet<usize> = outputs.iter().filter_map(internal).collect();
+        let mut stack: Vec<usize> = outputs.iter().cloned().collect();
+        let mut visited = HashSet::new();
+        while let Some(node) = stack.pop() {
+            if visited.insert(node) {
+                for input in graph.ops[node].inputs().into_iter().filter_map(internal) {
+                    users.entry(input).or_default().insert(node);
+                    stack.push(input);
+                }
+            }
+        }
+
+        // Users come after the nodes they use, so every user is assigned a group first.
+        let mut group_of: HashMap<usize, usize> = HashMap::new();
+        let mut groups: Vec<Vec<usize>> = vec![];
+        let mut nodes: Vec<usize> = visited.into_iter().collect();
+        nodes.sort_unstable_by(|a, b| b.cmp(a));
+        for node in nodes {
+            if !elementwise(&graph.ops[node]) || !backend.fusable(&graph.ops[node]) {
+                continue;
+            }
+            let only_user = match users.get(&node) {
+                Some(users) if users.len() == 1 && !outputs.contains(&node) => {
+                    users.iter().next().cloned()
+                }
+                _ => None,
+            };
+            match only_user.and_then(|user| group_of.get(&user).cloned()) {
+                Some(group) => {
+                    groups[group].push(node);
+                    group_of.insert(node, group);
+                }
+                None => {
+                    group_of.insert(node, groups.len());
+                    groups.push(vec![node]);
+                }
+            }
+        }
+
+        let mut groups: Vec<Group> = groups
+            .into_iter()
+            .filter(|nodes| nodes.len() > 1)
+            .map(|mut nodes| {
+                nodes.reverse();
+                let members: HashSet<usize> = nodes.iter().cloned().collect();
+                let mut inputs: Vec<Input> = vec![];
+                let mut kept = vec![];
+                let mut shaped = vec![];
+                for &node in &nodes {
+                    let op = &graph.ops[node];
+                    for input in op.inputs() {
+                        match internal(input) {
+                            Some(input) if members.contains(&input) => {
+                                if fusion == Fusion::Training {
+                                    match backend.reads(op) {
+                                        Reads::Values => kept.push(input),
+                                        Reads::Shapes => shaped.push(input),
+                                        Reads::Nothing => {}
+                                    }
+                                }
+                            }
+                            _ => {
+                                if !inputs.iter().any(|i| same_input(i, input)) {
+                                    inputs.push(input.clone());
+                                }
+                            }
+                        }
+                    }
+                }
+                kept.push(*nodes.last().unwrap());
+                kept.sort_unstable();
+                kept.dedup();
+                shaped.retain(|node| !kept.contains(node));
+                shaped.sort_unstable();
+                shaped.dedup();
+                Group {
+                    nodes,
+                    inputs,
+                    kept,
+                    shaped,
+                }
+            })
+            .collect();
+        groups.sort_unstable_by_key(Group::output);
+        Self { groups }
+    }
+}
+
+/// Checks if an op is applied to each element on its own.
+fn elementwise(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Add(..)
+            | Op::Sub(..)
+            | Op::Square(_)
+            | Op::Mul(..)
+            | Op::Scale(..)
+            | Op::Abs(_)
+            | Op::Sign(_)
+    )
+}
+
+fn internal(input: &Input) -> Option<usize> {
+    match input {
+        Input::Internal(internal) => Some(internal.node),
+        Input::Feed(_) => None,
+    }
+}
+
+fn same_input(a: &Input, b: &Input) -> bool {
+    match (a, b) {
+        (Input::Feed(a), Input::Feed(b)) => a == b,
+        (Input::Internal(a), Input::Internal(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Where an op of a group gets one of its operands.
+#[derive(Copy, Clone)]
+enum Operand {
+    /// One of the inputs of the group.
+    Input(usize),
+    /// The value of an earlier node of the group.
+    Node(usize),
+}
+
+/// Solves the inputs of a group, computes it, and records the values it keeps in the tape.
+///
+/// Groups whose inputs have different shapes are left to the handlers, since the handlers
+/// decide how to broadcast.
+pub(crate) fn solve_group(
+    backend: &Native,
+    group: &Group,
+    tape: &mut Tape<Native>,
+    graph: &Graph,
+    state: &[Vec<Tsor>],
+    inputs: &<Native as Backend>::Inputs,
+) -> Result<()> {
+    let values = group
+        .inputs
+        .iter()
+        .map(|input| tape.solve(backend, graph, state, inputs, input.clone()))
+        .collect::<Result<Vec<Tsor>>>()?;
+    let shape = values[0].shape().to_vec();
+    let fusable = values
+        .iter()
+        .all(|value| value.shape() == &shape[..] && value.is_standard_layout())
+        && group
+            .nodes
+            .iter()
+            .all(|&node| backend.fusable(&graph.ops[node]));
+    if !fusable {
+        for &node in &group.nodes {
+            let node = Input::Internal(Internal { node, output: 0 });
+            tape.solve(backend, graph, state, inputs, node)?;
+        }
+        return Ok(());
+    }
+
+    let operand = |input: &Input| match internal(input)
+        .and_then(|n| group.nodes.iter().position(|&node| node == n))
+    {
+        Some(position) => Operand::Node(position),
+        None => Operand::Input(
+            group
+                .inputs
+                .iter()
+                .position(|i| same_input(i, input))
+                .unwrap(),
+        ),
+    };
+    let program: Vec<(&Op, Vec<Operand>)> = group
+        .nodes
+        .iter()
+        .map(|&node| {
+            let op = &graph.ops[node];
+            (op, op.inputs().into_iter().map(operand).collect())
+        })
+        .collect();
+
+    let start = Instant::now();
+    let len = values[0].len();
+    let slices: Vec<&[f32]> = values.iter().map(|v| v.as_slice().unwrap()).collect();
+    let mut kept: Vec<Option<Vec<f32>>> = group
+        .nodes
+        .iter()
+        .map(|node| {
+            if group.kept.contains(node) {
+                Some(vec![0.0; len])
+            } else {
+                None
+            }
+        })
+        .collect();
+    compute(&program, &slices, &mut kept);
+    let kept: Vec<Option<Tsor>> = kept
+        .into_iter()
+        .map(|kept| kept.map(|kept| Tsor::from_shape_vec(&shape[..], kept).unwrap()))
+        .collect();
+    if let Some(profiler) = &backend.profiler {
+        let ty = (&graph.ops[group.output()]).into();
+        let outputs: Vec<Tsor> = kept.iter().flatten().cloned().collect();
+        profiler.fused(ty, start, &values, &outputs);
+    }
+    record(group, tape, &shape, kept);
+    Ok(())
+}
+
+/// Computes a group over `inputs`, writing the values of the nodes that are kept to `kept`.
+fn compute(program: &[(&Op, Vec<Operand>)], inputs: &[&[f32]], kept: &mut [Option<Vec<f32>>]) {
+    #[cfg(feature = "parallel")]
+    {
+        let len = inputs[0].len();
+        if len >= PARALLEL_THRESHOLD {
+            use rayon::prelude::*;
+            // Each block of elements gets the same block of every kept value to write to.
+            let block = chunk_len(len);
+            let mut blocks: Vec<Vec<Option<&mut [f32]>>> =
+                (0..len.div_ceil(block)).map(|_| vec![]).collect();
+            for kept in kept {
+                match kept {
+                    Some(kept) => {
+                        for (blocks, kept) in blocks.iter_mut().zip(kept.chunks_mut(block)) {
+                            blocks.push(Some(kept));
+                        }
+                    }
+                    None => blocks.iter_mut().for_each(|blocks| blocks.push(None)),
+                }
+            }
+            blocks
+                .into_par_iter()
+                .enumerate()
+                .for_each(|(index, mut kept)| {
+                    let range = index * block..(len.min((index + 1) * block));
+                    let inputs: Vec<&[f32]> =
+                        inputs.iter().map(|input| &input[range.clone()]).collect();
+                    run(program, &inputs, &mut kept);
+                });
+            return;
+        }
+    }
+    let mut kept_slices: Vec<Option<&mut [f32]>> = kept
+        .iter_mut()
+        .map(|kept| kept.as_mut().map(|kept| &mut kept[..]))
+        .collect();
+    run(program, inputs, &mut kept_slices);
+}
+
+/// Runs the ops of a group over `inputs` one chunk at a time, writing the values of the nodes
+/// that are kept to `kept`.
+fn run(program: &[(&Op, Vec<Operand>)], inputs: &[&[f32]], kept: &mut [Option<&mut [f32]>]) {
+    let len = inputs[0].len();
+    let mut scratch = vec![[0.0f32; CHUNK]; program.len()];
+    for start in (0..len).step_by(CHUNK) {
+        let end = (start + CHUNK).min(len);
+        for (position, (op, operands)) in program.iter().enumerate() {
+            let (done, rest) = scratch.split_at_mut(position);
+            let out = &mut rest[0][..end - start];
+            let get = |operand: Operand| match operand {
+                Operand::Input(i) => &inputs[i][start..end],
+                Operand::Node(n) => &done[n][..end - start],
+            };
+            match op {
+                Op::Add(..) => zip_map(get(operands[0]), get(operands[1]), out, |a, b| a + b),
+                Op::Sub(..) => zip_map(get(operands[0]), get(operands[1]), out, |a, b| a - b),
+                Op::Mul(..) => zip_map(get(operands[0]), get(operands[1]), out, |a, b| a * b),
+                Op::Square(_) => map(get(operands[0]), out, |n| n * n),
+                Op::Scale(_, scale) => map(get(operands[0]), out, |n| n * *scale as f32),
+                Op::Abs(_) => map(get(operands[0]), out, f32::abs),
+                Op::Sign(_) => map(get(operands[0]), out, sign),
+                op => unreachable!("{:?} is not elementwise", OpTy::from(*op)),
+            }
+            if let Some(kept) = &mut kept[position] {
+                kept[start..end].copy_from_slice(out);
+            }
+        }
+    }
+}
+
+/// Records the values and shapes that a group keeps in the tape.
+fn record(group: &Group, tape: &mut Tape<Native>, shape: &[usize], kept: Vec<Option<Tsor>>) {
+    for (&node, kept) in group.nodes.iter().zip(kept) {
+        if let Some(kept) = kept {
+            tape.insert(node, vec![kept]);
+        }
+    }
+    for &node in &group.shaped {
+        tape.insert_shapes(node, vec![shape.to_vec()]);
+    }
+}
@@ -0,0 +1,964 @@
+//! Handlers for the standard ops in `deep`.
+//!
+//! Use `standard` to add all of them to a `Native` backend at once.
+
+use crate::{kernels, tsor0, Handler, Native, Tsor};
+use deep::*;
+use deep_backend_tools::*;
+use ndarray::Axis;
+use rand_core::RngCore;
+
+/// Gets a handler for every standard op.
+pub fn standard() -> Vec<Box<dyn Handler>> {
+    vec![
+        Box::new(Add),
+        Box::new(Sub),
+        Box::new(Square),
+        Box::new(Mul),
+        Box::new(Scale),
+        Box::new(Sum),
+        Box::new(OnesLike),
+        Box::new(Abs),
+        Box::new(Sign),
+        Box::new(MatMul),
+        Box::new(Transpose),
+        Box::new(Conv2d),
+        Box::new(Conv2dInputDelta),
+        Box::new(Conv2dKernelDelta),
+        Box::new(Unbroadcast),
+        Box::new(TrainConst),
+    ]
+}
+
+/// Sums the axes of `delta` that were broadcast to turn a tensor of `shape` into its shape.
+fn unbroadcast(delta: Tsor, shape: &[usize]) -> Tsor {
+    if delta.shape() == shape {
+        return delta;
+    }
+    let mut delta = delta.into_owned();
+    while delta.ndim() > shape.len() {
+        delta = delta.sum_axis(Axis(0));
+    }
+    for (axis, &len) in shape.iter().enumerate() {
+        if len == 1 && delta.len_of(Axis(axis)) != 1 {
+            delta = delta.sum_axis(Axis(axis)).insert_axis(Axis(axis));
+        }
+    }
+    delta.into_shared()
+}
+
+pub struct Add;
+
+impl Handler for Add {
+    fn op(&self) -> OpTy {
+        OpTy::Add
+    }
+
+    fn fusable(&self) -> bool {
+        true
+    }
+
+    fn reads(&self) -> Reads {
+        Reads::Shapes
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to an add operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Add(a, b) = imop {
+            vec![kernels::zip_map(&a, &b, |a, b| a + b)]
+        } else {
+            panic!("got {:?} when OpTy::Add was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Add(a, b) = imop {
+            (
+                ImOp::Add(
+                    unbroadcast(output_delta.clone(), a.shape()),
+                    unbroadcast(output_delta, b.shape()),
+                ),
+                vec![],
+            )
+        } else {
+            panic!("got {:?} when OpTy::Add was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Add(..), ImOp::Add(ta, tb)) = (&imop, tangents) {
+            vec![ta + tb]
+        } else {
+            panic!("got {:?} when OpTy::Add was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Sub;
+
+impl Handler for Sub {
+    fn op(&self) -> OpTy {
+        OpTy::Sub
+    }
+
+    fn fusable(&self) -> bool {
+        true
+    }
+
+    fn reads(&self) -> Reads {
+        Reads::Shapes
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a sub operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Sub(a, b) = imop {
+            vec![kernels::zip_map(&a, &b, |a, b| a - b)]
+        } else {
+            panic!("got {:?} when OpTy::Sub was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Sub(a, b) = imop {
+            (
+                ImOp::Sub(
+                    unbroadcast(output_delta.clone(), a.shape()),
+                    unbroadcast(-output_delta, b.shape()),
+                ),
+                vec![],
+            )
+        } else {
+            panic!("got {:?} when OpTy::Sub was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Sub(..), ImOp::Sub(ta, tb)) = (&imop, tangents) {
+            vec![ta - tb]
+        } else {
+            panic!("got {:?} when OpTy::Sub was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Square;
+
+impl Handler for Square {
+    fn op(&self) -> OpTy {
+        OpTy::Square
+    }
+
+    fn fusable(&self) -> bool {
+        true
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a square operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Square(a) = imop {
+            vec![kernels::map(&a, |n| n * n)]
+        } else {
+            panic!("got {:?} when OpTy::Square was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Square(a) = imop {
+            let delta = kernels::zip_map(&output_delta, &a, |delta, a| 2.0 * a * delta);
+            (ImOp::Square(delta), vec![])
+        } else {
+            panic!("got {:?} when OpTy::Square was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Square(a), ImOp::Square(ta)) = (&imop, tangents) {
+            vec![2.0 * ta * a]
+        } else {
+            panic!("got {:?} when OpTy::Square was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Mul;
+
+impl Handler for Mul {
+    fn op(&self) -> OpTy {
+        OpTy::Mul
+    }
+
+    fn fusable(&self) -> bool {
+        true
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a mul operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Mul(a, b) = imop {
+            vec![kernels::zip_map(&a, &b, |a, b| a * b)]
+        } else {
+            panic!("got {:?} when OpTy::Mul was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Mul(a, b) = imop {
+            let da = kernels::zip_map(&output_delta, &b, |delta, b| delta * b);
+            let db = kernels::zip_map(&output_delta, &a, |delta, a| delta * a);
+            let (da, db) = (unbroadcast(da, a.shape()), unbroadcast(db, b.shape()));
+            (ImOp::Mul(da, db), vec![])
+        } else {
+            panic!("got {:?} when OpTy::Mul was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Mul(a, b), ImOp::Mul(ta, tb)) = (&imop, tangents) {
+            vec![ta * b + &(a * &tb)]
+        } else {
+            panic!("got {:?} when OpTy::Mul was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Scale;
+
+impl Handler for Scale {
+    fn op(&self) -> OpTy {
+        OpTy::Scale
+    }
+
+    fn fusable(&self) -> bool {
+        true
+    }
+
+    fn reads(&self) -> Reads {
+        Reads::Shapes
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a scale operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Scale(a, scale) = imop {
+            vec![kernels::map(&a, |n| n * scale as f32)]
+        } else {
+            panic!("got {:?} when OpTy::Scale was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Scale(_, scale) = imop {
+            let delta = kernels::map(&output_delta, |n| n * scale as f32);
+            (ImOp::Scale(delta, scale), vec![])
+        } else {
+            panic!("got {:?} when OpTy::Scale was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let ImOp::Scale(ta, scale) = tangents {
+            vec![ta * scale as f32]
+        } else {
+            panic!("got {:?} when OpTy::Scale was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Sum;
+
+impl Handler for Sum {
+    fn op(&self) -> OpTy {
+        OpTy::Sum
+    }
+
+    fn reads(&self) -> Reads {
+        Reads::Shapes
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a sum operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Sum(a) = imop {
+            vec![tsor0(kernels::sum(&a))]
+        } else {
+            panic!("got {:?} when OpTy::Sum was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Sum(a) = imop {
+            // The output is a scalar, so its sum is its only element.
+            let delta = Tsor::from_elem(a.shape(), output_delta.sum());
+            (ImOp::Sum(delta), vec![])
+        } else {
+            panic!("got {:?} when OpTy::Sum was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let ImOp::Sum(ta) = tangents {
+            vec![tsor0(ta.sum())]
+        } else {
+            panic!("got {:?} when OpTy::Sum was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct OnesLike;
+
+impl Handler for OnesLike {
+    fn op(&self) -> OpTy {
+        OpTy::OnesLike
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a ones like operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::OnesLike(a) = imop {
+            vec![Tsor::ones(a.shape())]
+        } else {
+            panic!(
+                "got {:?} when OpTy::OnesLike was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        _output_delta: (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        // The output doesn't depend on the values of the input, so this is never backpropogated.
+        panic!("backprop of {:?} is not possible", OpTy::from(&imop));
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        _tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let ImOp::OnesLike(a) = imop {
+            // The output doesn't depend on the values of the input.
+            vec![Tsor::zeros(a.shape())]
+        } else {
+            panic!(
+                "got {:?} when OpTy::OnesLike was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+}
+
+pub struct Abs;
+
+impl Handler for Abs {
+    fn op(&self) -> OpTy {
+        OpTy::Abs
+    }
+
+    fn fusable(&self) -> bool {
+        true
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to an abs operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Abs(a) = imop {
+            vec![kernels::map(&a, f32::abs)]
+        } else {
+            panic!("got {:?} when OpTy::Abs was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Abs(a) = imop {
+            let delta = kernels::zip_map(&output_delta, &a, |delta, a| delta * sign(a));
+            (ImOp::Abs(delta), vec![])
+        } else {
+            panic!("got {:?} when OpTy::Abs was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Abs(a), ImOp::Abs(ta)) = (&imop, tangents) {
+            vec![ta * &a.mapv(sign)]
+        } else {
+            panic!("got {:?} when OpTy::Abs was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Sign;
+
+impl Handler for Sign {
+    fn op(&self) -> OpTy {
+        OpTy::Sign
+    }
+
+    fn fusable(&self) -> bool {
+        true
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a sign operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Sign(a) = imop {
+            vec![a.mapv(sign).into_shared()]
+        } else {
+            panic!("got {:?} when OpTy::Sign was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        _output_delta: (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        // The output is piecewise constant, so this is never backpropogated.
+        panic!("backprop of {:?} is not possible", OpTy::from(&imop));
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        _tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let ImOp::Sign(a) = imop {
+            // The output is piecewise constant.
+            vec![Tsor::zeros(a.shape())]
+        } else {
+            panic!("got {:?} when OpTy::Sign was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct MatMul;
+
+impl Handler for MatMul {
+    fn op(&self) -> OpTy {
+        OpTy::MatMul
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a matmul operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::MatMul(a, b) = imop {
+            vec![kernels::matmul(&a, &b)]
+        } else {
+            panic!("got {:?} when OpTy::MatMul was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::MatMul(a, b) = imop {
+            let da = kernels::matmul(&output_delta, &b.reversed_axes());
+            let db = kernels::matmul(&a.reversed_axes(), &output_delta);
+            (ImOp::MatMul(da, db), vec![])
+        } else {
+            panic!("got {:?} when OpTy::MatMul was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::MatMul(a, b), ImOp::MatMul(ta, tb)) = (&imop, tangents) {
+            vec![kernels::matmul(&ta, b) + &kernels::matmul(a, &tb)]
+        } else {
+            panic!("got {:?} when OpTy::MatMul was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Transpose;
+
+impl Handler for Transpose {
+    fn op(&self) -> OpTy {
+        OpTy::Transpose
+    }
+
+    fn reads(&self) -> Reads {
+        Reads::Shapes
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a transpose operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Transpose(a) = imop {
+            vec![a.reversed_axes()]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Transpose was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Transpose(_) = imop {
+            (ImOp::Transpose(output_delta.reversed_axes()), vec![])
+        } else {
+            panic!(
+                "got {:?} when OpTy::Transpose was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Transpose(_), ImOp::Transpose(ta)) = (&imop, tangents) {
+            vec![ta.reversed_axes()]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Transpose was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+}
+
+pub struct Conv2d;
+
+impl Handler for Conv2d {
+    fn op(&self) -> OpTy {
+        OpTy::Conv2d
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to a convolution, since the kernel is an input.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Conv2d(input, kernel) = imop {
+            vec![kernels::conv2d(&input, &kernel)]
+        } else {
+            panic!("got {:?} when OpTy::Conv2d was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Conv2d(input, kernel) = imop {
+            let input_delta = kernels::conv2d_input_delta(&output_delta, &kernel);
+            let kernel_delta = kernels::conv2d_kernel_delta(&input, &output_delta);
+            (ImOp::Conv2d(input_delta, kernel_delta), vec![])
+        } else {
+            panic!("got {:?} when OpTy::Conv2d was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Conv2d(input, kernel), ImOp::Conv2d(t_input, t_kernel)) = (&imop, tangents) {
+            vec![kernels::conv2d(&t_input, kernel) + &kernels::conv2d(input, &t_kernel)]
+        } else {
+            panic!("got {:?} when OpTy::Conv2d was expected", OpTy::from(&imop));
+        }
+    }
+}
+
+pub struct Conv2dInputDelta;
+
+impl Handler for Conv2dInputDelta {
+    fn op(&self) -> OpTy {
+        OpTy::Conv2dInputDelta
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to the delta of a convolution.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Conv2dInputDelta(delta, kernel) = imop {
+            vec![kernels::conv2d_input_delta(&delta, &kernel)]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Conv2dInputDelta was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Conv2dInputDelta(delta, kernel) = imop {
+            let delta_delta = kernels::conv2d(&output_delta, &kernel);
+            let kernel_delta = kernels::conv2d_kernel_delta(&output_delta, &delta);
+            (ImOp::Conv2dInputDelta(delta_delta, kernel_delta), vec![])
+        } else {
+            panic!(
+                "got {:?} when OpTy::Conv2dInputDelta was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Conv2dInputDelta(delta, kernel), ImOp::Conv2dInputDelta(t_delta, t_kernel)) =
+            (&imop, tangents)
+        {
+            vec![
+                kernels::conv2d_input_delta(&t_delta, kernel)
+                    + &kernels::conv2d_input_delta(delta, &t_kernel),
+            ]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Conv2dInputDelta was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+}
+
+pub struct Conv2dKernelDelta;
+
+impl Handler for Conv2dKernelDelta {
+    fn op(&self) -> OpTy {
+        OpTy::Conv2dKernelDelta
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to the delta of a convolution.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Conv2dKernelDelta(input, delta) = imop {
+            vec![kernels::conv2d_kernel_delta(&input, &delta)]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Conv2dKernelDelta was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Conv2dKernelDelta(input, delta) = imop {
+            let input_delta = kernels::conv2d_input_delta(&delta, &output_delta);
+            let delta_delta = kernels::conv2d(&input, &output_delta);
+            (ImOp::Conv2dKernelDelta(input_delta, delta_delta), vec![])
+        } else {
+            panic!(
+                "got {:?} when OpTy::Conv2dKernelDelta was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Conv2dKernelDelta(input, delta), ImOp::Conv2dKernelDelta(t_input, t_delta)) =
+            (&imop, tangents)
+        {
+            vec![
+                kernels::conv2d_kernel_delta(&t_input, delta)
+                    + &kernels::conv2d_kernel_delta(input, &t_delta),
+            ]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Conv2dKernelDelta was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+}
+
+pub struct Unbroadcast;
+
+impl Handler for Unbroadcast {
+    fn op(&self) -> OpTy {
+        OpTy::Unbroadcast
+    }
+
+    fn reads(&self) -> Reads {
+        Reads::Shapes
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        // There are no internal variables to an unbroadcast operation.
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Unbroadcast(a, like) = imop {
+            vec![unbroadcast(a, like.shape())]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Unbroadcast was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Unbroadcast(a, like) = imop {
+            // The delta is broadcast back out over the axes that were summed. `like` only gives
+            // the shape, so it is passed back untouched in place of a delta.
+            let delta = output_delta
+                .broadcast(a.shape())
+                .expect("unbroadcast delta can't be broadcast to its input")
+                .to_owned()
+                .into_shared();
+            (ImOp::Unbroadcast(delta, like), vec![])
+        } else {
+            panic!(
+                "got {:?} when OpTy::Unbroadcast was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Unbroadcast(_, like), ImOp::Unbroadcast(ta, _)) = (&imop, tangents) {
+            vec![unbroadcast(ta, like.shape())]
+        } else {
+            panic!(
+                "got {:?} when OpTy::Unbroadcast was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+}
+
+/// The sign of `n`, which unlike `f32::signum` is zero at zero.
+pub(crate) fn sign(n: f32) -> f32 {
+    if n > 0.0 {
+        1.0
+    } else if n < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+pub struct TrainConst;
+
+impl Handler for TrainConst {
+    fn op(&self) -> OpTy {
+        OpTy::TrainConst
+    }
+
+    fn generate_state(&self, op: &Op, _rng: &mut dyn RngCore) -> Vec<Tsor> {
+        if let Op::TrainConst(shape, value) = op {
+            vec![Tsor::zeros(&shape[..]) + *value as f32]
+        } else {
+            panic!("got {:?} when Op::TrainConst was expected", OpTy::from(op));
+        }
+    }
+
+    fn forward(&self, imop: ImOp<Native>, state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::TrainConst = imop {
+            vec![state[0].clone()]
+        } else {
+            panic!(
+                "got {:?} when OpTy::TrainConst was expected",
+                OpTy::from(&imop)
+            );
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        let ty: OpTy = (&imop).into();
+        assert_eq!(ty, OpTy::TrainConst);
+        (ImOp::TrainConst, vec![output_delta])
+    }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        _tangents: ImOp<Native>,
+        state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        let ty: OpTy = (&imop).into();
+        assert_eq!(ty, OpTy::TrainConst);
+        vec![state_tangents[0].clone()]
+    }
+}
@@ -0,0 +1,202 @@
+use super::{invalid, Error, Result};
+use crate::Tsor;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Reads CSV data into a tensor with a row for each record and a column for each selected field.
+///
+/// Fields are coerced into `f32`s: numbers are parsed, `true` and `false` become 1 and 0, and
+/// empty fields, `NA` and `NaN` become the missing value. Anything else is an error.
+#[derive(Clone, Debug)]
+pub struct Csv {
+    delimiter: u8,
+    header: bool,
+    columns: Option<Columns>,
+    missing: f32,
+}
+
+#[derive(Clone, Debug)]
+enum Columns {
+    Names(Vec<String>),
+    Indices(Vec<usize>),
+}
+
+impl Default for Csv {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            header: true,
+            columns: None,
+            missing: f32::NAN,
+        }
+    }
+}
+
+impl Csv {
+    /// Creates a reader of comma separated data with a header and every column selected.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the byte that separates fields.
+    pub fn delimiter(self, delimiter: u8) -> Self {
+        Self { delimiter, ..self }
+    }
+
+    /// Sets whether the first record is a header of column names.
+    pub fn header(self, header: bool) -> Self {
+        Self { header, ..self }
+    }
+
+    /// Selects the columns with these names from the header, in this order.
+    pub fn columns<S: Into<String>>(self, names: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            columns: Some(Columns::Names(names.into_iter().map(Into::into).collect())),
+            ..self
+        }
+    }
+
+    /// Selects the columns at these indices, in this order.
+    pub fn column_indices(self, indices: impl IntoIterator<Item = usize>) -> Self {
+        Self {
+            columns: Some(Columns::Indices(indices.into_iter().collect())),
+            ..self
+        }
+    }
+
+    /// Sets the value that missing fields become.
+    pub fn missing(self, missing: f32) -> Self {
+        Self { missing, ..self }
+    }
+
+    /// Reads the CSV file at `path`.
+    pub fn read_path(&self, path: impl AsRef<Path>) -> Result<Tsor> {
+        self.read(File::open(path)?)
+    }
+
+    /// Reads CSV data into a tensor of shape `[records, columns]`.
+    pub fn read(&self, reader: impl Read) -> Result<Tsor> {
+        let mut lines = BufReader::new(reader).lines().enumerate();
+        let mut selected = match &self.columns {
+            Some(Columns::Indices(indices)) => Some(indices.clone()),
+            _ => None,
+        };
+
+        if self.header {
+            let header = match lines.next() {
+                Some((_, line)) => self.split(&line?, 1)?,
+                None => vec![],
+            };
+            if let Some(Columns::Names(names)) = &self.columns {
+                let indices = names
+                    .iter()
+                    .map(|name| {
+                        header
+                            .iter()
+                            .position(|field| field == name)
+                            .ok_or_else(|| Error::ColumnNotFound { name: name.clone() })
+                    })
+                    .collect::<Result<_>>()?;
+                selected = Some(indices);
+            }
+        } else if let Some(Columns::Names(_)) = &self.columns {
+            return Err(invalid(
+                "CSV",
+                "columns can't be selected by name without a header",
+            ));
+        }
+
+        let mut width = selected.as_ref().map(Vec::len);
+        let mut elements = vec![];
+        let mut records = 0;
+        for (index, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let number = index + 1;
+            let fields = self.split(&line, number)?;
+            let fields: Vec<&str> = match &selected {
+                Some(indices) => indices
+                    .iter()
+                    .map(|&i| {
+                        fields.get(i).map(String::as_str).ok_or_else(|| {
+                            invalid("CSV", format!("line {} has no column {}", number, i))
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                None => fields.iter().map(String::as_str).collect(),
+            };
+            match width {
+                Some(width) if width != fields.len() => {
+                    return Err(invalid(
+                        "CSV",
+                        format!(
+                            "line {} has {} fields, but {} were expected",
+                            number,
+                            fields.len(),
+                            width
+                        ),
+                    ))
+                }
+                _ => width = Some(fields.len()),
+            }
+            for field in fields {
+                elements.push(self.coerce(field, number)?);
+            }
+            records += 1;
+        }
+
+        Tsor::from_shape_vec(vec![records, width.unwrap_or(0)], elements)
+            .map_err(|e| invalid("CSV", e.to_string()))
+    }
+
+    /// Splits a line into its fields, removing quotes.
+    fn split(&self, line: &str, number: usize) -> Result<Vec<String>> {
+        let delimiter = self.delimiter as char;
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = !quoted,
+                c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+                c => field.push(c),
+            }
+        }
+        if quoted {
+            return Err(invalid(
+                "CSV",
+                format!("line {} has an unclosed quote", number),
+            ));
+        }
+        fields.push(field);
+        Ok(fields)
+    }
+
+    /// Coerces a field into an `f32`.
+    fn coerce(&self, field: &str, number: usize) -> Result<f32> {
+        let field = field.trim();
+        if field.is_empty() || field.eq_ignore_ascii_case("na") || field.eq_ignore_ascii_case("nan")
+        {
+            Ok(self.missing)
+        } else if field.eq_ignore_ascii_case("true") {
+            Ok(1.0)
+        } else if field.eq_ignore_ascii_case("false") {
+            Ok(0.0)
+        } else {
+            field.parse().map_err(|_| {
+                invalid(
+                    "CSV",
+                    format!("line {} has \"{}\", which isn't a number", number, field),
+                )
+            })
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use super::{gunzip, invalid, Result};
+use crate::Tsor;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Reads the IDX file at `path`, which can be gzipped like the files MNIST is distributed in.
+pub fn read_idx_path(path: impl AsRef<Path>) -> Result<Tsor> {
+    read_idx(File::open(path)?)
+}
+
+/// Reads IDX data, which is the format of MNIST, into a tensor of its shape.
+///
+/// Gzipped data is decompressed first.
+pub fn read_idx(mut reader: impl Read) -> Result<Tsor> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let data = gunzip(data)?;
+
+    if data.len() < 4 || data[0] != 0 || data[1] != 0 {
+        return Err(invalid("IDX", "bad magic number"));
+    }
+    let (ty, ndim) = (data[2], data[3] as usize);
+    let body = 4 + 4 * ndim;
+    let shape: Vec<usize> = data
+        .get(4..body)
+        .ok_or_else(|| invalid("IDX", "file is truncated"))?
+        .chunks(4)
+        .map(|n| u32::from_be_bytes([n[0], n[1], n[2], n[3]]) as usize)
+        .collect();
+
+    let data = &data[body..];
+    let elements: Vec<f32> = match ty {
+        0x08 => data.iter().map(|&n| n as f32).collect(),
+        0x09 => data.iter().map(|&n| n as i8 as f32).collect(),
+        0x0B => data
+            .chunks_exact(2)
+            .map(|n| i16::from_be_bytes([n[0], n[1]]) as f32)
+            .collect(),
+        0x0C => data
+            .chunks_exact(4)
+            .map(|n| i32::from_be_bytes([n[0], n[1], n[2], n[3]]) as f32)
+            .collect(),
+        0x0D => data
+            .chunks_exact(4)
+            .map(|n| f32::from_be_bytes([n[0], n[1], n[2], n[3]]))
+            .collect(),
+        0x0E => data
+            .chunks_exact(8)
+            .map(|n| f64::from_be_bytes([n[0], n[1], n[2], n[3], n[4], n[5], n[6], n[7]]) as f32)
+            .collect(),
+        ty => return Err(invalid("IDX", format!("unknown type 0x{:02X}", ty))),
+    };
+    Tsor::from_shape_vec(shape, elements)
+        .map_err(|_| invalid("IDX", "data doesn't match the shape"))
+}
@@ -0,0 +1,82 @@
+//! Readers that load data from files into `Tsor`s.
+//!
+//! The tensors they give back can be put together into a `data::TensorDataset` to be batched
+//! into feed dicts by a `data::DataLoader`.
+
+mod csv;
+mod idx;
+mod npy;
+mod zip;
+
+pub use self::csv::Csv;
+pub use self::idx::{read_idx, read_idx_path};
+pub use self::npy::{read_npy, read_npy_path, read_npz, read_npz_path};
+
+use failure::Fail;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "{}", _0)]
+    Io(#[cause] std::io::Error),
+    #[fail(display = "invalid {} data: {}", format, message)]
+    Invalid {
+        format: &'static str,
+        message: String,
+    },
+    #[fail(display = "no column is named \"{}\"", name)]
+    ColumnNotFound { name: String },
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Creates an `Error::Invalid`.
+fn invalid(format: &'static str, message: impl Into<String>) -> Error {
+    Error::Invalid {
+        format,
+        message: message.into(),
+    }
+}
+
+/// Decompresses gzip data, or gives back the data unchanged if it isn't gzipped.
+fn gunzip(data: Vec<u8>) -> Result<Vec<u8>> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Ok(data);
+    }
+    let truncated = || invalid("gzip", "file is truncated");
+    if data.len() < 18 || data[2] != 8 {
+        return Err(invalid("gzip", "only deflate compression is supported"));
+    }
+    let flags = data[3];
+    let mut position = 10;
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([data[position], data[position + 1]]) as usize;
+        position += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(position..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or_else(truncated)?;
+            position += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        position += 2;
+    }
+    // The last 8 bytes are the CRC and size of the uncompressed data.
+    let compressed = data.get(position..data.len() - 8).ok_or_else(truncated)?;
+    miniz_oxide::inflate::decompress_to_vec(compressed)
+        .map_err(|e| invalid("gzip", format!("{:?}", e.status)))
+}
@@ -0,0 +1,147 @@
+use super::{invalid, zip, Result};
+use crate::data::Example;
+use crate::Tsor;
+use ndarray::IxDyn;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Reads the `.npy` file at `path`.
+pub fn read_npy_path(path: impl AsRef<Path>) -> Result<Tsor> {
+    read_npy(File::open(path)?)
+}
+
+/// Reads a NumPy array in the `.npy` format.
+///
+/// Arrays of floats, integers and booleans are converted to `f32`.
+pub fn read_npy(mut reader: impl Read) -> Result<Tsor> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    parse_npy(&data)
+}
+
+/// Reads the `.npz` file at `path`.
+pub fn read_npz_path(path: impl AsRef<Path>) -> Result<Example> {
+    read_npz(File::open(path)?)
+}
+
+/// Reads the arrays of a NumPy `.npz` archive, which may be compressed, by name.
+pub fn read_npz(mut reader: impl Read) -> Result<Example> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    zip::entries(&data)?
+        .into_iter()
+        .filter(|(name, _)| name.ends_with(".npy"))
+        .map(|(name, data)| {
+            let array = parse_npy(&data)?;
+            Ok((name.trim_end_matches(".npy").to_owned(), array))
+        })
+        .collect()
+}
+
+fn parse_npy(data: &[u8]) -> Result<Tsor> {
+    let truncated = || invalid("NPY", "file is truncated");
+    if !data.starts_with(b"\x93NUMPY") || data.len() < 10 {
+        return Err(invalid("NPY", "bad magic string"));
+    }
+    let (header_len, start) = match data[6] {
+        1 => (u16::from_le_bytes([data[8], data[9]]) as usize, 10),
+        2 | 3 => {
+            let n = data.get(8..12).ok_or_else(truncated)?;
+            (u32::from_le_bytes([n[0], n[1], n[2], n[3]]) as usize, 12)
+        }
+        version => return Err(invalid("NPY", format!("unknown version {}", version))),
+    };
+    let header = data.get(start..start + header_len).ok_or_else(truncated)?;
+    let header = std::str::from_utf8(header).map_err(|_| invalid("NPY", "header isn't text"))?;
+    let descr = header_value(header, "descr")?;
+    let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+    let fortran_order = header_value(header, "fortran_order")? == "True";
+    let shape = parse_shape(header_value(header, "shape")?)?;
+
+    let elements = convert(descr, &data[start + header_len..])?;
+    let array = if fortran_order {
+        // Column-major data is the transpose of the row-major data of the reversed shape.
+        let reversed: Vec<usize> = shape.iter().rev().cloned().collect();
+        let array = ndarray::ArrayD::from_shape_vec(IxDyn(&reversed), elements)
+            .map_err(|_| invalid("NPY", "data doesn't match the shape"))?;
+        array.reversed_axes().as_standard_layout().to_owned()
+    } else {
+        ndarray::ArrayD::from_shape_vec(IxDyn(&shape), elements)
+            .map_err(|_| invalid("NPY", "data doesn't match the shape"))?
+    };
+    Ok(array.into_shared())
+}
+
+/// Gets the text of a value from the header, which is a Python dict literal.
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+    let missing = || invalid("NPY", format!("header has no \"{}\"", key));
+    let start = header.find(&format!("'{}'", key)).ok_or_else(missing)? + key.len() + 2;
+    let rest = header[start..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?;
+    let rest = rest.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|i| i + 1)
+    } else {
+        rest.find([',', '}'])
+    }
+    .ok_or_else(missing)?;
+    Ok(rest[..end].trim())
+}
+
+fn parse_shape(shape: &str) -> Result<Vec<usize>> {
+    shape
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| {
+            dim.parse()
+                .map_err(|_| invalid("NPY", format!("bad shape \"{}\"", shape)))
+        })
+        .collect()
+}
+
+/// Converts the data of the given dtype into `f32`s.
+fn convert(descr: &str, data: &[u8]) -> Result<Vec<f32>> {
+    let unknown = || invalid("NPY", format!("unknown dtype \"{}\"", descr));
+    let mut chars = descr.chars();
+    let big = match chars.next().ok_or_else(unknown)? {
+        '>' => true,
+        '<' | '|' | '=' => false,
+        _ => return Err(unknown()),
+    };
+    let ty = chars.as_str();
+    macro_rules! elements {
+        ($ty:ty, $n:expr) => {
+            data.chunks_exact($n)
+                .map(|b| {
+                    let mut bytes = [0; $n];
+                    bytes.copy_from_slice(b);
+                    let n = if big {
+                        <$ty>::from_be_bytes(bytes)
+                    } else {
+                        <$ty>::from_le_bytes(bytes)
+                    };
+                    n as f32
+                })
+                .collect()
+        };
+    }
+    Ok(match ty {
+        "f4" => elements!(f32, 4),
+        "f8" => elements!(f64, 8),
+        "i1" => elements!(i8, 1),
+        "i2" => elements!(i16, 2),
+        "i4" => elements!(i32, 4),
+        "i8" => elements!(i64, 8),
+        "u1" => elements!(u8, 1),
+        "u2" => elements!(u16, 2),
+        "u4" => elements!(u32, 4),
+        "u8" => elements!(u64, 8),
+        "b1" => data.iter().map(|&b| (b != 0) as u8 as f32).collect(),
+        _ => return Err(invalid("NPY", format!("unsupported dtype \"{}\"", descr))),
+    })
+}
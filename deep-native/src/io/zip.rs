@@ -0,0 +1,134 @@
+//! Just enough of the zip format to read `.npz` archives.
+
+use super::{invalid, Result};
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Gets the name and uncompressed data of every file in a zip archive.
+pub(super) fn entries(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let bytes = Bytes(data);
+    let end = (0..=data.len().saturating_sub(22))
+        .rev()
+        .find(|&i| bytes.u32(i) == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| invalid("zip", "no end of central directory"))?;
+
+    let mut count = bytes.u16(end + 10).ok_or_else(truncated)? as u64;
+    let mut offset = bytes.u32(end + 16).ok_or_else(truncated)? as u64;
+    if (count == 0xFFFF || offset == 0xFFFF_FFFF)
+        && end >= 20
+        && bytes.u32(end - 20) == Some(ZIP64_LOCATOR)
+    {
+        let zip64_end = bytes.u64(end - 12).ok_or_else(truncated)? as usize;
+        if bytes.u32(zip64_end) != Some(ZIP64_END_OF_CENTRAL_DIRECTORY) {
+            return Err(invalid("zip", "bad zip64 end of central directory"));
+        }
+        count = bytes.u64(zip64_end + 32).ok_or_else(truncated)?;
+        offset = bytes.u64(zip64_end + 48).ok_or_else(truncated)?;
+    }
+
+    let mut position = offset as usize;
+    let mut entries = vec![];
+    for _ in 0..count {
+        if bytes.u32(position) != Some(CENTRAL_DIRECTORY_HEADER) {
+            return Err(invalid("zip", "bad central directory header"));
+        }
+        let method = bytes.u16(position + 10).ok_or_else(truncated)?;
+        let mut compressed = bytes.u32(position + 20).ok_or_else(truncated)? as u64;
+        let mut uncompressed = bytes.u32(position + 24).ok_or_else(truncated)? as u64;
+        let name_len = bytes.u16(position + 28).ok_or_else(truncated)? as usize;
+        let extra_len = bytes.u16(position + 30).ok_or_else(truncated)? as usize;
+        let comment_len = bytes.u16(position + 32).ok_or_else(truncated)? as usize;
+        let mut local = bytes.u32(position + 42).ok_or_else(truncated)? as u64;
+        let name = bytes.slice(position + 46, name_len).ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        // Sizes that don't fit in 32 bits are in the zip64 extra field, in this order.
+        let extra = bytes
+            .slice(position + 46 + name_len, extra_len)
+            .ok_or_else(truncated)?;
+        let mut extra_position = 0;
+        while extra_position + 4 <= extra.len() {
+            let extra_bytes = Bytes(extra);
+            let id = extra_bytes.u16(extra_position).unwrap();
+            let len = extra_bytes.u16(extra_position + 2).unwrap() as usize;
+            if id == ZIP64_EXTRA {
+                let mut field = extra_position + 4;
+                for value in [&mut uncompressed, &mut compressed, &mut local] {
+                    if *value == 0xFFFF_FFFF {
+                        *value = extra_bytes.u64(field).ok_or_else(truncated)?;
+                        field += 8;
+                    }
+                }
+            }
+            extra_position += 4 + len;
+        }
+
+        let local = local as usize;
+        if bytes.u32(local) != Some(LOCAL_HEADER) {
+            return Err(invalid("zip", "bad local header"));
+        }
+        let start = local
+            + 30
+            + bytes.u16(local + 26).ok_or_else(truncated)? as usize
+            + bytes.u16(local + 28).ok_or_else(truncated)? as usize;
+        let contents = bytes
+            .slice(start, compressed as usize)
+            .ok_or_else(truncated)?;
+        let contents = match method {
+            STORED => contents.to_vec(),
+            DEFLATED => miniz_oxide::inflate::decompress_to_vec(contents)
+                .map_err(|e| invalid("zip", format!("{:?}", e.status)))?,
+            method => {
+                return Err(invalid(
+                    "zip",
+                    format!("unsupported compression method {}", method),
+                ))
+            }
+        };
+        if contents.len() as u64 != uncompressed {
+            return Err(invalid("zip", format!("\"{}\" has the wrong size", name)));
+        }
+        entries.push((name, contents));
+
+        position += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+fn truncated() -> super::Error {
+    invalid("zip", "file is truncated")
+}
+
+/// Reads little-endian integers out of bytes.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn slice(&self, start: usize, len: usize) -> Option<&'a [u8]> {
+        self.0.get(start..start.checked_add(len)?)
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        self.slice(at, 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        self.slice(at, 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&self, at: usize) -> Option<u64> {
+        self.slice(at, 8).map(|b| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(b);
+            u64::from_le_bytes(bytes)
+        })
+    }
+}
@@ -0,0 +1,435 @@
+//! Elementwise, reduction and linear algebra kernels used by the handlers.
+//!
+//! With the `parallel` feature, kernels over at least `PARALLEL_THRESHOLD` contiguous elements
+//! (or matrix products of at least that many multiplications) are split across rayon's global
+//! thread pool, which has one thread per core and is shared by every call. Smaller tensors stay
+//! on the calling thread, since handing out the work would cost more than it saves.
+//!
+//! With the `blas` feature, matrix multiplication and convolution (lowered to a matrix
+//! multiplication with im2col) use an optimized GEMM. Otherwise they use the loops in
+//! `reference`.
+
+use crate::Tsor;
+use ndarray::{ArrayD, ArrayView, Dimension, Zip};
+
+#[cfg(feature = "blas")]
+use self::blas as linear;
+#[cfg(not(feature = "blas"))]
+use self::reference as linear;
+
+/// The number of elements below which kernels stay single-threaded.
+pub const PARALLEL_THRESHOLD: usize = 1 << 16;
+
+/// Applies `f` to every element of `a`.
+pub fn map(a: &Tsor, f: impl Fn(f32) -> f32 + Sync) -> Tsor {
+    match a.as_slice() {
+        Some(a_slice) => {
+            let mut out = vec![0.0; a_slice.len()];
+            #[cfg(feature = "parallel")]
+            {
+                if out.len() >= PARALLEL_THRESHOLD {
+                    parallel::map(a_slice, &mut out, f);
+                    return shaped(a.shape(), out);
+                }
+            }
+            serial::map(a_slice, &mut out, f);
+            shaped(a.shape(), out)
+        }
+        None => a.mapv(f).into_shared(),
+    }
+}
+
+/// Applies `f` to every pair of elements of `a` and `b`, where `b` is broadcast to the shape
+/// of `a` like it is for ndarray's arithmetic operators.
+pub fn zip_map(a: &Tsor, b: &Tsor, f: impl Fn(f32, f32) -> f32 + Sync) -> Tsor {
+    if let (Some(a_slice), Some(b_slice), true) =
+        (a.as_slice(), b.as_slice(), a.shape() == b.shape())
+    {
+        let mut out = vec![0.0; a_slice.len()];
+        #[cfg(feature = "parallel")]
+        {
+            if out.len() >= PARALLEL_THRESHOLD {
+                parallel::zip_map(a_slice, b_slice, &mut out, f);
+                return shaped(a.shape(), out);
+            }
+        }
+        serial::zip_map(a_slice, b_slice, &mut out, f);
+        return shaped(a.shape(), out);
+    }
+    let b = b.broadcast(a.shape()).unwrap_or_else(|| {
+        panic!(
+            "could not broadcast array from shape: {:?} to: {:?}",
+            b.shape(),
+            a.shape()
+        )
+    });
+    let mut out = ArrayD::zeros(a.shape());
+    Zip::from(&mut out)
+        .and(a)
+        .and(&b)
+        .apply(|out, &a, &b| *out = f(a, b));
+    out.into_shared()
+}
+
+/// Sums every element of `a`.
+///
+/// In parallel the elements are summed in a different order, so the result can differ
+/// slightly from the single-threaded sum.
+pub fn sum(a: &Tsor) -> f32 {
+    match a.as_slice() {
+        #[cfg(feature = "parallel")]
+        Some(a_slice) if a_slice.len() >= PARALLEL_THRESHOLD => parallel::sum(a_slice),
+        Some(a_slice) => serial::sum(a_slice),
+        None => a.sum(),
+    }
+}
+
+/// The matrix product of two matrices.
+///
+/// Panics if either isn't a matrix or if the columns of `a` don't match the rows of `b`.
+pub fn matmul(a: &Tsor, b: &Tsor) -> Tsor {
+    let (a, b) = (view(a, "matmul"), view(b, "matmul"));
+    assert_eq!(
+        a.ncols(),
+        b.nrows(),
+        "can't multiply a matrix of shape {:?} by one of shape {:?}",
+        a.shape(),
+        b.shape()
+    );
+    #[cfg(feature = "parallel")]
+    {
+        if a.nrows() * a.ncols() * b.ncols() >= PARALLEL_THRESHOLD {
+            return parallel::matmul(a, b).into_dyn().into_shared();
+        }
+    }
+    serial::matmul(a, b).into_dyn().into_shared()
+}
+
+/// Convolves a batch of images with a kernel, as per `Op::Conv2d`.
+///
+/// Panics if the shapes aren't compatible.
+pub fn conv2d(input: &Tsor, kernel: &Tsor) -> Tsor {
+    let (input, kernel) = (view(input, "conv2d"), view(kernel, "conv2d"));
+    let ((_, channels, height, width), (_, kernel_channels, kernel_height, kernel_width)) =
+        (input.dim(), kernel.dim());
+    assert!(
+        channels == kernel_channels && kernel_height <= height && kernel_width <= width,
+        "can't convolve images of shape {:?} with a kernel of shape {:?}",
+        input.shape(),
+        kernel.shape()
+    );
+    linear::conv2d(input, kernel).into_dyn().into_shared()
+}
+
+/// Gets the delta of the images of `conv2d` from the delta of its output.
+pub fn conv2d_input_delta(delta: &Tsor, kernel: &Tsor) -> Tsor {
+    linear::conv2d_input_delta(view(delta, "conv2d"), view(kernel, "conv2d"))
+        .into_dyn()
+        .into_shared()
+}
+
+/// Gets the delta of the kernel of `conv2d` from the delta of its output.
+pub fn conv2d_kernel_delta(input: &Tsor, delta: &Tsor) -> Tsor {
+    linear::conv2d_kernel_delta(view(input, "conv2d"), view(delta, "conv2d"))
+        .into_dyn()
+        .into_shared()
+}
+
+/// Views a tensor with a fixed number of dimensions.
+fn view<'a, D>(tensor: &'a Tsor, op: &str) -> ArrayView<'a, f32, D>
+where
+    D: Dimension,
+{
+    tensor.view().into_dimensionality().unwrap_or_else(|_| {
+        panic!(
+            "{} can't take a tensor with {} dimensions",
+            op,
+            tensor.ndim()
+        )
+    })
+}
+
+fn shaped(shape: &[usize], elements: Vec<f32>) -> Tsor {
+    Tsor::from_shape_vec(shape, elements).unwrap()
+}
+
+/// Kernels on the calling thread.
+pub mod serial {
+    use ndarray::{Array2, ArrayView2};
+
+    pub fn map(a: &[f32], out: &mut [f32], f: impl Fn(f32) -> f32) {
+        for (out, &a) in out.iter_mut().zip(a) {
+            *out = f(a);
+        }
+    }
+
+    pub fn zip_map(a: &[f32], b: &[f32], out: &mut [f32], f: impl Fn(f32, f32) -> f32) {
+        for ((out, &a), &b) in out.iter_mut().zip(a).zip(b) {
+            *out = f(a, b);
+        }
+    }
+
+    pub fn sum(a: &[f32]) -> f32 {
+        a.iter().sum()
+    }
+
+    /// Multiplies matrices with the GEMM with the `blas` feature or the reference loops
+    /// otherwise.
+    pub fn matmul(a: ArrayView2<f32>, b: ArrayView2<f32>) -> Array2<f32> {
+        super::linear::matmul(a, b)
+    }
+}
+
+/// Kernels which split the work into one chunk per thread of rayon's global pool.
+#[cfg(feature = "parallel")]
+pub mod parallel {
+    use super::serial;
+    use ndarray::{stack, Array2, ArrayView2, Axis};
+    use rayon::prelude::*;
+
+    pub fn map(a: &[f32], out: &mut [f32], f: impl Fn(f32) -> f32 + Sync) {
+        let chunk = chunk_len(a.len());
+        a.par_chunks(chunk)
+            .zip(out.par_chunks_mut(chunk))
+            .for_each(|(a, out)| serial::map(a, out, &f));
+    }
+
+    pub fn zip_map(a: &[f32], b: &[f32], out: &mut [f32], f: impl Fn(f32, f32) -> f32 + Sync) {
+        let chunk = chunk_len(a.len());
+        a.par_chunks(chunk)
+            .zip(b.par_chunks(chunk))
+            .zip(out.par_chunks_mut(chunk))
+            .for_each(|((a, b), out)| serial::zip_map(a, b, out, &f));
+    }
+
+    pub fn sum(a: &[f32]) -> f32 {
+        let chunk = chunk_len(a.len());
+        // The partial sums are collected so they are added in the same order every time.
+        let sums: Vec<f32> = a.par_chunks(chunk).map(serial::sum).collect();
+        sums.into_iter().sum()
+    }
+
+    /// Multiplies a block of the rows of `a` by `b` on each thread.
+    pub fn matmul(a: ArrayView2<f32>, b: ArrayView2<f32>) -> Array2<f32> {
+        let rows: Vec<ArrayView2<f32>> =
+            a.axis_chunks_iter(Axis(0), chunk_len(a.nrows())).collect();
+        let products: Vec<Array2<f32>> = rows
+            .into_par_iter()
+            .map(|rows| serial::matmul(rows, b))
+            .collect();
+        let products: Vec<ArrayView2<f32>> =
+            products.iter().map(|product| product.view()).collect();
+        stack(Axis(0), &products).unwrap()
+    }
+
+    /// Splits `len` elements evenly between the threads of the pool.
+    pub(crate) fn chunk_len(len: usize) -> usize {
+        len.div_ceil(rayon::current_num_threads()).max(1)
+    }
+}
+
+/// Straightforward loops for the linear algebra kernels, which the optimized kernels are
+/// checked against.
+pub mod reference {
+    use ndarray::{Array2, Array4, ArrayView2, ArrayView4};
+
+    pub fn matmul(a: ArrayView2<f32>, b: ArrayView2<f32>) -> Array2<f32> {
+        Array2::from_shape_fn((a.nrows(), b.ncols()), |(i, j)| {
+            (0..a.ncols()).map(|k| a[(i, k)] * b[(k, j)]).sum()
+        })
+    }
+
+    pub fn conv2d(input: ArrayView4<f32>, kernel: ArrayView4<f32>) -> Array4<f32> {
+        let (batch, channels, height, width) = input.dim();
+        let (filters, _, kernel_height, kernel_width) = kernel.dim();
+        let shape = (
+            batch,
+            filters,
+            height - kernel_height + 1,
+            width - kernel_width + 1,
+        );
+        Array4::from_shape_fn(shape, |(n, f, y, x)| {
+            let mut sum = 0.0;
+            for c in 0..channels {
+                for ky in 0..kernel_height {
+                    for kx in 0..kernel_width {
+                        sum += input[(n, c, y + ky, x + kx)] * kernel[(f, c, ky, kx)];
+                    }
+                }
+            }
+            sum
+        })
+    }
+
+    pub fn conv2d_input_delta(delta: ArrayView4<f32>, kernel: ArrayView4<f32>) -> Array4<f32> {
+        let (batch, _, output_height, output_width) = delta.dim();
+        let (_, channels, kernel_height, kernel_width) = kernel.dim();
+        let mut input_delta = Array4::zeros((
+            batch,
+            channels,
+            output_height + kernel_height - 1,
+            output_width + kernel_width - 1,
+        ));
+        for ((n, f, y, x), &delta) in delta.indexed_iter() {
+            for c in 0..channels {
+                for ky in 0..kernel_height {
+                    for kx in 0..kernel_width {
+                        input_delta[(n, c, y + ky, x + kx)] += delta * kernel[(f, c, ky, kx)];
+                    }
+                }
+            }
+        }
+        input_delta
+    }
+
+    pub fn conv2d_kernel_delta(input: ArrayView4<f32>, delta: ArrayView4<f32>) -> Array4<f32> {
+        let (_, channels, height, width) = input.dim();
+        let (_, filters, output_height, output_width) = delta.dim();
+        let (kernel_height, kernel_width) = (height - output_height + 1, width - output_width + 1);
+        let mut kernel_delta = Array4::zeros((filters, channels, kernel_height, kernel_width));
+        for ((n, f, y, x), &delta) in delta.indexed_iter() {
+            for c in 0..channels {
+                for ky in 0..kernel_height {
+                    for kx in 0..kernel_width {
+                        kernel_delta[(f, c, ky, kx)] += delta * input[(n, c, y + ky, x + kx)];
+                    }
+                }
+            }
+        }
+        kernel_delta
+    }
+}
+
+/// Linear algebra kernels built on the GEMM from `matrixmultiply`.
+///
+/// Convolutions are lowered to matrix multiplications by unrolling every patch of the images
+/// that the kernel is applied to into a row of a matrix (im2col).
+#[cfg(feature = "blas")]
+pub mod blas {
+    use ndarray::{Array2, Array4, ArrayView2, ArrayView4};
+
+    pub fn matmul(a: ArrayView2<f32>, b: ArrayView2<f32>) -> Array2<f32> {
+        let ((m, k), n) = (a.dim(), b.ncols());
+        let mut c = Array2::zeros((m, n));
+        // Safety: the pointers and strides come from arrays of the given dimensions, and
+        // `sgemm` supports any strides, including the zero strides of broadcast arrays.
+        unsafe {
+            matrixmultiply::sgemm(
+                m,
+                k,
+                n,
+                1.0,
+                a.as_ptr(),
+                a.strides()[0],
+                a.strides()[1],
+                b.as_ptr(),
+                b.strides()[0],
+                b.strides()[1],
+                0.0,
+                c.as_mut_ptr(),
+                n as isize,
+                1,
+            );
+        }
+        c
+    }
+
+    pub fn conv2d(input: ArrayView4<f32>, kernel: ArrayView4<f32>) -> Array4<f32> {
+        let (batch, _, height, width) = input.dim();
+        let (filters, _, kernel_height, kernel_width) = kernel.dim();
+        let (output_height, output_width) = (height - kernel_height + 1, width - kernel_width + 1);
+        let output = matmul(
+            im2col(input, kernel_height, kernel_width).view(),
+            kernel_matrix(kernel).t(),
+        );
+        Array4::from_shape_fn(
+            (batch, filters, output_height, output_width),
+            |(n, f, y, x)| output[((n * output_height + y) * output_width + x, f)],
+        )
+    }
+
+    pub fn conv2d_input_delta(delta: ArrayView4<f32>, kernel: ArrayView4<f32>) -> Array4<f32> {
+        let (batch, _, output_height, output_width) = delta.dim();
+        let (_, channels, kernel_height, kernel_width) = kernel.dim();
+        let columns = matmul(delta_matrix(delta).view(), kernel_matrix(kernel).view());
+        let mut input_delta = Array4::zeros((
+            batch,
+            channels,
+            output_height + kernel_height - 1,
+            output_width + kernel_width - 1,
+        ));
+        // The reverse of im2col, summing the overlapping patches.
+        for ((row, column), &delta) in columns.indexed_iter() {
+            let (n, y, x) = patch(row, output_height, output_width);
+            let (c, ky, kx) = patch(column, kernel_height, kernel_width);
+            input_delta[(n, c, y + ky, x + kx)] += delta;
+        }
+        input_delta
+    }
+
+    pub fn conv2d_kernel_delta(input: ArrayView4<f32>, delta: ArrayView4<f32>) -> Array4<f32> {
+        let (_, channels, height, width) = input.dim();
+        let (_, filters, output_height, output_width) = delta.dim();
+        let (kernel_height, kernel_width) = (height - output_height + 1, width - output_width + 1);
+        let kernel_delta = matmul(
+            delta_matrix(delta).t(),
+            im2col(input, kernel_height, kernel_width).view(),
+        );
+        kernel_delta
+            .into_shape((filters, channels, kernel_height, kernel_width))
+            .unwrap()
+    }
+
+    /// Splits the index of a row or column of the im2col matrix into the three indices it
+    /// flattens, where the last two have the given lengths.
+    fn patch(index: usize, height: usize, width: usize) -> (usize, usize, usize) {
+        (
+            index / (height * width),
+            index / width % height,
+            index % width,
+        )
+    }
+
+    /// Unrolls the patches of the images into the rows of a matrix with the shape
+    /// `[batch * output_height * output_width, channels * kernel_height * kernel_width]`.
+    fn im2col(input: ArrayView4<f32>, kernel_height: usize, kernel_width: usize) -> Array2<f32> {
+        let (batch, channels, height, width) = input.dim();
+        let (output_height, output_width) = (height - kernel_height + 1, width - kernel_width + 1);
+        Array2::from_shape_fn(
+            (
+                batch * output_height * output_width,
+                channels * kernel_height * kernel_width,
+            ),
+            |(row, column)| {
+                let (n, y, x) = patch(row, output_height, output_width);
+                let (c, ky, kx) = patch(column, kernel_height, kernel_width);
+                input[(n, c, y + ky, x + kx)]
+            },
+        )
+    }
+
+    /// Flattens each filter of the kernel into a row.
+    fn kernel_matrix(kernel: ArrayView4<f32>) -> Array2<f32> {
+        let (filters, channels, kernel_height, kernel_width) = kernel.dim();
+        Array2::from_shape_fn(
+            (filters, channels * kernel_height * kernel_width),
+            |(f, column)| {
+                let (c, ky, kx) = patch(column, kernel_height, kernel_width);
+                kernel[(f, c, ky, kx)]
+            },
+        )
+    }
+
+    /// Lays out the delta of the output with one row per patch and one column per filter,
+    /// like the output of the matrix multiplication in `conv2d`.
+    fn delta_matrix(delta: ArrayView4<f32>) -> Array2<f32> {
+        let (batch, filters, output_height, output_width) = delta.dim();
+        Array2::from_shape_fn(
+            (batch * output_height * output_width, filters),
+            |(row, f)| {
+                let (n, y, x) = patch(row, output_height, output_width);
+                delta[(n, f, y, x)]
+            },
+        )
+    }
+}
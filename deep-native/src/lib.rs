@@ -1,8 +1,21 @@
+// The `Fail` derive generates its impls inside of a const block.
+#![allow(non_local_definitions)]
+
+pub mod data;
+pub mod fusion;
+pub mod handlers;
+pub mod io;
+pub mod kernels;
+pub mod profile;
+pub mod session;
+
 use deep::*;
 use deep_backend_tools::*;
+use fusion::{Fusion, FusionPlan};
 use ndarray::{ArcArray, IxDyn};
+use profile::{Profile, Profiler};
 use rand_core::RngCore;
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 use std::iter::{Extend, FromIterator};
 
 pub type Tsor = ArcArray<f32, IxDyn>;
@@ -30,7 +43,8 @@ where
     ndarray::arr3(n).into_shared().into_dyn()
 }
 
-pub trait Handler {
+/// Handlers are shared between the threads of the executor, so they must be `Send + Sync`.
+pub trait Handler: Send + Sync {
     /// This returns the op ty that this handler can execute.
     fn op(&self) -> OpTy;
 
@@ -49,11 +63,61 @@ pub trait Handler {
         state: &[Tsor],
         output_delta: (usize, Tsor),
     ) -> (ImOp<Native>, Vec<Tsor>);
+
+    /// This performs forward mode differentiation for the op.
+    ///
+    /// Returns the tangents of the outputs given the tangents of the inputs in `tangents`
+    /// and the tangents of the trainable state in `state_tangents`.
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        state: &[Tsor],
+        tangents: ImOp<Native>,
+        state_tangents: &[Tsor],
+    ) -> Vec<Tsor>;
+
+    /// This says if fusion may compute the op with the standard elementwise kernel in place of
+    /// `forward`, which is only true of the standard handlers of elementwise ops.
+    fn fusable(&self) -> bool {
+        false
+    }
+
+    /// This says what `backward` reads of the inputs of the op.
+    ///
+    /// The tape drops the values that aren't read when the backend keeps less than every value,
+    /// so this must not leave out anything that `backward` reads.
+    fn reads(&self) -> Reads {
+        Reads::Values
+    }
 }
 
-#[derive(Default)]
+/// A custom gradient for `Op::CustomGradient`.
+///
+/// It is given the values of the op's inputs, its output, and the delta of the output, and it
+/// returns one delta for each input.
+pub type Gradient = Box<dyn Fn(&[Tsor], &Tsor, Tsor) -> Vec<Tsor> + Send + Sync>;
+
 pub struct Native {
     handlers: HashMap<OpTy, Box<dyn Handler>>,
+    gradients: HashMap<String, Gradient>,
+    /// The threads that the forward pass runs on, if there are more than one.
+    pool: Option<ThreadPool>,
+    fusion: Fusion,
+    retention: Retention,
+    profiler: Option<Profiler>,
+}
+
+impl Default for Native {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            gradients: HashMap::new(),
+            pool: None,
+            fusion: Fusion::Off,
+            retention: Retention::All,
+            profiler: None,
+        }
+    }
 }
 
 impl Native {
@@ -61,6 +125,80 @@ impl Native {
         Self::default()
     }
 
+    /// Sets the number of threads that independent nodes of the graph are run on during the
+    /// forward pass. With one thread, which is the default, nodes run on the calling thread.
+    ///
+    /// The threads are started here and reused by every forward pass.
+    pub fn threads(self, threads: usize) -> Self {
+        Self {
+            pool: if threads > 1 {
+                Some(ThreadPool::new(threads))
+            } else {
+                None
+            },
+            ..self
+        }
+    }
+
+    /// Sets whether chains of elementwise ops are fused into one loop during the forward pass.
+    ///
+    /// Only ops whose handlers are `Handler::fusable` are fused, so ops with other handlers are
+    /// always run by their handlers. With `Fusion::Inference`, the tape from `forward` can't be
+    /// used to backprop through fused ops, so use `Fusion::Training` when training.
+    ///
+    /// Fused groups, along with every value they depend on, are solved on the calling thread
+    /// before the rest of the graph, and those values are all kept. Neither `threads` nor
+    /// `retention` applies to the part of the graph upstream of a group.
+    pub fn fusion(self, fusion: Fusion) -> Self {
+        Self { fusion, ..self }
+    }
+
+    /// Sets which values the tape from `forward` keeps.
+    ///
+    /// With anything other than `Retention::All`, the forward pass runs on the calling thread.
+    pub fn retention(self, retention: Retention) -> Self {
+        Self { retention, ..self }
+    }
+
+    /// Sets whether every call to a handler's `forward` and `backward` is timed and recorded,
+    /// which can be read back with `profile`. Profiling is off by default.
+    pub fn profiling(self, enabled: bool) -> Self {
+        Self {
+            profiler: if enabled { Some(Profiler::new()) } else { None },
+            ..self
+        }
+    }
+
+    /// Gets the handler calls recorded since profiling was enabled or the profile was last
+    /// taken, or `None` if profiling is off.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profiler.as_ref().map(Profiler::profile)
+    }
+
+    /// Takes the handler calls recorded so far, so the next profile starts empty, such as to
+    /// profile each training step on its own.
+    pub fn take_profile(&self) -> Option<Profile> {
+        self.profiler.as_ref().map(Profiler::take)
+    }
+
+    /// Compiles a `Session` for solving `outputs` of `graph` repeatedly, which is cheaper than
+    /// calling `forward` each time.
+    pub fn session(&self, graph: &Graph, outputs: &[Input]) -> Result<session::Session<'_>> {
+        session::Session::new(self, graph, outputs)
+    }
+
+    /// Checks if there is a handler for an op type.
+    pub(crate) fn has_handler(&self, ty: OpTy) -> bool {
+        self.handlers.contains_key(&ty)
+    }
+
+    /// Checks if the handler for an op lets fusion compute it.
+    pub(crate) fn fusable(&self, op: &Op) -> bool {
+        self.handlers
+            .get(&op.into())
+            .is_some_and(|handler| handler.fusable())
+    }
+
     /// Use this to add one handler.
     pub fn handler<H>(mut self, h: H) -> Self
     where
@@ -71,6 +209,15 @@ impl Native {
         self
     }
 
+    /// Use this to add a custom gradient that can be refered to by `Op::CustomGradient`.
+    pub fn gradient<F>(mut self, name: impl Into<String>, f: F) -> Self
+    where
+        F: Fn(&[Tsor], &Tsor, Tsor) -> Vec<Tsor> + Send + Sync + 'static,
+    {
+        self.gradients.insert(name.into(), Box::new(f));
+        self
+    }
+
     /// Use this to add several handlers at once, such as from a library.
     pub fn handlers<I>(mut self, iter: I) -> Self
     where
@@ -124,12 +271,16 @@ impl Backend for Native {
         graph
             .ops
             .iter()
-            .map(|op| {
-                let ty = op.into();
-                self.handlers
-                    .get(&ty)
-                    .ok_or_else(|| Error::OpHasNoHandler { ty })
-                    .map(|handler| handler.generate_state(op, &mut rng))
+            .map(|op| match op {
+                // These are carried out by the `Tape`, so they have no handler or state.
+                Op::StopGradient(..) | Op::CustomGradient(..) => Ok(vec![]),
+                op => {
+                    let ty = op.into();
+                    self.handlers
+                        .get(&ty)
+                        .ok_or(Error::OpHasNoHandler { ty })
+                        .map(|handler| handler.generate_state(op, &mut rng))
+                }
             })
             .collect()
     }
@@ -142,9 +293,41 @@ impl Backend for Native {
         inputs: &Self::Inputs,
         tensor: Input,
     ) -> Result<(Self::Tensor, Self::Internal)> {
+        let (mut outputs, tape) = self.forward_many(graph, state, inputs, &[tensor])?;
+        Ok((outputs.pop().unwrap(), tape))
+    }
+
+    /// Gets the outputs of solving each of the requested tensors in one pass, so the values
+    /// they depend on are only computed once.
+    fn forward_many(
+        &self,
+        graph: &Graph,
+        state: &Self::State,
+        inputs: &Self::Inputs,
+        tensors: &[Input],
+    ) -> Result<(Vec<Self::Tensor>, Self::Internal)> {
         let mut tape = Tape::new();
-        tape.solve(self, graph, &state[..], inputs, tensor)
-            .map(|tensor| (tensor, tape))
+        for group in FusionPlan::new(self, graph, tensors, self.fusion).groups {
+            fusion::solve_group(self, &group, &mut tape, graph, &state[..], inputs)?;
+        }
+        let outputs = if self.retention != Retention::All {
+            tape.solve_planned(self, graph, &state[..], inputs, tensors, self.retention)?
+        } else {
+            tensors
+                .iter()
+                .map(|tensor| {
+                    let tensor = tensor.clone();
+                    match &self.pool {
+                        Some(pool) => {
+                            tape.solve_parallel(self, graph, &state[..], inputs, tensor, pool)
+                        }
+                        None => tape.solve(self, graph, &state[..], inputs, tensor),
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        tape.discard_checkpointed(self, graph, tensors);
+        Ok((outputs, tape))
     }
 
     /// Propogates a delta from the output back to the input via chain rule
@@ -171,9 +354,68 @@ impl Backend for Native {
         )
     }
 
-    /// Applies a delta to the graph.
-    fn train(&self, state: &mut Self::State, delta: &Self::Delta) -> Result<()> {
+    /// Propogates the deltas of several outputs back through the graph in one pass, summing
+    /// them wherever they meet, and produces one `Delta`.
+    fn backward_many(
+        &self,
+        graph: &Graph,
+        state: &Self::State,
+        internal: &Self::Internal,
+        inputs: &Self::Inputs,
+        output_deltas: Vec<(Input, Self::Tensor)>,
+    ) -> Result<Self::Delta> {
+        internal.backprop_many(
+            self,
+            graph,
+            &state[..],
+            inputs,
+            output_deltas,
+            AccumulateTensors::new(),
+        )
+    }
+
+    /// Propogates a delta from the output back to the parameters and the inputs, producing
+    /// the `Delta` of the parameters and a feed dict of deltas for the inputs.
+    fn backward_inputs(
+        &self,
+        graph: &Graph,
+        state: &Self::State,
+        internal: &Self::Internal,
+        inputs: &Self::Inputs,
+        tensor: Input,
+        output_delta: Self::Tensor,
+    ) -> Result<(Self::Delta, Self::Inputs)> {
+        internal
+            .backprop_feeds(
+                self,
+                graph,
+                &state[..],
+                inputs,
+                tensor,
+                output_delta,
+                AccumulateTensors::new(),
+                vec![],
+            )
+            .map(|(delta, feed_deltas)| {
+                let mut input_deltas: HashMap<String, Tsor> = HashMap::new();
+                for (name, feed_delta) in feed_deltas {
+                    match input_deltas.entry(name) {
+                        Entry::Occupied(mut o) => *o.get_mut() += &feed_delta,
+                        Entry::Vacant(v) => {
+                            v.insert(feed_delta);
+                        }
+                    }
+                }
+                (delta, input_deltas)
+            })
+    }
+
+    /// Applies a delta to the graph, skipping frozen nodes.
+    fn train(&self, graph: &Graph, state: &mut Self::State, delta: &Self::Delta) -> Result<()> {
         for (&node, deltas) in &delta.table {
+            if graph.is_frozen(node) {
+                continue;
+            }
             for (a, b) in state[node].iter_mut().zip(deltas.iter()) {
                 *a += b;
             }
@@ -184,10 +426,45 @@ impl Backend for Native {
 
 impl Immediate for Native {
     fn solve(&self, imop: ImOp<Self>, state: &[Tsor]) -> Option<Vec<Tsor>> {
+        let ty = (&imop).into();
+        let handler = self.handlers.get(&ty)?;
+        Some(match &self.profiler {
+            Some(profiler) => profiler.forward(imop, |imop| handler.forward(imop, state)),
+            None => handler.forward(imop, state),
+        })
+    }
+}
+
+impl Tangent for Native {
+    fn tangent(
+        &self,
+        imop: ImOp<Self>,
+        state: &[Tsor],
+        tangents: ImOp<Self>,
+        state_tangents: &[Tsor],
+    ) -> Option<Vec<Tsor>> {
         let ty = (&imop).into();
         self.handlers
             .get(&ty)
-            .map(|handler| handler.forward(imop, state))
+            .map(|handler| handler.tangent(imop, state, tangents, state_tangents))
+    }
+
+    fn zeros_like(&self, tensor: &Tsor) -> Tsor {
+        Tsor::zeros(tensor.shape())
+    }
+}
+
+impl Dense for Native {
+    fn shape(&self, tensor: &Tsor) -> Vec<usize> {
+        tensor.shape().to_vec()
+    }
+
+    fn to_vec(&self, tensor: &Tsor) -> Vec<f32> {
+        tensor.iter().cloned().collect()
+    }
+
+    fn tensor_from_vec(&self, shape: &[usize], elements: Vec<f32>) -> Tsor {
+        Tsor::from_shape_vec(shape, elements).expect("elements don't match the shape")
     }
 }
 
@@ -199,8 +476,38 @@ impl Propogate for Native {
         output_delta: (usize, Tsor),
     ) -> Option<(ImOp<Self>, Vec<Tsor>)> {
         let ty = (&imop).into();
-        self.handlers
-            .get(&ty)
-            .map(|handler| handler.backward(imop, state, output_delta))
+        let handler = self.handlers.get(&ty)?;
+        Some(match &self.profiler {
+            Some(profiler) => profiler.backward(imop, output_delta, |imop, output_delta| {
+                handler.backward(imop, state, output_delta)
+            }),
+            None => handler.backward(imop, state, output_delta),
+        })
+    }
+
+    fn custom_gradient(
+        &self,
+        name: &str,
+        inputs: &[Tsor],
+        output: &Tsor,
+        output_delta: Tsor,
+    ) -> Option<Vec<Tsor>> {
+        self.gradients
+            .get(name)
+            .map(|gradient| gradient(inputs, output, output_delta))
+    }
+
+    fn add_delta(&self, sum: &mut Tsor, delta: &Tsor) {
+        *sum += delta;
+    }
+
+    fn reads(&self, op: &Op) -> Reads {
+        match Reads::of(op) {
+            Reads::Nothing => Reads::Nothing,
+            _ => self
+                .handlers
+                .get(&op.into())
+                .map_or(Reads::Values, |handler| handler.reads()),
+        }
     }
 }
@@ -0,0 +1,317 @@
+//! Profiling of the time that each handler call takes and the memory it allocates.
+//!
+//! Enable it with `Native::profiling`, run the graph, and then get the `Profile` with
+//! `Native::profile`. Ops in fused groups are computed without their handlers, so each group is
+//! recorded as one call under the op type of its output.
+
+use crate::{Native, Tsor};
+use deep::OpTy;
+use deep_backend_tools::ImOp;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+/// What a recorded call computed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Pass {
+    /// `Handler::forward`.
+    Forward,
+    /// `Handler::backward`.
+    Backward,
+    /// A fused group of elementwise ops.
+    Fused,
+}
+
+/// One call to a handler, or one fused group.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub ty: OpTy,
+    pub pass: Pass,
+    /// When the call started, relative to when profiling was enabled.
+    pub start: Duration,
+    pub duration: Duration,
+    /// The thread that made the call, numbered in the order that threads were first seen.
+    pub thread: usize,
+    /// The shapes of the op's inputs. For the backward pass, the delta of the output comes last.
+    pub inputs: Vec<Vec<usize>>,
+    /// The shapes of the tensors that were produced, which for the backward pass are the deltas
+    /// of the inputs followed by the deltas of the state, and for a fused group are the values
+    /// that it keeps.
+    pub outputs: Vec<Vec<usize>>,
+    /// The memory allocated for the outputs, in bytes. Outputs that share memory with an input,
+    /// such as a delta that is passed through, aren't counted, and neither is scratch space.
+    pub allocated_bytes: usize,
+}
+
+/// The totals of the calls to one handler method.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PassStats {
+    pub calls: usize,
+    pub time: Duration,
+    /// The total memory allocated for outputs, in bytes.
+    pub allocated_bytes: usize,
+}
+
+/// The totals of the calls to the handler of one op type, and of the fused groups that it is
+/// the output of.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpStats {
+    pub ty: OpTy,
+    pub forward: PassStats,
+    pub backward: PassStats,
+    pub fused: PassStats,
+}
+
+impl OpStats {
+    /// The time spent in every pass.
+    pub fn time(&self) -> Duration {
+        self.forward.time + self.backward.time + self.fused.time
+    }
+}
+
+/// The calls to handlers that were recorded.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    /// The calls in the order that they finished.
+    pub events: Vec<Event>,
+}
+
+impl Profile {
+    /// Totals the calls of each op type, with the op types that took the most time first.
+    pub fn by_op(&self) -> Vec<OpStats> {
+        let mut stats: HashMap<OpTy, OpStats> = HashMap::new();
+        for event in &self.events {
+            let op = stats.entry(event.ty).or_insert(OpStats {
+                ty: event.ty,
+                forward: PassStats::default(),
+                backward: PassStats::default(),
+                fused: PassStats::default(),
+            });
+            let pass = match event.pass {
+                Pass::Forward => &mut op.forward,
+                Pass::Backward => &mut op.backward,
+                Pass::Fused => &mut op.fused,
+            };
+            pass.calls += 1;
+            pass.time += event.duration;
+            pass.allocated_bytes += event.allocated_bytes;
+        }
+        let mut stats: Vec<OpStats> = stats.into_values().collect();
+        stats.sort_by_key(|op| std::cmp::Reverse(op.time()));
+        stats
+    }
+
+    /// Formats the totals of each op type as a table.
+    pub fn summary(&self) -> String {
+        let stats = self.by_op();
+        let total: Duration = stats.iter().map(OpStats::time).sum();
+        let mut table = format!("{:<14}", "op");
+        for pass in ["fwd", "bwd", "fused"] {
+            write!(
+                table,
+                "{:>12}{:>12}{:>14}",
+                format!("{} calls", pass),
+                format!("{} ms", pass),
+                format!("{} alloc B", pass)
+            )
+            .unwrap();
+        }
+        writeln!(table, "{:>8}", "time %").unwrap();
+        for op in &stats {
+            let share = if total > Duration::from_secs(0) {
+                100.0 * op.time().as_secs_f64() / total.as_secs_f64()
+            } else {
+                0.0
+            };
+            write!(table, "{:<14}", format!("{:?}", op.ty)).unwrap();
+            for pass in [&op.forward, &op.backward, &op.fused] {
+                write!(
+                    table,
+                    "{:>12}{:>12.3}{:>14}",
+                    pass.calls,
+                    millis(pass.time),
+                    pass.allocated_bytes
+                )
+                .unwrap();
+            }
+            writeln!(table, "{:>8.1}", share).unwrap();
+        }
+        writeln!(table, "total {:.3} ms", millis(total)).unwrap();
+        table
+    }
+
+    /// Writes the calls in the Chrome trace event format, which can be opened in
+    /// `chrome://tracing` or Perfetto.
+    pub fn write_chrome_trace(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "{{\"traceEvents\":[")?;
+        for (i, event) in self.events.iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            let pass = match event.pass {
+                Pass::Forward => "forward",
+                Pass::Backward => "backward",
+                Pass::Fused => "fused",
+            };
+            write!(
+                writer,
+                "{{\"name\":\"{:?}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\
+                 \"pid\":0,\"tid\":{},\"args\":{{\"inputs\":{:?},\"outputs\":{:?},\"allocated_bytes\":{}}}}}",
+                event.ty,
+                pass,
+                micros(event.start),
+                micros(event.duration),
+                event.thread,
+                event.inputs,
+                event.outputs,
+                event.allocated_bytes
+            )?;
+        }
+        write!(writer, "],\"displayTimeUnit\":\"ms\"}}")
+    }
+
+    /// Gets the calls in the Chrome trace event format as per `write_chrome_trace`.
+    pub fn chrome_trace(&self) -> String {
+        let mut trace = vec![];
+        self.write_chrome_trace(&mut trace).unwrap();
+        String::from_utf8(trace).unwrap()
+    }
+}
+
+/// Records calls to handlers as they happen.
+pub(crate) struct Profiler {
+    start: Instant,
+    events: Mutex<Vec<Event>>,
+    threads: Mutex<Vec<ThreadId>>,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            events: Mutex::new(vec![]),
+            threads: Mutex::new(vec![]),
+        }
+    }
+
+    /// Times a call to `Handler::forward`.
+    pub(crate) fn forward(
+        &self,
+        imop: ImOp<Native>,
+        call: impl FnOnce(ImOp<Native>) -> Vec<Tsor>,
+    ) -> Vec<Tsor> {
+        let ty = (&imop).into();
+        let tensors = imop.tensors();
+        let inputs = shapes(&tensors);
+        let shared = pointers(&tensors);
+        let start = Instant::now();
+        let outputs = call(imop);
+        self.record(ty, Pass::Forward, start, inputs, shared, outputs.iter());
+        outputs
+    }
+
+    /// Times a call to `Handler::backward`.
+    pub(crate) fn backward(
+        &self,
+        imop: ImOp<Native>,
+        output_delta: (usize, Tsor),
+        call: impl FnOnce(ImOp<Native>, (usize, Tsor)) -> (ImOp<Native>, Vec<Tsor>),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        let ty = (&imop).into();
+        let mut tensors = imop.tensors();
+        tensors.push(&output_delta.1);
+        let inputs = shapes(&tensors);
+        let shared = pointers(&tensors);
+        let start = Instant::now();
+        let (input_deltas, state_deltas) = call(imop, output_delta);
+        let outputs = input_deltas.tensors().into_iter().chain(&state_deltas);
+        self.record(ty, Pass::Backward, start, inputs, shared, outputs);
+        (input_deltas, state_deltas)
+    }
+
+    /// Records a fused group whose output is of type `ty`, which started at `start`.
+    pub(crate) fn fused(&self, ty: OpTy, start: Instant, inputs: &[Tsor], kept: &[Tsor]) {
+        let inputs: Vec<&Tsor> = inputs.iter().collect();
+        let shared = pointers(&inputs);
+        self.record(ty, Pass::Fused, start, shapes(&inputs), shared, kept.iter());
+    }
+
+    /// Takes the calls recorded so far.
+    pub(crate) fn take(&self) -> Profile {
+        Profile {
+            events: std::mem::take(&mut *self.events.lock().unwrap()),
+        }
+    }
+
+    /// Gets the calls recorded so far.
+    pub(crate) fn profile(&self) -> Profile {
+        Profile {
+            events: self.events.lock().unwrap().clone(),
+        }
+    }
+
+    fn record<'a>(
+        &self,
+        ty: OpTy,
+        pass: Pass,
+        start: Instant,
+        inputs: Vec<Vec<usize>>,
+        mut shared: Vec<*const f32>,
+        outputs: impl Iterator<Item = &'a Tsor>,
+    ) {
+        let duration = start.elapsed();
+        let outputs: Vec<&Tsor> = outputs.collect();
+        // Outputs that share memory with an input or an earlier output weren't allocated.
+        let mut allocated_bytes = 0;
+        for tensor in &outputs {
+            if !shared.contains(&tensor.as_ptr()) {
+                shared.push(tensor.as_ptr());
+                allocated_bytes += tensor.len() * std::mem::size_of::<f32>();
+            }
+        }
+        let event = Event {
+            ty,
+            pass,
+            start: start.duration_since(self.start),
+            duration,
+            thread: self.thread(),
+            inputs,
+            outputs: shapes(&outputs),
+            allocated_bytes,
+        };
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// Numbers the current thread.
+    fn thread(&self) -> usize {
+        let id = thread::current().id();
+        let mut threads = self.threads.lock().unwrap();
+        threads.iter().position(|&t| t == id).unwrap_or_else(|| {
+            threads.push(id);
+            threads.len() - 1
+        })
+    }
+}
+
+fn shapes(tensors: &[&Tsor]) -> Vec<Vec<usize>> {
+    tensors
+        .iter()
+        .map(|tensor| tensor.shape().to_vec())
+        .collect()
+}
+
+/// Gets where the memory of each tensor starts, which is shared by tensors that share memory.
+fn pointers(tensors: &[&Tsor]) -> Vec<*const f32> {
+    tensors.iter().map(|tensor| tensor.as_ptr()).collect()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e3
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
@@ -0,0 +1,42 @@
+//! Sessions that run a compiled `Plan` with a `Native` backend.
+
+use crate::{Native, Tsor};
+use deep::*;
+use deep_backend_tools::*;
+use std::collections::HashMap;
+
+/// A graph compiled for solving some of its outputs over and over with a backend.
+pub struct Session<'a> {
+    backend: &'a Native,
+    plan: Plan,
+}
+
+impl<'a> Session<'a> {
+    /// Compiles the plan for solving `outputs` of `graph`.
+    ///
+    /// Fails if any op that is needed has no handler.
+    pub fn new(backend: &'a Native, graph: &Graph, outputs: &[Input]) -> Result<Self> {
+        let plan = Plan::compile(graph, outputs);
+        for node in plan.nodes() {
+            let op = &graph.ops[node];
+            // These are carried out by the plan, so they have no handler.
+            if !matches!(op, Op::StopGradient(..) | Op::CustomGradient(..)) {
+                let ty = op.into();
+                if !backend.has_handler(ty) {
+                    return Err(Error::OpHasNoHandler { ty });
+                }
+            }
+        }
+        Ok(Self { backend, plan })
+    }
+
+    /// Gets the compiled plan.
+    pub fn plan(&self) -> &Plan {
+        &self.plan
+    }
+
+    /// Solves the outputs the session was compiled for, in the same order.
+    pub fn run(&self, state: &[Vec<Tsor>], inputs: &HashMap<String, Tsor>) -> Result<Vec<Tsor>> {
+        self.plan.run(self.backend, state, inputs)
+    }
+}
@@ -4,6 +4,7 @@ use deep_native::*;
 use maplit::hashmap;
 use ndarray::arr1;
 use rand::{thread_rng, Rng, RngCore};
+use std::collections::HashMap;
 
 struct Add;
 
@@ -38,6 +39,20 @@ impl Handler for Add {
             vec![],
         )
     }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Add(..), ImOp::Add(ta, tb)) = (&imop, tangents) {
+            vec![ta + tb]
+        } else {
+            panic!("got {:?} when OpTy::Add was expected", OpTy::from(&imop));
+        }
+    }
 }
 
 struct Sub;
@@ -70,6 +85,20 @@ impl Handler for Sub {
         assert_eq!(ty, OpTy::Sub);
         (ImOp::Sub(output_delta.clone(), -output_delta), vec![])
     }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Sub(..), ImOp::Sub(ta, tb)) = (&imop, tangents) {
+            vec![ta - tb]
+        } else {
+            panic!("got {:?} when OpTy::Sub was expected", OpTy::from(&imop));
+        }
+    }
 }
 
 struct Square;
@@ -106,6 +135,20 @@ impl Handler for Square {
             panic!("got {:?} when OpTy::Square was expected", OpTy::from(&imop));
         }
     }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        if let (ImOp::Square(a), ImOp::Square(ta)) = (&imop, tangents) {
+            vec![2.0 * ta * a]
+        } else {
+            panic!("got {:?} when OpTy::Square was expected", OpTy::from(&imop));
+        }
+    }
 }
 
 struct TrainConst;
@@ -144,6 +187,18 @@ impl Handler for TrainConst {
         assert_eq!(ty, OpTy::TrainConst);
         (ImOp::TrainConst, vec![output_delta])
     }
+
+    fn tangent(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        _tangents: ImOp<Native>,
+        state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        let ty: OpTy = (&imop).into();
+        assert_eq!(ty, OpTy::TrainConst);
+        vec![state_tangents[0].clone()]
+    }
 }
 
 #[test]
@@ -196,7 +251,7 @@ fn train_add() {
     // The learning rate.
     let learning_rate = 0.01;
 
-    let mut loss_value = std::f32::NAN;
+    let mut loss_value = f32::NAN;
 
     for _ in 0..1000 {
         // Random x value
@@ -210,19 +265,350 @@ fn train_add() {
         };
 
         // Train the network and get back the loss value.
-        loss_value = loss
-            .gradient_descent(
-                &backend,
-                &mut state,
-                &feed,
-                learning_rate,
-                |t| *t.iter().next().unwrap(),
-                tsor0,
-            )
+        let (value, _) = loss
+            .gradient_descent(&backend, &mut state, &feed, |t| {
+                (*t.iter().next().unwrap(), tsor0(-learning_rate))
+            })
             .expect("unable to train");
+        loss_value = value;
         eprintln!("loss at {}", loss_value);
     }
 
     // Loss starts around 25.
     assert!(loss_value < 0.1);
 }
+
+#[test]
+fn transfer_named_state() {
+    let backend = Native::new().handler(Add).handler(TrainConst);
+
+    // Two graphs with the parameter at different positions.
+    let a = Tensor::from("x") + Tensor::train_const(vec![], 0.0).named("layer/bias");
+    let b = (Tensor::train_const(vec![], 1.0).named("other") + Tensor::from("x"))
+        + Tensor::train_const(vec![], 0.0)
+            .named("bias")
+            .scoped("layer");
+
+    let mut a_state = a
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let mut b_state = b
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+
+    a_state
+        .set_named(&a.graph(), "layer/bias", vec![tsor0(3.0)])
+        .expect("unable to set state");
+    let map = a_state.state_map(&a.graph());
+    let loaded = b_state
+        .load_state_map(&b.graph(), &map)
+        .expect("unable to load state");
+    assert_eq!(loaded, 1);
+    assert_eq!(
+        b_state.get_named(&b.graph(), "layer/bias"),
+        Some(&[tsor0(3.0)][..])
+    );
+
+    let feed = hashmap! {
+        "x".to_owned() => tsor0(2.0),
+    };
+    let output = b.eval(&backend, &b_state, &feed).expect("unable to eval");
+    assert_eq!(output, tsor0(6.0));
+}
+
+#[test]
+fn shared_parameter() {
+    let backend = Native::new()
+        .handler(Add)
+        .handler(Square)
+        .handler(TrainConst);
+
+    // Use one parameter in two separate graphs and then join them.
+    let w = Tensor::train_const(vec![], 0.0).named("w");
+    let a = (Tensor::from("a") + w.clone()).squared();
+    let b = (Tensor::from("b") + w).squared();
+    let loss = a + b;
+
+    let graph = loss.graph().clone();
+    let params = graph
+        .ops
+        .iter()
+        .filter(|op| OpTy::from(*op) == OpTy::TrainConst)
+        .count();
+    assert_eq!(params, 1);
+
+    let mut state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "a".to_owned() => tsor0(1.0),
+        "b".to_owned() => tsor0(2.0),
+    };
+    let output = Input::Internal(Internal {
+        node: graph.ops.len() - 1,
+        output: 0,
+    });
+    let (_, tape) = backend
+        .forward(&graph, &state, &feed, output.clone())
+        .expect("unable to forward");
+    let delta = backend
+        .backward(&graph, &state, &tape, &feed, output, tsor0(1.0))
+        .expect("unable to backward");
+
+    // The gradient from both uses is summed into one entry.
+    let node = graph.node("w").unwrap();
+    let trained = delta.table.values().filter(|t| !t.is_empty()).count();
+    assert_eq!(trained, 1);
+    assert_eq!(delta.table[&node], vec![tsor0(6.0)]);
+
+    backend
+        .train(&graph, &mut state, &delta)
+        .expect("unable to train");
+    assert_eq!(state[node], vec![tsor0(6.0)]);
+}
+
+#[test]
+fn tied_by_identity() {
+    let backend = Native::new()
+        .handler(Add)
+        .handler(Square)
+        .handler(TrainConst);
+
+    // An unnamed parameter is still one node wherever its clones are used.
+    let w = Tensor::train_const(vec![], 0.0);
+    let loss =
+        (Tensor::from("a") + w.clone()).squared() + (Tensor::from("b") + w.clone()).squared();
+    let graph = loss.graph().clone();
+    let params: Vec<usize> = graph.parameters().collect();
+    assert_eq!(params.len(), 1);
+
+    let state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "a".to_owned() => tsor0(1.0),
+        "b".to_owned() => tsor0(2.0),
+    };
+    let delta = delta_of(&backend, &loss, &state, &feed, tsor0(1.0));
+    assert_eq!(delta.table[&params[0]], vec![tsor0(6.0)]);
+
+    // A named intermediate node can be used from several graphs too.
+    let h = (Tensor::from("a") + w).named("h");
+    let y = h.squared() + (Tensor::from("b") + h);
+    let graph = y.graph();
+    assert_eq!(graph.ops.len(), 5);
+    assert_eq!(graph.node("h"), Some(1));
+}
+
+#[test]
+fn name_collision() {
+    let a = Tensor::train_const(vec![], 0.0).named("w");
+    let b = Tensor::train_const(vec![], 1.0).named("w");
+    let mut graph = a.graph().clone();
+    assert_eq!(
+        graph.merge(b.graph().clone()),
+        Err(NameCollision {
+            name: "w".to_owned()
+        })
+    );
+    assert_eq!(graph.ops.len(), 1);
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| a + b));
+    assert!(result.is_err());
+}
+
+#[test]
+fn frozen_parameters() {
+    let backend = Native::new()
+        .handler(Add)
+        .handler(Sub)
+        .handler(Square)
+        .handler(TrainConst);
+
+    let frozen = Tensor::train_const(vec![], 1.0)
+        .named("encoder/weight")
+        .squared();
+    let trained = Tensor::train_const(vec![], 0.0).named("decoder/weight");
+    let loss = ((Tensor::from("x") + frozen) + trained - Tensor::from("y")).squared();
+    loss.freeze("encoder");
+
+    let graph = loss.graph().clone();
+    let mut state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor0(1.0),
+        "y".to_owned() => tsor0(5.0),
+    };
+    let output = Input::Internal(Internal {
+        node: graph.ops.len() - 1,
+        output: 0,
+    });
+    let backward = |state: &Vec<Vec<Tsor>>, graph: &Graph| {
+        let (_, tape) = backend
+            .forward(graph, state, &feed, output.clone())
+            .expect("unable to forward");
+        backend
+            .backward(graph, state, &tape, &feed, output.clone(), tsor0(-0.1))
+            .expect("unable to backward")
+    };
+
+    // Nothing in the frozen subgraph is visited.
+    let delta = backward(&state, &graph);
+    let encoder = graph.node("encoder/weight").unwrap();
+    let decoder = graph.node("decoder/weight").unwrap();
+    assert!(!delta.table.contains_key(&encoder));
+    assert!(!delta.table.contains_key(&(encoder + 1)));
+    assert!(delta.table.contains_key(&decoder));
+
+    backend
+        .train(&graph, &mut state, &delta)
+        .expect("unable to train");
+    assert_eq!(state[encoder], vec![tsor0(1.0)]);
+
+    // Frozen nodes aren't trained even by a delta that was built by hand.
+    let decoder_before = state[decoder].clone();
+    let by_hand = AccumulateTensors {
+        table: hashmap! {
+            encoder => vec![tsor0(5.0)],
+            decoder => vec![tsor0(1.0)],
+        },
+    };
+    backend
+        .train(&graph, &mut state, &by_hand)
+        .expect("unable to train");
+    assert_eq!(state[encoder], vec![tsor0(1.0)]);
+    assert_eq!(state[decoder], vec![&decoder_before[0] + 1.0]);
+
+    // Once unfrozen, the encoder is trained again.
+    loss.unfreeze("encoder");
+    let delta = backward(&state, &loss.graph());
+    assert!(delta.table.contains_key(&encoder));
+}
+
+fn delta_of(
+    backend: &Native,
+    tensor: &Tensor,
+    state: &Vec<Vec<Tsor>>,
+    feed: &HashMap<String, Tsor>,
+    output_delta: Tsor,
+) -> AccumulateTensors<Tsor> {
+    let ((), delta) = tensor
+        .delta(backend, state, feed, |_| ((), output_delta))
+        .expect("unable to compute delta");
+    delta
+}
+
+#[test]
+fn stop_and_custom_gradient() {
+    let backend = Native::new()
+        .handler(Add)
+        .handler(Sub)
+        .handler(Square)
+        .handler(TrainConst)
+        .gradient("straight_through", |_, _, delta| vec![delta]);
+    let feed = hashmap! {
+        "r".to_owned() => tsor0(1.0),
+    };
+
+    // The target doesn't recieve a delta, even though it depends on `w`.
+    let w = Tensor::train_const(vec![], 0.0).named("w");
+    let target = (w.clone() + Tensor::from("r")).stop_gradient();
+    let loss = (w - target).squared();
+    let state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let node = loss.graph().node("w").unwrap();
+    let delta = delta_of(&backend, &loss, &state, &feed, tsor0(1.0));
+    assert_eq!(delta.table[&node], vec![tsor0(-2.0)]);
+
+    // The gradient of the square is replaced with the identity.
+    let w = Tensor::train_const(vec![], 3.0).named("w");
+    let y = w.squared().custom_gradient("straight_through", &[w]);
+    let state = y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let output = y.eval(&backend, &state, &feed).expect("unable to eval");
+    assert_eq!(output, tsor0(9.0));
+    let node = y.graph().node("w").unwrap();
+    let delta = delta_of(&backend, &y, &state, &feed, tsor0(1.0));
+    assert_eq!(delta.table[&node], vec![tsor0(1.0)]);
+}
+
+#[test]
+fn input_gradients() {
+    let backend = Native::new()
+        .handler(Add)
+        .handler(Square)
+        .handler(TrainConst);
+
+    // The feed "x" is used twice, so its deltas are summed.
+    let w = Tensor::train_const(vec![], 1.0).named("w");
+    let loss = (Tensor::from("x") + w).squared() + Tensor::from("x");
+    let graph = loss.graph().clone();
+    let state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor0(2.0),
+        "unused".to_owned() => tsor0(0.0),
+    };
+    let (_, tape) = backend
+        .forward(&graph, &state, &feed, loss.input().clone())
+        .expect("unable to forward");
+    let (delta, input_deltas) = backend
+        .backward_inputs(
+            &graph,
+            &state,
+            &tape,
+            &feed,
+            loss.input().clone(),
+            tsor0(1.0),
+        )
+        .expect("unable to backward");
+
+    assert_eq!(input_deltas.len(), 1);
+    assert_eq!(input_deltas["x"], tsor0(7.0));
+    assert_eq!(delta.table[&graph.node("w").unwrap()], vec![tsor0(6.0)]);
+}
+
+#[test]
+fn micro_batches() {
+    let backend = Native::new().handlers(handlers::standard());
+    let learning_rate = 0.1;
+    // The mean squared error of a bias over a batch of the given size.
+    let mse = |batch_size: f64| {
+        let w = Tensor::train_const(vec![], 0.0);
+        (Tensor::from("x") - w)
+            .squared()
+            .sum()
+            .scale(1.0 / batch_size)
+    };
+    let seed = |t: Tsor| (t.sum(), tsor0(-learning_rate));
+
+    let batch = mse(4.0);
+    let mut batch_state = batch
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, 2.0, 3.0, 6.0]) };
+    batch
+        .gradient_descent(&backend, &mut batch_state, &feed, seed)
+        .expect("unable to train");
+
+    let micro_batch = mse(2.0);
+    let mut micro_state = micro_batch
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feeds = vec![
+        hashmap! { "x".to_owned() => tsor1(&[1.0, 2.0]) },
+        hashmap! { "x".to_owned() => tsor1(&[3.0, 6.0]) },
+    ];
+    let (losses, _) = train_micro_batches(&backend, &micro_batch, &mut micro_state, feeds, seed)
+        .expect("unable to train");
+
+    assert_eq!(losses, vec![2.5, 22.5]);
+    // The gradient of the mean is 2 * (w - mean(x)) = -6, so the bias moves by 0.6.
+    let bias = |state: &Vec<Vec<Tsor>>| *state.iter().flatten().next().unwrap().first().unwrap();
+    assert!((bias(&batch_state) - 0.6).abs() < 1e-6);
+    assert!((bias(&micro_state) - bias(&batch_state)).abs() < 1e-6);
+}
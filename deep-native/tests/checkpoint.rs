@@ -0,0 +1,85 @@
+use deep::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+
+/// Builds a stack of layers, checkpointing each layer if requested.
+fn model(layers: usize, checkpoint: bool) -> Tensor {
+    let mut h = Tensor::from("x");
+    for layer in 0..layers {
+        let w =
+            Tensor::train_const(vec![4], 0.5 + layer as f64 * 0.25).named(format!("w{}", layer));
+        let b = Tensor::train_const(vec![4], 0.1).named(format!("b{}", layer));
+        let next = (h.clone() * w + b).squared().scale(0.5).abs();
+        h = if checkpoint && layer > 0 {
+            next.checkpoint(&[h])
+        } else {
+            next
+        };
+    }
+    (h - Tensor::from("y")).squared().sum()
+}
+
+#[test]
+fn segment() {
+    let x = Tensor::from("x");
+    let h = x.squared().scale(2.0);
+    let y = (h.clone().abs() + h.clone())
+        .squared()
+        .checkpoint(std::slice::from_ref(&h));
+    let graph = y.graph();
+    let h = match h.input() {
+        Input::Internal(internal) => internal.node,
+        Input::Feed(_) => unreachable!(),
+    };
+    // Only the nodes between `h` and `y` are checkpointed.
+    assert_eq!(
+        graph.checkpointed.iter().cloned().collect::<Vec<_>>(),
+        vec![h + 1, h + 2]
+    );
+}
+
+#[test]
+fn recompute() {
+    let backend = Native::new().handlers(handlers::standard());
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, -2.0, 0.5, 3.0]),
+        "y".to_owned() => tsor1(&[0.0, 1.0, 2.0, 3.0]),
+    };
+    let run = |checkpoint: bool| {
+        let loss = model(4, checkpoint);
+        let graph = loss.graph().clone();
+        let state = loss
+            .gen_state(&backend, thread_rng())
+            .expect("unable to generate state");
+        let output = loss.input().clone();
+        let (value, tape) = backend
+            .forward(&graph, &state, &feed, output.clone())
+            .expect("unable to forward");
+        let retained = tape.memory_report(&backend).retained;
+        let (delta, input_deltas) = backend
+            .backward_inputs(&graph, &state, &tape, &feed, output, tsor0(1.0))
+            .expect("unable to backward");
+        let peak = tape.memory_report(&backend).peak;
+        assert!(peak >= retained + tape.recomputed_peak());
+        (value, retained, delta, input_deltas, tape.recomputed_peak())
+    };
+
+    let (value, retained, delta, input_deltas, recomputed) = run(false);
+    let (
+        checkpointed_value,
+        checkpointed_retained,
+        checkpointed_delta,
+        checkpointed_inputs,
+        checkpointed_recomputed,
+    ) = run(true);
+    assert_eq!(checkpointed_value, value);
+    assert_eq!(checkpointed_delta.table, delta.table);
+    assert_eq!(checkpointed_inputs, input_deltas);
+    assert!(checkpointed_retained < retained);
+
+    // Each layer is recomputed on its own and dropped before the layer below it, so at most
+    // the six values of one layer (including its parameters) are recomputed at once.
+    assert_eq!(recomputed, 0);
+    assert_eq!(checkpointed_recomputed, 6 * 4 * std::mem::size_of::<f32>());
+}
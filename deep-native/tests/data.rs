@@ -0,0 +1,125 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::data::*;
+use deep_native::*;
+use maplit::hashmap;
+use ndarray::Array;
+use rand::{rngs::StdRng, SeedableRng};
+
+/// Ten examples where "x" is `[i, i]` and "y" is `i`.
+fn dataset() -> TensorDataset {
+    let x = Array::from_shape_fn((10, 2), |(i, _)| i as f32);
+    let y = Array::from_shape_fn(10, |i| i as f32);
+    TensorDataset::new(hashmap! {
+        "x".to_owned() => x.into_shared().into_dyn(),
+        "y".to_owned() => y.into_shared().into_dyn(),
+    })
+}
+
+fn ys(loader: &mut DataLoader<TensorDataset>) -> Vec<Vec<f32>> {
+    loader
+        .iter()
+        .map(|batch| batch["y"].iter().cloned().collect())
+        .collect()
+}
+
+#[test]
+fn batches_in_order() {
+    let mut loader = DataLoader::new(dataset(), 4);
+    assert_eq!(loader.len(), 3);
+    let batches: Vec<Example> = loader.iter().collect();
+    assert_eq!(batches.len(), 3);
+    assert_eq!(batches[0]["x"].shape(), &[4, 2]);
+    assert_eq!(
+        batches[0]["x"].index_axis(ndarray::Axis(0), 3).into_owned(),
+        tsor1(&[3.0, 3.0]).into_owned()
+    );
+    assert_eq!(batches[2]["y"], tsor1(&[8.0, 9.0]));
+}
+
+#[test]
+fn last_batch() {
+    let mut dropped = DataLoader::new(dataset(), 4).last_batch(LastBatch::Drop);
+    assert_eq!(dropped.len(), 2);
+    assert_eq!(
+        ys(&mut dropped).concat(),
+        (0..8).map(|i| i as f32).collect::<Vec<_>>()
+    );
+
+    let mut padded = DataLoader::new(dataset(), 4).last_batch(LastBatch::Pad);
+    let batches: Vec<Example> = padded.iter().collect();
+    assert_eq!(batches[2]["y"], tsor1(&[8.0, 9.0, 0.0, 0.0]));
+    assert_eq!(batches[2]["x"].shape(), &[4, 2]);
+}
+
+#[test]
+fn seeded_shuffle_and_prefetch() {
+    let loader = || DataLoader::new(dataset(), 3).shuffle(StdRng::seed_from_u64(7));
+    let mut a = loader();
+    let mut b = loader().prefetch(2);
+
+    let first = ys(&mut a);
+    assert_eq!(first, ys(&mut b));
+    // Every example is still given exactly once.
+    let mut all = first.concat();
+    all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(all, (0..10).map(|i| i as f32).collect::<Vec<_>>());
+    assert_ne!(first.concat(), all);
+
+    // The order changes each epoch.
+    assert_ne!(ys(&mut a), first);
+}
+
+/// A dataset that panics when asked for one of its examples.
+struct Panicking {
+    at: usize,
+}
+
+impl Dataset for Panicking {
+    fn len(&self) -> usize {
+        10
+    }
+
+    fn get(&self, index: usize) -> Example {
+        if index == self.at {
+            panic!("unable to load example {}", index);
+        }
+        dataset().get(index)
+    }
+}
+
+#[test]
+fn prefetch_panics() {
+    let mut loader = DataLoader::new(Panicking { at: 7 }, 3).prefetch(1);
+    let mut batches = loader.iter();
+    assert!(batches.next().is_some());
+    assert!(batches.next().is_some());
+    // The panic on the prefetch thread reaches the loop instead of ending the epoch early.
+    let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| batches.next()))
+        .expect_err("the epoch ended early");
+    assert_eq!(
+        panic.downcast_ref::<String>().map(String::as_str),
+        Some("unable to load example 7")
+    );
+}
+
+#[test]
+fn train_from_loader() {
+    let backend = Native::new().handlers(handlers::standard());
+    // Learn the mean of "y" with a broadcast bias.
+    let loss = (Tensor::from("y") - Tensor::train_const(vec![], 0.0))
+        .squared()
+        .sum()
+        .scale(0.5);
+    let mut state = loss
+        .gen_state(&backend, rand::thread_rng())
+        .expect("unable to generate state");
+    let mut loader = DataLoader::new(dataset(), 5)
+        .shuffle(StdRng::seed_from_u64(0))
+        .prefetch(1);
+    let history = Trainer::new(&backend, loss, Sgd::new(0.02))
+        .epochs(50)
+        .fit(&mut state, &mut loader, None)
+        .expect("unable to fit");
+    assert!(history.epochs.last().unwrap().loss < history.epochs[0].loss);
+}
@@ -0,0 +1,89 @@
+use deep_backend_tools::*;
+use deep_native::*;
+use maplit::hashmap;
+
+fn delta(table: std::collections::HashMap<usize, Vec<Tsor>>) -> AccumulateTensors<Tsor> {
+    AccumulateTensors { table }
+}
+
+#[test]
+fn delta_arithmetic() {
+    let mut a = delta(hashmap! {
+        0 => vec![tsor1(&[3.0, 0.0])],
+        1 => vec![tsor0(4.0)],
+    });
+    let b = delta(hashmap! {
+        0 => vec![tsor1(&[1.0, 1.0])],
+        2 => vec![tsor0(-2.0)],
+    });
+    a.add(b).expect("unable to add deltas");
+    assert_eq!(a.table[&0], vec![tsor1(&[4.0, 1.0])]);
+    assert_eq!(a.table[&2], vec![tsor0(-2.0)]);
+
+    a.scale(0.5);
+    assert_eq!(a.table[&0], vec![tsor1(&[2.0, 0.5])]);
+    assert_eq!(a.table[&1], vec![tsor0(2.0)]);
+
+    // Adding a different number of tensors to a node is an error rather than a panic, and it
+    // leaves the delta as it was.
+    let mismatched = delta(hashmap! {
+        0 => vec![tsor1(&[1.0, 1.0])],
+        1 => vec![tsor0(1.0), tsor0(1.0)],
+        3 => vec![tsor0(1.0)],
+    });
+    assert!(a.add(mismatched).is_err());
+    assert_eq!(a.table.len(), 3);
+    assert_eq!(a.table[&0], vec![tsor1(&[2.0, 0.5])]);
+    assert_eq!(a.table[&1], vec![tsor0(2.0)]);
+    let backend = Native::new();
+    assert!(a.accumulate(&backend, 1, vec![]).is_err());
+
+    // Tensors of a different shape are only caught when the backend can tell their shapes.
+    let reshaped = delta(hashmap! { 0 => vec![tsor1(&[1.0, 1.0, 1.0])] });
+    match a.add_dense(&backend, reshaped) {
+        Err(Error::DeltaShapeMismatch {
+            node,
+            expected,
+            found,
+        }) => assert_eq!((node, expected, found), (0, vec![2], vec![3])),
+        _ => panic!("expected the shapes to mismatch"),
+    }
+    assert!(a.accumulate(&backend, 0, vec![tsor0(1.0)]).is_err());
+    assert_eq!(a.table[&0], vec![tsor1(&[2.0, 0.5])]);
+
+    // Deltas can still be collected with `Extend`.
+    a.extend(vec![(0, vec![tsor1(&[1.0, 1.0])]), (4, vec![tsor0(3.0)])]);
+    assert_eq!(a.table[&0], vec![tsor1(&[3.0, 1.5])]);
+    assert_eq!(a.table[&4], vec![tsor0(3.0)]);
+}
+
+#[test]
+fn delta_norms_and_clipping() {
+    let make = || {
+        delta(hashmap! {
+            0 => vec![tsor1(&[3.0, 4.0])],
+            1 => vec![tsor0(-12.0)],
+        })
+    };
+
+    let d = make();
+    let norms = d.norms();
+    assert_eq!(norms[&0], 5.0);
+    assert_eq!(norms[&1], 12.0);
+    assert_eq!(d.global_norm(), 13.0);
+
+    let mut d = make();
+    d.clip_by_value(-1.0, 3.5);
+    assert_eq!(d.table[&0], vec![tsor1(&[3.0, 3.5])]);
+    assert_eq!(d.table[&1], vec![tsor0(-1.0)]);
+
+    let mut d = make();
+    d.clip_by_norm(6.0);
+    assert_eq!(d.table[&0], vec![tsor1(&[3.0, 4.0])]);
+    assert_eq!(d.table[&1], vec![tsor0(-6.0)]);
+
+    let mut d = make();
+    assert_eq!(d.clip_by_global_norm(6.5), 13.0);
+    assert_eq!(d.table[&0], vec![tsor1(&[1.5, 2.0])]);
+    assert_eq!(d.table[&1], vec![tsor0(-6.0)]);
+}
@@ -0,0 +1,225 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::fusion::{Fusion, FusionPlan};
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+
+fn node(tensor: &Tensor) -> usize {
+    match tensor.input() {
+        Input::Internal(internal) => internal.node,
+        Input::Feed(name) => panic!("\"{}\" is a feed", name),
+    }
+}
+
+#[test]
+fn plan() {
+    let backend = Native::new().handlers(handlers::standard());
+    // A chain is fused into one group.
+    let loss = (Tensor::from("x") - Tensor::from("y")).squared().scale(0.5);
+    let plan = FusionPlan::new(
+        &backend,
+        &loss.graph(),
+        &[loss.input().clone()],
+        Fusion::Inference,
+    );
+    assert_eq!(plan.groups.len(), 1);
+    assert_eq!(plan.groups[0].nodes, vec![0, 1, 2]);
+    assert_eq!(plan.groups[0].output(), node(&loss));
+    assert_eq!(plan.groups[0].kept, vec![node(&loss)]);
+    assert_eq!(plan.groups[0].inputs.len(), 2);
+
+    // Training keeps the values that backprop reads, and only the shape of the square since
+    // scaling doesn't read its input.
+    let plan = FusionPlan::new(
+        &backend,
+        &loss.graph(),
+        &[loss.input().clone()],
+        Fusion::Training,
+    );
+    assert_eq!(plan.groups[0].kept, vec![0, 2]);
+    assert_eq!(plan.groups[0].shaped, vec![1]);
+
+    // A value used twice is the end of its own group, and reductions aren't fused.
+    let shared = (Tensor::from("x") * Tensor::from("y")).abs();
+    let out = (shared.squared() + shared.scale(2.0).sign()).sum();
+    let graph = out.graph();
+    let plan = FusionPlan::new(&backend, &graph, &[out.input().clone()], Fusion::Inference);
+    let groups: Vec<Vec<usize>> = plan.groups.iter().map(|g| g.nodes.clone()).collect();
+    assert_eq!(groups, vec![vec![0, 1], vec![2, 3, 4, 5]]);
+
+    assert!(
+        FusionPlan::new(&backend, &graph, &[out.input().clone()], Fusion::Off)
+            .groups
+            .is_empty()
+    );
+}
+
+#[test]
+fn fused_values() {
+    let unfused = Native::new().handlers(handlers::standard());
+    let fused = Native::new()
+        .handlers(handlers::standard())
+        .fusion(Fusion::Inference);
+
+    let x = Tensor::from("x");
+    let y = Tensor::from("y");
+    let shared = (x.clone() - y.clone()).abs();
+    let out = (shared.squared() + (shared.scale(-3.0) * y).sign()).sum();
+    let state = out
+        .gen_state(&unfused, thread_rng())
+        .expect("unable to generate state");
+
+    // Large enough to take several chunks, plus a feed that has to be broadcast.
+    let x: Vec<f32> = (0..1000).map(|n| (n as f32).sin()).collect();
+    let y: Vec<f32> = (0..1000).map(|n| (n as f32 * 0.3).cos()).collect();
+    for feed in [
+        hashmap! { "x".to_owned() => tsor1(&x), "y".to_owned() => tsor1(&y) },
+        hashmap! { "x".to_owned() => tsor2(&[[1.0, -2.0], [3.0, 0.5]]), "y".to_owned() => tsor1(&[0.5, 2.0]) },
+    ] {
+        let expected = out.eval(&unfused, &state, &feed).expect("unable to eval");
+        let output = out.eval(&fused, &state, &feed).expect("unable to eval");
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn fused_training() {
+    let unfused = Native::new().handlers(handlers::standard());
+    let training = Native::new()
+        .handlers(handlers::standard())
+        .fusion(Fusion::Training);
+    let inference = Native::new()
+        .handlers(handlers::standard())
+        .fusion(Fusion::Inference);
+
+    let w = Tensor::train_const(vec![3], 0.5).named("w");
+    let loss = ((Tensor::from("x") - w).squared().scale(0.5) * Tensor::from("y")).sum();
+    let graph = loss.graph().clone();
+    let state = loss
+        .gen_state(&unfused, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+        "y".to_owned() => tsor1(&[1.0, -1.0, 2.0]),
+    };
+    let output = loss.input().clone();
+    let delta = |backend: &Native| {
+        let (_, tape) = backend
+            .forward(&graph, &state, &feed, output.clone())
+            .expect("unable to forward");
+        backend.backward(&graph, &state, &tape, &feed, output.clone(), tsor0(1.0))
+    };
+
+    let expected = delta(&unfused).expect("unable to backward");
+    let fused = delta(&training).expect("unable to backward");
+    assert_eq!(fused.table, expected.table);
+
+    // Inference doesn't keep the values that backprop needs.
+    match delta(&inference) {
+        Err(Error::InternalNotComputed { .. }) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("backprop shouldn't pass through an inference group"),
+    }
+}
+
+#[test]
+fn large_training() {
+    let unfused = Native::new().handlers(handlers::standard());
+    let training = Native::new()
+        .handlers(handlers::standard())
+        .fusion(Fusion::Training);
+
+    let w = Tensor::train_const(vec![], 0.5).named("w");
+    let loss = ((Tensor::from("x") * w).abs().scale(2.0) + Tensor::from("x")).sum();
+    let graph = loss.graph().clone();
+    let state = loss
+        .gen_state(&unfused, thread_rng())
+        .expect("unable to generate state");
+    // Large enough to be split between threads with the parallel feature.
+    let x: Vec<f32> = (0..100_003).map(|n| (n as f32 * 0.1).sin()).collect();
+    let feed = hashmap! { "x".to_owned() => tsor1(&x) };
+    let output = loss.input().clone();
+    let delta = |backend: &Native| {
+        let (value, tape) = backend
+            .forward(&graph, &state, &feed, output.clone())
+            .expect("unable to forward");
+        let delta = backend
+            .backward(&graph, &state, &tape, &feed, output.clone(), tsor0(1.0))
+            .expect("unable to backward");
+        (value, delta.table)
+    };
+
+    // The pool has several threads even on a machine with one core.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+    pool.install(|| assert_eq!(delta(&training), delta(&unfused)));
+}
+
+/// Takes the negative of the absolute value.
+struct NegatedAbs;
+
+impl Handler for NegatedAbs {
+    fn op(&self) -> OpTy {
+        OpTy::Abs
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn rand_core::RngCore) -> Vec<Tsor> {
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Abs(a) = imop {
+            vec![a.mapv(|n| -n.abs()).into_shared()]
+        } else {
+            panic!("got {:?} when OpTy::Abs was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        _imop: ImOp<Native>,
+        _state: &[Tsor],
+        _output_delta: (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        unimplemented!()
+    }
+
+    fn tangent(
+        &self,
+        _imop: ImOp<Native>,
+        _state: &[Tsor],
+        _tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn custom_handlers_unfused() {
+    let fused = Native::new()
+        .handlers(handlers::standard())
+        .handler(NegatedAbs)
+        .fusion(Fusion::Inference);
+
+    let out = (Tensor::from("x") - Tensor::from("y")).abs().scale(2.0);
+    let graph = out.graph();
+    let state = out
+        .gen_state(&fused, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+        "y".to_owned() => tsor1(&[2.0, 2.0, 1.0]),
+    };
+
+    // The abs is left to its handler, which splits the chain into ops too small to fuse.
+    let plan = FusionPlan::new(&fused, &graph, &[out.input().clone()], Fusion::Inference);
+    assert!(plan.groups.is_empty());
+    assert_eq!(
+        out.eval(&fused, &state, &feed).expect("unable to eval"),
+        tsor1(&[-2.0, 0.0, -4.0])
+    );
+}
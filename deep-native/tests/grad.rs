@@ -0,0 +1,378 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+
+#[test]
+fn second_derivative() {
+    let backend = Native::new().handlers(handlers::standard());
+
+    // y = w^3, so dy/dw = 3w^2 and d2y/dw2 = 6w.
+    let w = Tensor::train_const(vec![], 2.0).named("w");
+    let y = w.clone() * w.clone() * w.clone();
+    let dy = y.grad(&w);
+    let d2y = dy.grad(&w);
+
+    let state = d2y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {};
+    let eval = |t: &Tensor| t.eval(&backend, &state, &feed).expect("unable to eval");
+    assert_eq!(eval(&y), tsor0(8.0));
+    assert_eq!(eval(&dy), tsor0(12.0));
+    assert_eq!(eval(&d2y), tsor0(12.0));
+}
+
+#[test]
+fn hessian_vector_product() {
+    let backend = Native::new().handlers(handlers::standard());
+    let x = Tensor::from("x");
+    let v = Tensor::from("v");
+
+    // f = sum(x^3), so the gradient is 3x^2 and the Hessian is diag(6x).
+    let f = (x.clone() * x.clone() * x.clone()).sum();
+    let g = f.grad(&x);
+    let hvp = (g.clone() * v).sum().grad(&x);
+    // Nothing depends on "v" through the gradient of `f`, so it is zero.
+    let unrelated = f.grad(&Tensor::from("v"));
+
+    let state = hvp
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+        "v".to_owned() => tsor1(&[1.0, 1.0, 0.0]),
+    };
+    let eval = |t: &Tensor| t.eval(&backend, &state, &feed).expect("unable to eval");
+    assert_eq!(eval(&g), tsor1(&[3.0, 12.0, 27.0]));
+    assert_eq!(eval(&hvp), tsor1(&[6.0, 12.0, 0.0]));
+    assert_eq!(eval(&unrelated), tsor1(&[0.0, 0.0, 0.0]));
+}
+
+#[test]
+fn jacobian_vector_product() {
+    let backend = Native::new().handlers(handlers::standard());
+    let x = Tensor::from("x");
+    let w = Tensor::train_const(vec![], 2.0).named("w");
+
+    // y = x^2 * w + x, so dy/dx = 2xw + 1 and dy/dw = x^2.
+    let y = x.squared() * w + x.clone();
+    let graph = y.graph().clone();
+    let node = graph.node("w").unwrap();
+    let state = y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+    };
+    let jvp = |input_tangents, state_tangents| {
+        Tape::new()
+            .solve_tangent(
+                &backend,
+                &graph,
+                &state,
+                &feed,
+                y.input().clone(),
+                &input_tangents,
+                &state_tangents,
+            )
+            .expect("unable to solve tangent")
+    };
+
+    let (value, tangent) = jvp(
+        hashmap! { "x".to_owned() => tsor1(&[1.0, 0.0, 2.0]) },
+        hashmap! {},
+    );
+    assert_eq!(value, tsor1(&[3.0, 10.0, 21.0]));
+    assert_eq!(tangent, tsor1(&[5.0, 0.0, 26.0]));
+
+    let (_, tangent) = jvp(hashmap! {}, hashmap! { node => vec![tsor0(1.0)] });
+    assert_eq!(tangent, tsor1(&[1.0, 4.0, 9.0]));
+}
+
+#[test]
+fn dense_jacobian() {
+    use deep_backend_tools::jacobian::*;
+    let backend = Native::new().handlers(handlers::standard());
+    let x = Tensor::from("x");
+    let w = Tensor::train_const(vec![], 2.0).named("w");
+
+    // y = x^2 * w + x, so dy/dx = diag(2xw + 1) and dy/dw = x^2.
+    let y = x.squared() * w + x.clone();
+    let graph = y.graph().clone();
+    let w = Input::Internal(Internal {
+        node: graph.node("w").unwrap(),
+        output: 0,
+    });
+    let x = Input::Feed("x".to_owned());
+    let state = y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+    };
+    let output = y.input().clone();
+
+    let expected = tsor2(&[[5.0, 0.0, 0.0], [0.0, 9.0, 0.0], [0.0, 0.0, 13.0]]);
+    let reverse = jacobian_reverse(&backend, &graph, &state, &feed, output.clone(), x.clone());
+    let forward = jacobian_forward(&backend, &graph, &state, &feed, output.clone(), x.clone());
+    assert_eq!(reverse.expect("unable to get jacobian"), expected);
+    assert_eq!(forward.expect("unable to get jacobian"), expected);
+
+    let dw = jacobian(&backend, &graph, &state, &feed, output, w).expect("unable to get jacobian");
+    assert_eq!(dw, tsor1(&[1.0, 4.0, 9.0]));
+}
+
+#[test]
+fn dense_hessian() {
+    use deep_backend_tools::jacobian::*;
+    let backend = Native::new().handlers(handlers::standard());
+    let x = Tensor::from("x");
+
+    // f = sum(x^3), so the Hessian is diag(6x).
+    let f = (x.clone() * x.clone() * x).sum();
+    let graph = f.graph().clone();
+    let state = f
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+    };
+    let output = f.input().clone();
+    let x = Input::Feed("x".to_owned());
+
+    let h = hessian(&backend, &graph, &state, &feed, output.clone(), x.clone())
+        .expect("unable to get hessian");
+    assert_eq!(
+        h,
+        tsor2(&[[6.0, 0.0, 0.0], [0.0, 12.0, 0.0], [0.0, 0.0, 18.0]])
+    );
+    let hvp = hessian_vector_product(
+        &backend,
+        &graph,
+        &state,
+        &feed,
+        output,
+        x,
+        tsor1(&[1.0, 1.0, 0.0]),
+    )
+    .expect("unable to get hessian vector product");
+    assert_eq!(hvp, tsor1(&[6.0, 12.0, 0.0]));
+}
+
+#[test]
+fn linear_gradients() {
+    use deep_backend_tools::jacobian::*;
+    let backend = Native::new().handlers(handlers::standard());
+
+    // The gradient of sum(ab) with respect to `a` is ones * b^T.
+    let a = Tensor::from("a");
+    let b = Tensor::train_const(vec![3, 2], 0.5).named("b");
+    let y = a.matmul(&b).sum();
+    let da = y.grad(&a);
+    let state = da
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "a".to_owned() => tsor2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]),
+    };
+    let output = da.eval(&backend, &state, &feed).expect("unable to eval");
+    assert_eq!(output, tsor2(&[[1.0; 3]; 2]));
+
+    // Backprop agrees with forward mode through convolutions and matrix products.
+    let kernel = Tensor::train_const(vec![2, 1, 2, 2], 0.25).named("kernel");
+    let weight = Tensor::train_const(vec![2, 3], 0.5).named("weight");
+    let features = Tensor::from("image").conv2d(&kernel).squared().sum();
+    let y = Tensor::from("m")
+        .matmul(&weight.transpose())
+        .squared()
+        .sum()
+        + features;
+    let graph = y.graph().clone();
+    let state = y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "image".to_owned() => tsor2(&[[1.0, -2.0, 3.0], [0.5, 1.0, -1.0], [2.0, 0.0, 1.0]])
+            .into_shape(vec![1, 1, 3, 3])
+            .unwrap(),
+        "m".to_owned() => tsor2(&[[1.0, 2.0, 3.0], [-1.0, 0.5, 2.0]]),
+    };
+    let wrt = [
+        Input::Feed("image".to_owned()),
+        Input::Feed("m".to_owned()),
+        Input::Internal(Internal {
+            node: graph.node("kernel").unwrap(),
+            output: 0,
+        }),
+        Input::Internal(Internal {
+            node: graph.node("weight").unwrap(),
+            output: 0,
+        }),
+    ];
+    for wrt in &wrt {
+        let output = y.input().clone();
+        let reverse =
+            jacobian_reverse(&backend, &graph, &state, &feed, output.clone(), wrt.clone())
+                .expect("unable to get jacobian");
+        let forward = jacobian_forward(&backend, &graph, &state, &feed, output, wrt.clone())
+            .expect("unable to get jacobian");
+        assert_eq!(reverse.shape(), forward.shape());
+        for (r, f) in reverse.iter().zip(forward.iter()) {
+            assert!((r - f).abs() < 1e-5, "{} != {}", r, f);
+        }
+    }
+}
+
+#[test]
+fn broadcast_gradients() {
+    let backend = Native::new().handlers(handlers::standard());
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+        "m".to_owned() => tsor2(&[[1.0, 2.0, 3.0], [-1.0, 0.5, 2.0]]),
+    };
+
+    // The gradient of a broadcast operand is summed over the axes it was broadcast along.
+    let x = Tensor::from("x");
+    let w = Tensor::train_const(vec![], 2.0).named("w");
+    let grads = [
+        ((x.clone() + w.clone()).sum().grad(&w), 3.0),
+        ((x.clone() - w.clone()).sum().grad(&w), -3.0),
+        ((x.clone() * w.clone()).sum().grad(&w), 6.0),
+    ];
+    for (grad, expected) in &grads {
+        let state = grad
+            .gen_state(&backend, thread_rng())
+            .expect("unable to generate state");
+        let output = grad.eval(&backend, &state, &feed).expect("unable to eval");
+        assert_eq!(output, tsor0(*expected));
+    }
+
+    // The symbolic gradients agree with backprop on the tape.
+    let scale = Tensor::train_const(vec![3], 0.5).named("scale");
+    let bias = Tensor::train_const(vec![1, 3], 0.25).named("bias");
+    let loss = ((Tensor::from("m") * scale.clone()) - bias.clone())
+        .squared()
+        .sum();
+    let grads = [loss.grad(&scale), loss.grad(&bias)];
+    let graph = loss.graph().clone();
+    let state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let ((), delta) = loss
+        .delta(&backend, &state, &feed, |output| {
+            ((), Tsor::ones(output.shape()))
+        })
+        .expect("unable to backprop");
+    for (grad, name) in grads.iter().zip(&["scale", "bias"]) {
+        let output = grad.eval(&backend, &state, &feed).expect("unable to eval");
+        let expected = &delta.table[&graph.node(name).unwrap()][0];
+        assert_eq!(&output, expected);
+    }
+}
+
+#[test]
+fn convolution_gradients() {
+    use deep_backend_tools::jacobian::*;
+    let backend = Native::new().handlers(handlers::standard());
+    let image = Tensor::from("image");
+    let kernel = Tensor::train_const(vec![2, 1, 2, 2], 0.25).named("kernel");
+    let y = image.conv2d(&kernel).squared().sum();
+    let (d_image, d_kernel) = (y.grad(&image), y.grad(&kernel));
+    // Second derivatives go through the ops that compute the deltas of a convolution.
+    let product = (d_image.clone() * Tensor::from("u")).sum();
+    let mixed = product.grad(&kernel);
+
+    let graph = mixed.graph().clone();
+    let state = mixed
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "image".to_owned() => tsor2(&[[1.0, -2.0, 3.0], [0.5, 1.0, -1.0], [2.0, 0.0, 1.0]])
+            .into_shape(vec![1, 1, 3, 3])
+            .unwrap(),
+        "u".to_owned() => tsor2(&[[0.5, 1.0, 0.0], [-1.0, 2.0, 1.0], [0.0, 0.5, -0.5]])
+            .into_shape(vec![1, 1, 3, 3])
+            .unwrap(),
+    };
+    let eval = |t: &Tensor| t.eval(&backend, &state, &feed).expect("unable to eval");
+    let image = Input::Feed("image".to_owned());
+    let kernel = Input::Internal(Internal {
+        node: graph.node("kernel").unwrap(),
+        output: 0,
+    });
+    let assert_close = |a: &Tsor, b: &Tsor| {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+        }
+    };
+
+    // The symbolic gradients agree with backprop on the tape.
+    for (grad, wrt) in [(&d_image, &image), (&d_kernel, &kernel)].iter() {
+        let expected = jacobian_reverse(
+            &backend,
+            &graph,
+            &state,
+            &feed,
+            y.input().clone(),
+            (*wrt).clone(),
+        )
+        .expect("unable to get jacobian");
+        assert_close(&eval(grad), &expected);
+    }
+
+    // Backprop and forward mode agree through the deltas of the convolution, in both inputs.
+    let output = product.input().clone();
+    for wrt in &[image, kernel.clone()] {
+        let reverse =
+            jacobian_reverse(&backend, &graph, &state, &feed, output.clone(), wrt.clone())
+                .expect("unable to get jacobian");
+        let forward =
+            jacobian_forward(&backend, &graph, &state, &feed, output.clone(), wrt.clone())
+                .expect("unable to get jacobian");
+        assert_close(&reverse, &forward);
+    }
+    let expected = jacobian_reverse(&backend, &graph, &state, &feed, output, kernel)
+        .expect("unable to get jacobian");
+    assert_close(&eval(&mixed), &expected);
+}
+
+#[test]
+fn summed_deltas() {
+    // The deltas that meet at a node are summed by the backend, so graphs without an add don't
+    // need its handler and backprop doesn't record forward adds.
+    let handlers = handlers::standard()
+        .into_iter()
+        .filter(|handler| handler.op() != OpTy::Add);
+    let backend = Native::new().handlers(handlers).profiling(true);
+
+    let w = Tensor::train_const(vec![3], 2.0).named("w");
+    let loss = (w.squared() * w.abs()).sum();
+    let graph = loss.graph().clone();
+    let state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let (_, tape) = backend
+        .forward(&graph, &state, &hashmap! {}, loss.input().clone())
+        .expect("unable to forward");
+    let delta = backend
+        .backward(
+            &graph,
+            &state,
+            &tape,
+            &hashmap! {},
+            loss.input().clone(),
+            tsor0(1.0),
+        )
+        .expect("unable to backward");
+
+    // The derivative of w^2 |w| is 2w |w| + w^2 sign(w).
+    assert_eq!(
+        delta.table[&graph.node("w").unwrap()],
+        vec![tsor1(&[12.0; 3])]
+    );
+    let profile = backend.profile().unwrap();
+    assert!(profile.events.iter().all(|event| event.ty != OpTy::Add));
+}
@@ -0,0 +1,222 @@
+use deep_native::data::*;
+use deep_native::io::*;
+use deep_native::*;
+
+#[test]
+fn csv_columns_and_coercion() {
+    let data = "id,name,score,passed\n\
+                1,\"Smith, J\",3.5,true\n\
+                2,Doe,,false\n";
+    let all = Csv::new()
+        .column_indices(vec![0, 2, 3])
+        .missing(-1.0)
+        .read(data.as_bytes())
+        .expect("unable to read");
+    assert_eq!(all, tsor2(&[[1.0, 3.5, 1.0], [2.0, -1.0, 0.0]]));
+
+    let selected = Csv::new()
+        .columns(vec!["passed", "id"])
+        .read(data.as_bytes())
+        .expect("unable to read");
+    assert_eq!(selected, tsor2(&[[1.0, 1.0], [0.0, 2.0]]));
+
+    let missing = Csv::new().columns(vec!["age"]).read(data.as_bytes());
+    assert!(matches!(missing, Err(Error::ColumnNotFound { .. })));
+    // The name column can't be coerced.
+    assert!(Csv::new().read(data.as_bytes()).is_err());
+
+    let no_header = Csv::new()
+        .header(false)
+        .delimiter(b';')
+        .read("1;2\n3;4\n".as_bytes())
+        .expect("unable to read");
+    assert_eq!(no_header, tsor2(&[[1.0, 2.0], [3.0, 4.0]]));
+}
+
+/// Encodes a `.npy` file.
+fn npy(descr: &str, fortran_order: bool, shape: &str, data: &[u8]) -> Vec<u8> {
+    let order = if fortran_order { "True" } else { "False" };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+        descr, order, shape
+    );
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+    let mut file = b"\x93NUMPY\x01\x00".to_vec();
+    file.extend(&(header.len() as u16).to_le_bytes());
+    file.extend(header.as_bytes());
+    file.extend(data);
+    file
+}
+
+fn f32s(elements: &[f32]) -> Vec<u8> {
+    elements
+        .iter()
+        .flat_map(|n| n.to_le_bytes().to_vec())
+        .collect()
+}
+
+#[test]
+fn npy_dtypes_and_order() {
+    let floats = npy(
+        "<f4",
+        false,
+        "(2, 3)",
+        &f32s(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+    );
+    assert_eq!(
+        read_npy(&floats[..]).expect("unable to read"),
+        tsor2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]])
+    );
+
+    // The same array stored column-major.
+    let fortran = npy(
+        "<f4",
+        true,
+        "(2, 3)",
+        &f32s(&[1.0, 4.0, 2.0, 5.0, 3.0, 6.0]),
+    );
+    assert_eq!(
+        read_npy(&fortran[..]).expect("unable to read"),
+        tsor2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]])
+    );
+
+    let bytes = npy("|u1", false, "(3,)", &[0, 7, 255]);
+    assert_eq!(
+        read_npy(&bytes[..]).expect("unable to read"),
+        tsor1(&[0.0, 7.0, 255.0])
+    );
+
+    let big: Vec<u8> = [-2i64, 9]
+        .iter()
+        .flat_map(|n| n.to_be_bytes().to_vec())
+        .collect();
+    let big = npy(">i8", false, "(2,)", &big);
+    assert_eq!(
+        read_npy(&big[..]).expect("unable to read"),
+        tsor1(&[-2.0, 9.0])
+    );
+
+    let scalar = npy("<f8", false, "()", &2.5f64.to_le_bytes());
+    assert_eq!(read_npy(&scalar[..]).expect("unable to read"), tsor0(2.5));
+}
+
+#[test]
+fn npy_malformed_headers() {
+    // An empty dtype, one that starts with a multi-byte character, and one without a type.
+    for descr in &["", "é4", "<"] {
+        let file = npy(descr, false, "(1,)", &f32s(&[1.0]));
+        assert!(read_npy(&file[..]).is_err(), "read dtype \"{}\"", descr);
+    }
+    let file = npy("<f4", false, "(2,)", &f32s(&[1.0]));
+    assert!(read_npy(&file[..]).is_err());
+}
+
+/// Encodes a zip archive, deflating the files that ask for it.
+fn zip(files: &[(&str, Vec<u8>, bool)]) -> Vec<u8> {
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, data, deflate) in files {
+        let (method, contents) = if *deflate {
+            (8u16, miniz_oxide::deflate::compress_to_vec(data, 6))
+        } else {
+            (0, data.clone())
+        };
+        let offset = archive.len() as u32;
+        let header = |signature: u32, central: bool| {
+            let mut h = signature.to_le_bytes().to_vec();
+            if central {
+                h.extend(&20u16.to_le_bytes());
+            }
+            h.extend(&20u16.to_le_bytes());
+            h.extend(&0u16.to_le_bytes());
+            h.extend(&method.to_le_bytes());
+            h.extend(&[0; 8]);
+            h.extend(&(contents.len() as u32).to_le_bytes());
+            h.extend(&(data.len() as u32).to_le_bytes());
+            h.extend(&(name.len() as u16).to_le_bytes());
+            h.extend(&0u16.to_le_bytes());
+            if central {
+                h.extend(&[0; 10]);
+                h.extend(&offset.to_le_bytes());
+            }
+            h.extend(name.as_bytes());
+            h
+        };
+        let local = header(0x0403_4b50, false);
+        directory.extend(header(0x0201_4b50, true));
+        archive.extend(local);
+        archive.extend(&contents);
+    }
+    let offset = archive.len() as u32;
+    archive.extend(&directory);
+    archive.extend(&0x0605_4b50u32.to_le_bytes());
+    archive.extend(&[0; 4]);
+    archive.extend(&(files.len() as u16).to_le_bytes());
+    archive.extend(&(files.len() as u16).to_le_bytes());
+    archive.extend(&(directory.len() as u32).to_le_bytes());
+    archive.extend(&offset.to_le_bytes());
+    archive.extend(&0u16.to_le_bytes());
+    archive
+}
+
+#[test]
+fn npz_to_dataset() {
+    let archive = zip(&[
+        (
+            "x.npy",
+            npy(
+                "<f4",
+                false,
+                "(3, 2)",
+                &f32s(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]),
+            ),
+            false,
+        ),
+        (
+            "y.npy",
+            npy("<f4", false, "(3,)", &f32s(&[7.0, 8.0, 9.0])),
+            true,
+        ),
+    ]);
+    let arrays = read_npz(&archive[..]).expect("unable to read");
+    assert_eq!(arrays["y"], tsor1(&[7.0, 8.0, 9.0]));
+
+    let mut loader = DataLoader::new(TensorDataset::new(arrays), 2);
+    let batches: Vec<Example> = loader.iter().collect();
+    assert_eq!(batches[0]["x"], tsor2(&[[0.0, 1.0], [2.0, 3.0]]));
+    assert_eq!(batches[1]["y"], tsor1(&[9.0]));
+}
+
+/// Encodes an IDX file of unsigned bytes.
+fn idx(shape: &[u32], data: &[u8]) -> Vec<u8> {
+    let mut file = vec![0, 0, 0x08, shape.len() as u8];
+    for dim in shape {
+        file.extend(&dim.to_be_bytes());
+    }
+    file.extend(data);
+    file
+}
+
+#[test]
+fn idx_and_gzip() {
+    let images = idx(&[2, 2, 2], &[0, 1, 2, 3, 4, 5, 6, 255]);
+    let expected = tsor3(&[[[0.0, 1.0], [2.0, 3.0]], [[4.0, 5.0], [6.0, 255.0]]]);
+    assert_eq!(read_idx(&images[..]).expect("unable to read"), expected);
+
+    // Gzip with a file name, as MNIST is distributed. The trailer isn't checked.
+    let mut gzipped = vec![0x1f, 0x8b, 8, 8, 0, 0, 0, 0, 0, 3];
+    gzipped.extend(b"images-idx3-ubyte\0");
+    gzipped.extend(miniz_oxide::deflate::compress_to_vec(&images, 6));
+    gzipped.extend(&[0; 8]);
+    assert_eq!(read_idx(&gzipped[..]).expect("unable to read"), expected);
+
+    let labels = idx(&[3], &[7, 2, 1]);
+    let labels = read_idx(&labels[..]).expect("unable to read");
+    assert_eq!(labels, tsor1(&[7.0, 2.0, 1.0]));
+
+    let truncated = idx(&[2, 2], &[0, 1, 2]);
+    assert!(read_idx(&truncated[..]).is_err());
+}
@@ -0,0 +1,189 @@
+use deep_native::kernels::{self, PARALLEL_THRESHOLD};
+use deep_native::*;
+use ndarray::{Array, IxDyn};
+
+/// A tensor large enough for the parallel kernels to be used.
+fn large(offset: f32) -> Tsor {
+    let len = PARALLEL_THRESHOLD + 7;
+    Array::from_shape_fn(IxDyn(&[len / 7, 7]), |index| {
+        (index[0] * 7 + index[1]) as f32 * 1e-3 - offset
+    })
+    .into_shared()
+}
+
+#[test]
+fn elementwise() {
+    let a = large(1.0);
+    let b = large(3.0);
+    assert_eq!(kernels::map(&a, f32::abs), a.mapv(f32::abs).into_shared());
+    assert_eq!(kernels::zip_map(&a, &b, |a, b| a * b), &a * &b);
+
+    // Small tensors, broadcasting and non-contiguous tensors.
+    let small = tsor2(&[[1.0, -2.0], [3.0, -4.0]]);
+    assert_eq!(
+        kernels::zip_map(&small, &tsor1(&[1.0, 2.0]), |a, b| a - b),
+        tsor2(&[[0.0, -4.0], [2.0, -6.0]])
+    );
+    let transposed = a.clone().reversed_axes();
+    assert_eq!(
+        kernels::zip_map(&transposed, &transposed, |a, b| a + b),
+        &transposed * 2.0
+    );
+    assert_eq!(
+        kernels::map(&transposed, |n| n * n),
+        transposed.mapv(|n| n * n).into_shared()
+    );
+}
+
+#[test]
+fn reduction() {
+    let a = large(0.0);
+    let expected: f64 = a.iter().map(|&n| n as f64).sum();
+    assert!((kernels::sum(&a) as f64 - expected).abs() / expected < 1e-4);
+    assert!((kernels::sum(&a.clone().reversed_axes()) as f64 - expected).abs() / expected < 1e-4);
+    assert_eq!(kernels::sum(&tsor1(&[1.0, 2.0, 3.0])), 6.0);
+}
+
+/// A tensor of the given shape filled with arbitrary values.
+#[cfg(any(feature = "blas", feature = "parallel"))]
+fn filled(shape: &[usize], seed: usize) -> Tsor {
+    let mut n = 0;
+    Array::from_shape_fn(IxDyn(shape), |_| {
+        n += 1;
+        ((n * 7919 + seed * 104_729) % 201) as f32 / 100.0 - 1.0
+    })
+    .into_shared()
+}
+
+fn assert_close(a: &Tsor, b: &Tsor) {
+    assert_eq!(a.shape(), b.shape());
+    for (a, b) in a.iter().zip(b.iter()) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+}
+
+/// Views a tensor with a fixed number of dimensions for the reference kernels.
+#[cfg(any(feature = "blas", feature = "parallel"))]
+fn fixed<D: ndarray::Dimension>(tensor: &Tsor) -> ndarray::ArrayView<'_, f32, D> {
+    tensor.view().into_dimensionality().unwrap()
+}
+
+#[test]
+fn matmul() {
+    let a = tsor2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+    let b = tsor2(&[[7.0, 8.0], [9.0, 10.0], [11.0, 12.0]]);
+    let expected = tsor2(&[[58.0, 64.0], [139.0, 154.0]]);
+    assert_close(&kernels::matmul(&a, &b), &expected);
+    assert_close(
+        &kernels::matmul(&tsor2(&[[1.0, 2.0]]), &tsor2(&[[3.0], [4.0]])),
+        &tsor2(&[[11.0]]),
+    );
+
+    // Transposed matrices aren't contiguous.
+    let a_t = a.clone().reversed_axes().into_owned().into_shared();
+    assert_close(&kernels::matmul(&a_t.reversed_axes(), &b), &expected);
+}
+
+/// The optimized kernels agree with the reference loops on larger matrices.
+#[cfg(feature = "blas")]
+#[test]
+fn blas_matmul() {
+    use deep_native::kernels::reference;
+    let a = filled(&[5, 3], 0);
+    let b = filled(&[3, 4], 1);
+    let expected = reference::matmul(fixed(&a), fixed(&b))
+        .into_dyn()
+        .into_shared();
+    assert_close(&kernels::matmul(&a, &b), &expected);
+    let a_t = a.clone().reversed_axes().into_owned().into_shared();
+    assert_close(&kernels::matmul(&a_t.reversed_axes(), &b), &expected);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_matmul() {
+    use deep_native::kernels::{parallel, serial};
+    // Large enough to be split between threads, with a number of rows that doesn't divide evenly.
+    let a = filled(&[67, 64], 5);
+    let b = filled(&[64, 31], 6);
+    assert!(a.len() * b.shape()[1] >= PARALLEL_THRESHOLD);
+    let expected = serial::matmul(fixed(&a), fixed(&b));
+    // The pool has several threads even on a machine with one core.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+    pool.install(|| {
+        assert_eq!(parallel::matmul(fixed(&a), fixed(&b)), expected);
+        assert_eq!(
+            kernels::matmul(&a, &b),
+            expected.clone().into_dyn().into_shared()
+        );
+    });
+}
+
+/// An image with shape `[1, 1, 3, 3]`.
+fn image() -> Tsor {
+    tsor2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]])
+        .into_shape(vec![1, 1, 3, 3])
+        .unwrap()
+}
+
+/// A tensor with shape `[1, 1, 2, 2]`.
+fn square(values: [[f32; 2]; 2]) -> Tsor {
+    tsor2(&values).into_shape(vec![1, 1, 2, 2]).unwrap()
+}
+
+#[test]
+fn convolution() {
+    let kernel = square([[1.0, 2.0], [3.0, 4.0]]);
+    assert_close(
+        &kernels::conv2d(&image(), &kernel),
+        &square([[37.0, 47.0], [67.0, 77.0]]),
+    );
+
+    // Each pixel of the image gets the delta of every output it was multiplied into.
+    let delta = square([[1.0, 1.0], [1.0, 1.0]]);
+    let input_delta = tsor2(&[[1.0, 3.0, 2.0], [4.0, 10.0, 6.0], [3.0, 7.0, 4.0]])
+        .into_shape(vec![1, 1, 3, 3])
+        .unwrap();
+    assert_close(&kernels::conv2d_input_delta(&delta, &kernel), &input_delta);
+    assert_close(
+        &kernels::conv2d_kernel_delta(&image(), &delta),
+        &square([[12.0, 16.0], [24.0, 28.0]]),
+    );
+
+    // A single pixel kernel just scales the image.
+    let scale = tsor0(2.0).into_shape(vec![1, 1, 1, 1]).unwrap();
+    assert_close(
+        &kernels::conv2d(&image(), &scale),
+        &(&image() * 2.0).into_shared(),
+    );
+}
+
+/// The optimized kernels agree with the reference loops with several images, channels and
+/// filters.
+#[cfg(feature = "blas")]
+#[test]
+fn blas_convolution() {
+    use deep_native::kernels::reference;
+    let input = filled(&[2, 3, 6, 5], 2);
+    let kernel = filled(&[4, 3, 3, 2], 3);
+    let output = reference::conv2d(fixed(&input), fixed(&kernel))
+        .into_dyn()
+        .into_shared();
+    assert_eq!(output.shape(), &[2, 4, 4, 4]);
+    assert_close(&kernels::conv2d(&input, &kernel), &output);
+
+    let delta = filled(output.shape(), 4);
+    let input_delta = reference::conv2d_input_delta(fixed(&delta), fixed(&kernel));
+    let kernel_delta = reference::conv2d_kernel_delta(fixed(&input), fixed(&delta));
+    assert_close(
+        &kernels::conv2d_input_delta(&delta, &kernel),
+        &input_delta.into_dyn().into_shared(),
+    );
+    assert_close(
+        &kernels::conv2d_kernel_delta(&input, &delta),
+        &kernel_delta.into_dyn().into_shared(),
+    );
+}
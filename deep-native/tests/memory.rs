@@ -0,0 +1,289 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+use std::collections::HashMap;
+
+/// The bytes used by a vector of `n` elements.
+fn vector(n: usize) -> usize {
+    n * std::mem::size_of::<f32>()
+}
+
+/// Trains `loss` for one step like `Tensor::gradient_descent`, giving back the loss and the
+/// memory used by its tape.
+fn train(
+    backend: &Native,
+    loss: &Tensor,
+    state: &mut Vec<Vec<Tsor>>,
+    feed: &HashMap<String, Tsor>,
+) -> (Tsor, MemoryReport) {
+    let graph = loss.graph().clone();
+    let (value, tape) = backend
+        .forward(&graph, state, feed, loss.input().clone())
+        .expect("unable to forward");
+    let delta = backend
+        .backward(
+            &graph,
+            state,
+            &tape,
+            feed,
+            loss.input().clone(),
+            tsor0(-0.1),
+        )
+        .expect("unable to backward");
+    backend
+        .train(&graph, state, &delta)
+        .expect("unable to train");
+    (value, tape.memory_report(backend))
+}
+
+#[test]
+fn inference() {
+    let all = Native::new().handlers(handlers::standard());
+    let inference = Native::new()
+        .handlers(handlers::standard())
+        .retention(Retention::Inference);
+
+    let mut y = Tensor::from("x");
+    for _ in 0..5 {
+        y = y.squared().scale(0.5);
+    }
+    let z = y.scale(2.0);
+    let state = y
+        .gen_state(&all, thread_rng())
+        .expect("unable to generate state");
+    let x: Vec<f32> = (0..1000).map(|n| n as f32 / 1000.0).collect();
+    let feed = hashmap! { "x".to_owned() => tsor1(&x) };
+
+    let graph = y.graph();
+    let (expected, all_tape) = all
+        .forward(&graph, &state, &feed, y.input().clone())
+        .expect("unable to forward");
+    assert_eq!(
+        all_tape.memory_report(&all),
+        MemoryReport::new(10 * vector(1000))
+    );
+
+    // Only an op's input and output are alive at once.
+    let (output, tape) = inference
+        .forward(&graph, &state, &feed, y.input().clone())
+        .expect("unable to forward");
+    assert_eq!(output, expected);
+    assert_eq!(
+        tape.memory_report(&inference),
+        MemoryReport {
+            peak: 2 * vector(1000),
+            retained: vector(1000),
+        }
+    );
+
+    // Each tape has its own report, even when the backend is shared.
+    let (_, tape) = all
+        .forward(&graph, &state, &feed, z.input().clone())
+        .expect("unable to forward");
+    assert_eq!(
+        tape.memory_report(&all),
+        MemoryReport::new(11 * vector(1000))
+    );
+    assert_eq!(
+        all_tape.memory_report(&all),
+        MemoryReport::new(10 * vector(1000))
+    );
+}
+
+#[test]
+fn training() {
+    let all = Native::new().handlers(handlers::standard());
+    let training = Native::new()
+        .handlers(handlers::standard())
+        .retention(Retention::Training);
+
+    // Backprop never reads the values behind the stopped gradient.
+    let w = Tensor::train_const(vec![100], 0.0).named("w");
+    let target = Tensor::from("x").squared().scale(3.0).stop_gradient();
+    let loss = (w - target).squared().sum();
+    let x: Vec<f32> = (0..100).map(|n| n as f32).collect();
+    let feed = hashmap! { "x".to_owned() => tsor1(&x) };
+
+    let mut all_state = loss
+        .gen_state(&all, thread_rng())
+        .expect("unable to generate state");
+    let mut training_state = all_state.clone();
+    let (expected, all_report) = train(&all, &loss, &mut all_state, &feed);
+    let (value, training_report) = train(&training, &loss, &mut training_state, &feed);
+    assert_eq!(value, expected);
+    assert_eq!(training_state, all_state);
+
+    // Only the input of the square is kept, since the subtraction and the sum only need the
+    // shapes of their inputs, along with the loss itself.
+    assert_eq!(
+        training_report.retained,
+        all_report.retained - 5 * vector(100)
+    );
+    assert_eq!(training_report.retained, vector(100) + vector(1));
+    // The peak includes the deltas from backprop.
+    assert_eq!(all_report.peak, all_report.retained + vector(100));
+    assert!(training_report.peak < all_report.peak);
+}
+
+#[test]
+fn mlp() {
+    let all = Native::new().handlers(handlers::standard());
+    let training = Native::new()
+        .handlers(handlers::standard())
+        .retention(Retention::Training);
+
+    let w1 = Tensor::train_const(vec![8, 16], 0.1).named("w1");
+    let b1 = Tensor::train_const(vec![16], -0.2).named("b1");
+    let w2 = Tensor::train_const(vec![16, 1], 0.3).named("w2");
+    let hidden = (Tensor::from("x").matmul(&w1) + b1).abs();
+    let loss = (hidden.matmul(&w2) - Tensor::from("y")).squared().sum();
+    let x: Vec<f32> = (0..32).map(|n| n as f32 / 32.0 - 0.5).collect();
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&x).into_shape(vec![4, 8]).unwrap(),
+        "y".to_owned() => tsor2(&[[1.0], [0.0], [-1.0], [2.0]]),
+    };
+
+    let mut all_state = loss
+        .gen_state(&all, thread_rng())
+        .expect("unable to generate state");
+    let mut training_state = all_state.clone();
+    let (expected, all_report) = train(&all, &loss, &mut all_state, &feed);
+    let (value, training_report) = train(&training, &loss, &mut training_state, &feed);
+    assert_eq!(value, expected);
+    assert_eq!(training_state, all_state);
+
+    // Adding the bias, subtracting the target and summing only need the shapes of their inputs,
+    // so the first product, the bias, the second product and the squared error are dropped.
+    assert_eq!(
+        training_report.retained,
+        all_report.retained - vector(4 * 16 + 16 + 4 + 4)
+    );
+}
+
+#[test]
+fn training_inputs() {
+    let backend = |retention| {
+        Native::new()
+            .handlers(handlers::standard())
+            .retention(retention)
+    };
+    let w = Tensor::train_const(vec![3], 2.0).named("w");
+    let loss = (Tensor::from("x").squared().squared() + w).sum();
+    let graph = loss.graph().clone();
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]) };
+    let backward_inputs = |backend: &Native| {
+        let state = loss
+            .gen_state(backend, thread_rng())
+            .expect("unable to generate state");
+        let (_, tape) = backend
+            .forward(&graph, &state, &feed, loss.input().clone())
+            .expect("unable to forward");
+        backend
+            .backward_inputs(
+                &graph,
+                &state,
+                &tape,
+                &feed,
+                loss.input().clone(),
+                tsor0(1.0),
+            )
+            .map(|(_, inputs)| inputs)
+    };
+
+    // Only the path to the feed reads the value of the first square.
+    let expected = backward_inputs(&backend(Retention::All)).expect("unable to backward");
+    assert_eq!(expected["x"], tsor1(&[4.0, 32.0, 108.0]));
+    match backward_inputs(&backend(Retention::Training)) {
+        Err(Error::InternalNotComputed { .. }) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("training retention shouldn't keep the feed paths"),
+    }
+    assert_eq!(
+        backward_inputs(&backend(Retention::TrainingInputs)).expect("unable to backward"),
+        expected
+    );
+}
+
+/// Adds, but propogates each delta scaled by the other input, so it reads the values of both.
+struct ScaledAdd;
+
+impl Handler for ScaledAdd {
+    fn op(&self) -> OpTy {
+        OpTy::Add
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn rand_core::RngCore) -> Vec<Tsor> {
+        vec![]
+    }
+
+    fn forward(&self, imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        if let ImOp::Add(a, b) = imop {
+            vec![a + b]
+        } else {
+            panic!("got {:?} when OpTy::Add was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn backward(
+        &self,
+        imop: ImOp<Native>,
+        _state: &[Tsor],
+        (_, output_delta): (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        if let ImOp::Add(a, b) = imop {
+            (
+                ImOp::Add(
+                    (&output_delta * &b).into_shared(),
+                    (&output_delta * &a).into_shared(),
+                ),
+                vec![],
+            )
+        } else {
+            panic!("got {:?} when OpTy::Add was expected", OpTy::from(&imop));
+        }
+    }
+
+    fn tangent(
+        &self,
+        _imop: ImOp<Native>,
+        _state: &[Tsor],
+        _tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn custom_handler_reads() {
+    let backend = |retention| {
+        Native::new()
+            .handlers(handlers::standard())
+            .handler(ScaledAdd)
+            .retention(retention)
+    };
+    let all = backend(Retention::All);
+    let training = backend(Retention::Training);
+
+    // The standard add would only need the shape of the square, but this one reads its value.
+    let w = Tensor::train_const(vec![3], 1.0).named("w");
+    let loss = (w + Tensor::from("x").squared()).sum();
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]) };
+    let seed = |t: Tsor| (t.sum(), tsor0(-1.0));
+
+    let mut all_state = loss
+        .gen_state(&all, thread_rng())
+        .expect("unable to generate state");
+    let mut training_state = all_state.clone();
+    loss.gradient_descent(&all, &mut all_state, &feed, seed)
+        .expect("unable to train");
+    loss.gradient_descent(&training, &mut training_state, &feed, seed)
+        .expect("unable to train");
+    assert_eq!(training_state, all_state);
+    assert_eq!(
+        all_state.iter().flatten().next().unwrap(),
+        &tsor1(&[0.0, -3.0, -8.0])
+    );
+}
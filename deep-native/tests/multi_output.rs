@@ -0,0 +1,147 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+
+/// Builds two losses that share a trunk, along with the trunk itself.
+fn heads() -> (Tensor, Tensor, Tensor) {
+    let w = Tensor::train_const(vec![3], 0.5).named("w");
+    let h = Tensor::from("x") * w;
+    let a = (h.clone() - Tensor::from("a")).squared().sum();
+    let b = (h.scale(2.0) - Tensor::from("b")).squared().sum();
+    (a, b, h)
+}
+
+fn feed() -> <Native as Backend>::Inputs {
+    hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, -1.0]),
+        "a".to_owned() => tsor1(&[0.0, 1.0, 1.0]),
+        "b".to_owned() => tsor1(&[2.0, 0.0, -1.0]),
+    }
+}
+
+#[test]
+fn eval_many() {
+    let backend = Native::new().handlers(handlers::standard());
+    let inference = Native::new()
+        .handlers(handlers::standard())
+        .retention(Retention::Inference);
+    let (a, b, h) = heads();
+    let state = a
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = feed();
+
+    let tensors = [a.clone(), b.clone(), h.clone()];
+    let expected: Vec<Tsor> = tensors
+        .iter()
+        .map(|t| t.eval(&backend, &state, &feed).expect("unable to eval"))
+        .collect();
+    let outputs = Tensor::eval_many(&tensors, &backend, &state, &feed).expect("unable to eval");
+    assert_eq!(outputs, expected);
+    let outputs = Tensor::eval_many(&tensors, &inference, &state, &feed).expect("unable to eval");
+    assert_eq!(outputs, expected);
+}
+
+#[test]
+fn eval_many_prediction() {
+    let backend = Native::new().handlers(handlers::standard());
+    let w = Tensor::train_const(vec![3], 0.5).named("w");
+    let pred = Tensor::from("x") * w;
+    let loss = (Tensor::from("y") - pred.clone()).squared().sum();
+    let state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, -1.0]),
+        "y".to_owned() => tsor1(&[0.0, 1.0, 1.0]),
+    };
+
+    // The prediction is part of the graph of the loss.
+    let outputs = Tensor::eval_many(&[loss.clone(), pred.clone()], &backend, &state, &feed)
+        .expect("unable to eval");
+    assert_eq!(outputs[0], tsor0(2.5));
+    assert_eq!(outputs[1], tsor1(&[0.5, 1.0, -0.5]));
+}
+
+#[test]
+fn delta_many() {
+    let backend = Native::new().handlers(handlers::standard());
+    let (a, b, _) = heads();
+    let state = a
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = feed();
+
+    let ones = |v: Tsor| {
+        let scalar: f32 = v.iter().sum();
+        (scalar, tsor0(1.0))
+    };
+    let (_, mut expected) = a
+        .delta(&backend, &state, &feed, ones)
+        .expect("unable to get delta");
+    let (_, b_delta) = b
+        .delta(&backend, &state, &feed, ones)
+        .expect("unable to get delta");
+    expected.add(b_delta).expect("unable to add deltas");
+
+    let tensors = [a.clone(), b.clone()];
+    let (losses, delta) = Tensor::delta_many(&tensors, &backend, &state, &feed, |values| {
+        (
+            values.clone(),
+            values.iter().map(|_| Some(tsor0(1.0))).collect(),
+        )
+    })
+    .expect("unable to get delta");
+    assert_eq!(losses.len(), 2);
+    assert_eq!(delta.table, expected.table);
+
+    // The deltas of both losses are summed at the shared trunk, so backprop only passes
+    // through it once.
+    let profiling = Native::new().handlers(handlers::standard()).profiling(true);
+    Tensor::delta_many(&tensors, &profiling, &state, &feed, |values| {
+        ((), values.iter().map(|_| Some(tsor0(1.0))).collect())
+    })
+    .expect("unable to get delta");
+    let profile = profiling.take_profile().expect("profiling is enabled");
+    let mul = profile
+        .by_op()
+        .into_iter()
+        .find(|op| op.ty == OpTy::Mul)
+        .unwrap();
+    assert_eq!(mul.backward.calls, 1);
+
+    // An output without a delta only gets evaluated.
+    let (_, only_a) = a
+        .delta(&backend, &state, &feed, ones)
+        .expect("unable to get delta");
+    let (_, delta) = Tensor::delta_many(&tensors, &backend, &state, &feed, |_| {
+        ((), vec![Some(tsor0(1.0)), None])
+    })
+    .expect("unable to get delta");
+    assert_eq!(delta.table, only_a.table);
+}
+
+#[test]
+fn gradient_descent_many() {
+    let backend = Native::new().handlers(handlers::standard());
+    let (a, b, _) = heads();
+    let mut state = a
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = feed();
+
+    let tensors = [a, b];
+    let mut losses = vec![];
+    for _ in 0..20 {
+        let (loss, _) =
+            Tensor::gradient_descent_many(&tensors, &backend, &mut state, &feed, |values| {
+                let loss: f32 = values.iter().map(|v| v.iter().sum::<f32>()).sum();
+                (loss, vec![Some(tsor0(-0.01)), Some(tsor0(-0.01))])
+            })
+            .expect("unable to train");
+        losses.push(loss);
+    }
+    assert!(losses.last().unwrap() < &losses[0]);
+}
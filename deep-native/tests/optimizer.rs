@@ -0,0 +1,168 @@
+use deep::*;
+use deep_backend_tools::schedule::*;
+use deep_backend_tools::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::{thread_rng, Rng};
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+#[test]
+fn schedules() {
+    let step = StepDecay::new(1.0, 0.5, 2);
+    let rates: Vec<f32> = (0..5).map(|s| step.learning_rate(s)).collect();
+    assert_eq!(rates, vec![1.0, 1.0, 0.5, 0.5, 0.25]);
+
+    let exponential = Exponential {
+        initial: 1.0,
+        gamma: 0.5,
+    };
+    assert_eq!(exponential.learning_rate(3), 0.125);
+
+    // Periods of 2 and then 4 steps.
+    let cosine = CosineAnnealing::new(1.0, 0.0, 2).period_mult(2);
+    assert!(close(cosine.learning_rate(0), 1.0));
+    assert!(close(cosine.learning_rate(1), 0.5));
+    assert!(close(cosine.learning_rate(2), 1.0));
+    assert!(close(cosine.learning_rate(4), 0.5));
+    assert!(close(cosine.learning_rate(6), 1.0));
+
+    let warmup = LinearWarmup {
+        warmup: 4,
+        schedule: step,
+    };
+    assert!(close(warmup.learning_rate(0), 0.25));
+    assert!(close(warmup.learning_rate(3), 1.0));
+    assert!(close(warmup.learning_rate(6), 0.5));
+
+    let one_cycle = OneCycle::new(1.0, 10);
+    assert!(close(one_cycle.learning_rate(0), 0.04));
+    assert!(close(one_cycle.learning_rate(3), 1.0));
+    assert!(one_cycle.learning_rate(10) < 1e-5);
+}
+
+#[test]
+fn degenerate_schedules() {
+    // A zero step size or period is treated as one.
+    let step = StepDecay::new(1.0, 0.5, 0);
+    assert_eq!(step.learning_rate(2), 0.25);
+    let cosine = CosineAnnealing::new(1.0, 0.0, 0).period_mult(0);
+    assert!(close(cosine.learning_rate(3), 1.0));
+
+    // Huge step counts decay all the way rather than wrapping around.
+    assert_eq!(step.learning_rate(usize::MAX), 0.0);
+    let exponential = Exponential {
+        initial: 1.0,
+        gamma: 0.5,
+    };
+    assert_eq!(exponential.learning_rate(1 << 40), 0.0);
+    let growing = CosineAnnealing::new(1.0, 0.0, 2).period_mult(1 << 20);
+    assert!(growing.learning_rate(usize::MAX).is_finite());
+}
+
+#[test]
+fn reduce_on_plateau() {
+    let mut schedule = ReduceOnPlateau::new(1.0, 0.5, 1);
+    for (step, &metric) in [3.0, 2.0, 2.0, 2.5].iter().enumerate() {
+        schedule.observe(step, metric);
+    }
+    assert_eq!(schedule.learning_rate(4), 0.5);
+
+    // The schedule can be checkpointed and restored.
+    let checkpoint = schedule.clone();
+    schedule.observe(4, 2.0);
+    schedule.observe(5, 2.0);
+    assert_eq!(schedule.learning_rate(6), 0.25);
+    assert_eq!(checkpoint.learning_rate(6), 0.5);
+}
+
+#[test]
+fn sgd_with_schedule() {
+    let backend = Native::new().handlers(handlers::standard());
+    let y = Tensor::from("x") + Tensor::train_const(vec![], 0.0);
+    let loss = (y - Tensor::from("y")).squared();
+    let mut state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+
+    let mut optimizer = Sgd::new(LinearWarmup {
+        warmup: 10,
+        schedule: Exponential {
+            initial: 0.05,
+            gamma: 0.999,
+        },
+    });
+    let mut loss_value = f32::NAN;
+    for _ in 0..500 {
+        let x: f32 = thread_rng().gen();
+        let feed = hashmap! {
+            "x".to_owned() => tsor0(x),
+            "y".to_owned() => tsor0(x + 5.0),
+        };
+        let (value, _) = optimizer
+            .minimize(&backend, &loss, &mut state, &feed, |t| {
+                (t.sum(), tsor0(1.0))
+            })
+            .expect("unable to train");
+        loss_value = value;
+    }
+
+    assert_eq!(optimizer.step, 500);
+    assert!(close(optimizer.learning_rate(), 0.05 * 0.999f32.powi(490)));
+    assert!(loss_value < 0.01);
+}
+
+#[test]
+fn weight_decay() {
+    let backend = Native::new().handlers(handlers::standard());
+    let weight = Tensor::train_const(vec![], 2.0).named("weight");
+    let bias = Tensor::train_const(vec![], 2.0).named("bias");
+    let norm = (Tensor::train_const(vec![], 2.0).named("scale")
+        * Tensor::train_const(vec![], 2.0).named("shift"))
+    .scoped("norm");
+    // The loss doesn't depend on the parameters, so only decay changes them.
+    let loss = (weight * Tensor::from("x") + bias + norm).scale(0.0);
+    let graph = loss.graph().clone();
+    let (weight, bias) = (graph.node("weight").unwrap(), graph.node("bias").unwrap());
+    let norm: Vec<usize> = graph.scope_nodes("norm").collect();
+    assert_eq!(norm.len(), 2);
+    let mut state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+
+    let mut optimizer = Sgd::new(0.1)
+        .weight_decay(0.5)
+        .no_decay(vec!["bias", "norm"]);
+    let feed = hashmap! { "x".to_owned() => tsor0(1.0) };
+    optimizer
+        .minimize(&backend, &loss, &mut state, &feed, |_| ((), tsor0(1.0)))
+        .expect("unable to train");
+    assert_eq!(state[weight], vec![tsor0(1.9)]);
+    assert_eq!(state[bias], vec![tsor0(2.0)]);
+    for node in norm {
+        assert_eq!(state[node], vec![tsor0(2.0)]);
+    }
+}
+
+#[test]
+fn penalties() {
+    let backend = Native::new().handlers(handlers::standard());
+    let weight = Tensor::train_const(vec![2], -3.0).named("layer/weight");
+    let bias = Tensor::train_const(vec![], 1.0).named("layer/bias");
+    let y = weight.clone() * Tensor::from("x") + bias;
+    let not_bias = |name: Option<&str>| !name.is_some_and(|name| name.ends_with("bias"));
+    let l2 = y.l2_penalty(0.5, not_bias);
+    let l1 = y.l1_penalty(2.0, |_| true);
+    let l1_grad = l1.grad(&weight);
+
+    let state = l1_grad
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, 1.0]) };
+    let eval = |t: &Tensor| t.eval(&backend, &state, &feed).expect("unable to eval");
+    assert_eq!(eval(&l2), tsor0(9.0));
+    assert_eq!(eval(&l1), tsor0(14.0));
+    assert_eq!(eval(&l1_grad), tsor1(&[-2.0, -2.0]));
+}
@@ -0,0 +1,140 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+
+/// A graph with many independent branches that join at the end.
+fn wide() -> Tensor {
+    let x = Tensor::from("x");
+    (0..16)
+        .map(|i| {
+            let w = Tensor::train_const(vec![3], i as f64).named(format!("w{}", i));
+            (x.clone() * w).squared().scale(0.5) - x.clone()
+        })
+        .fold(Tensor::from("x").scale(0.0), |total, branch| total + branch)
+        .sum()
+}
+
+#[test]
+fn parallel_matches_serial() {
+    let serial = Native::new().handlers(handlers::standard());
+    let parallel = Native::new().handlers(handlers::standard()).threads(4);
+    let loss = wide();
+    let state = loss
+        .gen_state(&serial, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, -2.0, 0.5]) };
+
+    let expected = loss.eval(&serial, &state, &feed).expect("unable to eval");
+    for _ in 0..10 {
+        let output = loss.eval(&parallel, &state, &feed).expect("unable to eval");
+        assert_eq!(output, expected);
+    }
+
+    // The tape from the parallel executor can be used for backprop as usual.
+    let delta = |backend: &Native| {
+        let ((), delta) = loss
+            .delta(backend, &state, &feed, |_| ((), tsor0(1.0)))
+            .expect("unable to compute delta");
+        delta
+    };
+    let (a, b) = (delta(&serial), delta(&parallel));
+    assert_eq!(a.table.len(), b.table.len());
+    for (node, tensors) in &a.table {
+        assert_eq!(&b.table[node], tensors);
+    }
+}
+
+#[test]
+fn parallel_reuses_threads() {
+    let parallel = Native::new()
+        .handlers(handlers::standard())
+        .threads(4)
+        .profiling(true);
+    let loss = wide();
+    let state = loss
+        .gen_state(&parallel, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, -2.0, 0.5]) };
+    for _ in 0..10 {
+        loss.eval(&parallel, &state, &feed).expect("unable to eval");
+    }
+
+    // Every call runs on the same four threads rather than starting new ones.
+    let profile = parallel.take_profile().expect("profiling is enabled");
+    let threads = profile
+        .events
+        .iter()
+        .map(|event| event.thread)
+        .max()
+        .unwrap()
+        + 1;
+    assert!(threads <= 4, "nodes ran on {} threads", threads);
+}
+
+#[test]
+fn parallel_errors() {
+    let parallel = Native::new().handlers(handlers::standard()).threads(4);
+    let loss = wide();
+    let state = loss
+        .gen_state(&parallel, thread_rng())
+        .expect("unable to generate state");
+    let result = loss.eval(&parallel, &state, &hashmap! {});
+    assert!(matches!(result, Err(Error::InputNotProvided { .. })));
+}
+
+/// Panics instead of taking the absolute value.
+struct PanickingAbs;
+
+impl Handler for PanickingAbs {
+    fn op(&self) -> OpTy {
+        OpTy::Abs
+    }
+
+    fn generate_state(&self, _op: &Op, _rng: &mut dyn rand_core::RngCore) -> Vec<Tsor> {
+        vec![]
+    }
+
+    fn forward(&self, _imop: ImOp<Native>, _state: &[Tsor]) -> Vec<Tsor> {
+        panic!("abs exploded");
+    }
+
+    fn backward(
+        &self,
+        _imop: ImOp<Native>,
+        _state: &[Tsor],
+        _output_delta: (usize, Tsor),
+    ) -> (ImOp<Native>, Vec<Tsor>) {
+        unreachable!()
+    }
+
+    fn tangent(
+        &self,
+        _imop: ImOp<Native>,
+        _state: &[Tsor],
+        _tangents: ImOp<Native>,
+        _state_tangents: &[Tsor],
+    ) -> Vec<Tsor> {
+        unreachable!()
+    }
+}
+
+#[test]
+fn parallel_panics() {
+    let mut handlers = handlers::standard();
+    handlers.push(Box::new(PanickingAbs));
+    let parallel = Native::new().handlers(handlers).threads(4);
+    // The other branches depend on nothing that panics, so threads are waiting on the panicking
+    // branch only to join them at the end.
+    let loss = wide() + Tensor::from("x").abs().sum();
+    let state = loss
+        .gen_state(&parallel, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, -2.0, 0.5]) };
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        loss.eval(&parallel, &state, &feed)
+    }));
+    let payload = result.expect_err("the panic wasn't resumed");
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"abs exploded"));
+}
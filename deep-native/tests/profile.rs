@@ -0,0 +1,130 @@
+use deep::*;
+use deep_native::fusion::Fusion;
+use deep_native::profile::*;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+
+#[test]
+fn events() {
+    let backend = Native::new().handlers(handlers::standard()).profiling(true);
+    let w = Tensor::train_const(vec![3, 2], 0.5).named("w");
+    let loss = (Tensor::from("x").matmul(&w) - Tensor::from("y"))
+        .squared()
+        .sum();
+    let mut state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]),
+        "y".to_owned() => tsor2(&[[1.0, 0.0], [0.0, 1.0]]),
+    };
+    loss.gradient_descent(&backend, &mut state, &feed, |_| ((), tsor0(-0.01)))
+        .expect("unable to train");
+
+    let profile = backend.take_profile().expect("profiling is enabled");
+    let matmul: Vec<&Event> = profile
+        .events
+        .iter()
+        .filter(|event| event.ty == OpTy::MatMul)
+        .collect();
+    assert_eq!(matmul.len(), 2);
+    assert_eq!(matmul[0].pass, Pass::Forward);
+    assert_eq!(matmul[0].inputs, vec![vec![2, 3], vec![3, 2]]);
+    assert_eq!(matmul[0].outputs, vec![vec![2, 2]]);
+    assert_eq!(matmul[0].allocated_bytes, 4 * std::mem::size_of::<f32>());
+    assert_eq!(matmul[1].pass, Pass::Backward);
+    assert_eq!(matmul[1].inputs, vec![vec![2, 3], vec![3, 2], vec![2, 2]]);
+    assert_eq!(matmul[1].outputs, vec![vec![2, 3], vec![3, 2]]);
+
+    // The delta of the minuend is the delta of the output, so only the negated delta of the
+    // subtrahend is allocated.
+    let sub = profile
+        .events
+        .iter()
+        .find(|event| event.ty == OpTy::Sub && event.pass == Pass::Backward)
+        .unwrap();
+    assert_eq!(sub.outputs, vec![vec![2, 2], vec![2, 2]]);
+    assert_eq!(sub.allocated_bytes, 4 * std::mem::size_of::<f32>());
+
+    let stats = profile.by_op();
+    let sum = stats.iter().find(|op| op.ty == OpTy::Sum).unwrap();
+    assert_eq!((sum.forward.calls, sum.backward.calls), (1, 1));
+    // Every forward call of the graph is recorded: TrainConst, MatMul, Sub, Square and Sum.
+    let forward: usize = stats.iter().map(|op| op.forward.calls).sum();
+    assert_eq!(forward, 5);
+    assert!(profile
+        .summary()
+        .lines()
+        .any(|line| line.starts_with("MatMul")));
+
+    let trace: serde_json::Value =
+        serde_json::from_str(&profile.chrome_trace()).expect("the trace isn't valid JSON");
+    let trace_events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(trace_events.len(), profile.events.len());
+    for (trace_event, event) in trace_events.iter().zip(&profile.events) {
+        assert_eq!(trace_event["name"], format!("{:?}", event.ty));
+        assert_eq!(trace_event["ph"], "X");
+        assert_eq!(trace_event["tid"], event.thread);
+        assert_eq!(
+            trace_event["args"]["allocated_bytes"],
+            event.allocated_bytes
+        );
+        let outputs: Vec<Vec<usize>> =
+            serde_json::from_value(trace_event["args"]["outputs"].clone()).unwrap();
+        assert_eq!(outputs, event.outputs);
+    }
+    let cat = |event: &serde_json::Value| event["cat"].as_str().unwrap().to_owned();
+    assert_eq!(cat(&trace_events[0]), "forward");
+    assert_eq!(cat(trace_events.last().unwrap()), "backward");
+
+    // Taking the profile starts a new one.
+    assert!(backend.profile().unwrap().events.is_empty());
+}
+
+#[test]
+fn fused() {
+    let backend = Native::new()
+        .handlers(handlers::standard())
+        .fusion(Fusion::Inference)
+        .profiling(true);
+    let y = (Tensor::from("x") - Tensor::from("y")).squared().scale(0.5);
+    let state = y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, 2.0, 3.0]),
+        "y".to_owned() => tsor1(&[0.0, 1.0, 2.0]),
+    };
+    y.eval(&backend, &state, &feed).expect("unable to eval");
+
+    // The group is recorded as one call under its output, which is the only value it keeps.
+    let profile = backend.take_profile().expect("profiling is enabled");
+    assert_eq!(profile.events.len(), 1);
+    let event = &profile.events[0];
+    assert_eq!((event.ty, event.pass), (OpTy::Scale, Pass::Fused));
+    assert_eq!(event.inputs, vec![vec![3], vec![3]]);
+    assert_eq!(event.outputs, vec![vec![3]]);
+    assert_eq!(event.allocated_bytes, 3 * std::mem::size_of::<f32>());
+
+    let stats = profile.by_op();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].fused.calls, 1);
+    assert_eq!(stats[0].time(), event.duration);
+    assert!(profile
+        .summary()
+        .lines()
+        .any(|line| line.starts_with("Scale")));
+}
+
+#[test]
+fn disabled() {
+    let backend = Native::new().handlers(handlers::standard());
+    let y = Tensor::from("x").squared();
+    let state = y
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! { "x".to_owned() => tsor1(&[1.0, 2.0]) };
+    y.eval(&backend, &state, &feed).expect("unable to eval");
+    assert!(backend.profile().is_none());
+}
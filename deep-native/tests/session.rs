@@ -0,0 +1,86 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::handlers::Add;
+use deep_native::*;
+use maplit::hashmap;
+use rand::thread_rng;
+
+#[test]
+fn run() {
+    let backend = Native::new().handlers(handlers::standard());
+    let w = Tensor::train_const(vec![2], 0.5).named("w");
+    let hidden = (Tensor::from("x") * w).abs();
+    let loss = (hidden.clone() - Tensor::from("y")).squared().sum();
+    let graph = loss.graph().clone();
+    let state = loss
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+
+    let session = backend
+        .session(&graph, &[loss.input().clone(), hidden.input().clone()])
+        .expect("unable to compile");
+    let mut feeds = session.plan().feeds().to_vec();
+    feeds.sort();
+    assert_eq!(feeds, vec!["x", "y"]);
+
+    for (x, y) in &[([1.0, -2.0], [0.0, 1.0]), ([3.0, 4.0], [1.0, 1.0])] {
+        let feed = hashmap! {
+            "x".to_owned() => tsor1(x),
+            "y".to_owned() => tsor1(y),
+        };
+        let outputs = session.run(&state, &feed).expect("unable to run");
+        assert_eq!(
+            outputs,
+            vec![
+                loss.eval(&backend, &state, &feed).expect("unable to eval"),
+                hidden
+                    .eval(&backend, &state, &feed)
+                    .expect("unable to eval"),
+            ]
+        );
+    }
+
+    match session.run(&state, &hashmap! { "x".to_owned() => tsor1(&[1.0, 2.0]) }) {
+        Err(Error::InputNotProvided { name }) => assert_eq!(name, "y"),
+        _ => panic!("expected the feed to be missing"),
+    }
+}
+
+#[test]
+fn missing_handler() {
+    let backend = Native::new().handler(Add);
+    let y = (Tensor::from("x") + Tensor::from("x")).squared();
+    let graph = y.graph().clone();
+    match backend.session(&graph, &[y.input().clone()]) {
+        Err(Error::OpHasNoHandler { ty }) => assert_eq!(ty, OpTy::Square),
+        _ => panic!("expected the handler to be missing"),
+    }
+}
+
+#[test]
+fn input_order() {
+    let backend = Native::new().handlers(handlers::standard());
+    let x = Tensor::from("x");
+    let y = Tensor::from("y");
+    // Inputs that repeat, come from feeds and nodes in either order, and an op with a list of
+    // inputs.
+    let h = (x.clone() - y.clone()).squared();
+    let out = ((y - h.clone()) * h.clone() + (h.clone() - x.clone()))
+        .custom_gradient("g", &[h, x])
+        .sum();
+    let graph = out.graph().clone();
+    let state = out
+        .gen_state(&backend, thread_rng())
+        .expect("unable to generate state");
+    let feed = hashmap! {
+        "x".to_owned() => tsor1(&[1.0, -2.0, 3.0]),
+        "y".to_owned() => tsor1(&[0.5, 2.0, -1.0]),
+    };
+    let session = backend
+        .session(&graph, &[out.input().clone()])
+        .expect("unable to compile");
+    assert_eq!(
+        session.run(&state, &feed).expect("unable to run"),
+        vec![out.eval(&backend, &state, &feed).expect("unable to eval")]
+    );
+}
@@ -0,0 +1,159 @@
+use deep::*;
+use deep_backend_tools::*;
+use deep_native::*;
+use maplit::hashmap;
+use std::collections::HashMap;
+
+/// Batches of `y = x + 5`.
+fn batches(xs: &[f32]) -> Vec<HashMap<String, Tsor>> {
+    xs.iter()
+        .map(|&x| {
+            hashmap! {
+                "x".to_owned() => tsor0(x),
+                "y".to_owned() => tsor0(x + 5.0),
+            }
+        })
+        .collect()
+}
+
+fn loss() -> Tensor {
+    let y = Tensor::from("x") + Tensor::train_const(vec![], 0.0).named("bias");
+    (y - Tensor::from("y")).squared()
+}
+
+#[derive(Default)]
+struct Counter {
+    batches: usize,
+    epochs: usize,
+    stop_after: Option<usize>,
+}
+
+impl Callback<Native> for &mut Counter {
+    fn on_batch_end(&mut self, _metrics: &BatchMetrics, _state: &Vec<Vec<Tsor>>) -> Flow {
+        self.batches += 1;
+        Flow::Continue
+    }
+
+    fn on_epoch_end(&mut self, metrics: &EpochMetrics, _state: &Vec<Vec<Tsor>>) -> Flow {
+        self.epochs += 1;
+        if Some(metrics.epoch + 1) == self.stop_after {
+            Flow::Stop
+        } else {
+            Flow::Continue
+        }
+    }
+}
+
+#[test]
+fn fit_with_validation() {
+    let backend = Native::new().handlers(handlers::standard());
+    let loss = loss();
+    let mut state = loss
+        .gen_state(&backend, rand::thread_rng())
+        .expect("unable to generate state");
+
+    let mut counter = Counter::default();
+    let mut trainer = Trainer::new(&backend, loss.clone(), Sgd::new(0.1))
+        .epochs(20)
+        .callback(&mut counter);
+    let history = trainer
+        .fit(
+            &mut state,
+            &mut batches(&[0.0, 0.5, 1.0]),
+            Some(&mut batches(&[0.25, 0.75])),
+        )
+        .expect("unable to fit");
+    drop(trainer);
+
+    assert_eq!(history.epochs.len(), 20);
+    assert!(!history.stopped_early);
+    assert_eq!(counter.batches, 60);
+    assert_eq!(counter.epochs, 20);
+    let last = history.epochs.last().unwrap();
+    assert!(last.validation_loss.unwrap() < 1e-3);
+    assert!(last.loss < history.epochs[0].loss);
+}
+
+#[test]
+fn early_stopping_keeps_best() {
+    let backend = Native::new().handlers(handlers::standard());
+    let loss = loss();
+    let graph = loss.graph().clone();
+    let bias = graph.node("bias").unwrap();
+    let mut state = loss
+        .gen_state(&backend, rand::thread_rng())
+        .expect("unable to generate state");
+
+    // The learning rate is so high that every step overshoots further, so the first epoch is
+    // the best one.
+    let mut trainer = Trainer::new(&backend, loss.clone(), Sgd::new(1.5))
+        .epochs(20)
+        .early_stopping(2)
+        .keep_best();
+    let history = trainer
+        .fit(&mut state, &mut batches(&[1.0]), None)
+        .expect("unable to fit");
+
+    assert!(history.stopped_early);
+    assert_eq!(history.best_epoch, Some(0));
+    assert_eq!(history.epochs.len(), 3);
+    // The first step takes the bias from 0 to 15.
+    assert_eq!(state[bias], vec![tsor0(15.0)]);
+}
+
+#[test]
+fn callback_stops_training() {
+    let backend = Native::new().handlers(handlers::standard());
+    let loss = loss();
+    let mut state = loss
+        .gen_state(&backend, rand::thread_rng())
+        .expect("unable to generate state");
+
+    let mut counter = Counter {
+        stop_after: Some(3),
+        ..Counter::default()
+    };
+    let history = Trainer::new(&backend, loss.clone(), Sgd::new(0.1))
+        .epochs(10)
+        .callback(&mut counter)
+        .fit(&mut state, &mut batches(&[0.0, 1.0]), None)
+        .expect("unable to fit");
+    assert!(history.stopped_early);
+    assert_eq!(history.epochs.len(), 3);
+    assert_eq!(counter.batches, 6);
+}
+
+#[test]
+fn metric_logging() {
+    let backend = Native::new().handlers(handlers::standard());
+    let loss = loss();
+    let bias = loss.graph().node("bias").unwrap();
+    let mut state = loss
+        .gen_state(&backend, rand::thread_rng())
+        .expect("unable to generate state");
+
+    let mut logger = MetricLogger::new(vec![])
+        .metric("bias", |_, state: &Vec<Vec<Tsor>>| state[bias][0].sum())
+        .metric("double_loss", |metrics, _| metrics.loss * 2.0);
+    let mut trainer = Trainer::new(&backend, loss, Sgd::new(0.1))
+        .epochs(3)
+        .callback(&mut logger);
+    let history = trainer
+        .fit(&mut state, &mut batches(&[0.0, 1.0]), None)
+        .expect("unable to fit");
+    drop(trainer);
+
+    let log = String::from_utf8(logger.finish().expect("unable to log")).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "epoch,loss,validation_loss,bias,double_loss");
+    for (line, metrics) in lines[1..].iter().zip(&history.epochs) {
+        let row: Vec<&str> = line.split(',').collect();
+        assert_eq!(row[0], metrics.epoch.to_string());
+        assert_eq!(row[1].parse::<f32>().unwrap(), metrics.loss);
+        assert_eq!(row[2], "");
+        assert_eq!(row[4].parse::<f32>().unwrap(), metrics.loss * 2.0);
+    }
+    let last: Vec<&str> = lines[3].split(',').collect();
+    assert_eq!(last[3].parse::<f32>().unwrap(), state[bias][0].sum());
+}
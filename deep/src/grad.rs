@@ -0,0 +1,176 @@
+use crate::{Graph, Input, Internal, Op};
+use std::collections::HashMap;
+
+impl Graph {
+    /// Appends ops that compute the gradient of the sum of `output` with respect to each of `wrt`.
+    ///
+    /// Returns the gradient of each of `wrt`, or `None` if `output` doesn't depend on it.
+    /// The gradients are built from ordinary ops, so they can be differentiated again to get
+    /// higher-order derivatives.
+    ///
+    /// Panics if an `Op::CustomGradient` contributes to `output`, since its gradient only exists
+    /// in the backend.
+    pub fn gradients(&mut self, output: Input, wrt: &[Input]) -> Vec<Option<Input>> {
+        let output_delta = self.append_input(Op::OnesLike(output.clone()));
+        self.gradients_with(output, output_delta, wrt)
+    }
+
+    /// This is the same as `gradients`, but the delta of `output` is given by `output_delta`
+    /// rather than being all ones, which gives vector-Jacobian products.
+    pub fn gradients_with(
+        &mut self,
+        output: Input,
+        output_delta: Input,
+        wrt: &[Input],
+    ) -> Vec<Option<Input>> {
+        let mut order = vec![];
+        let mut visited = vec![false; self.ops.len()];
+        self.visit(&output, &mut visited, &mut order);
+
+        let mut deltas = Deltas::default();
+        deltas.add(self, output, output_delta);
+        // Every node is visited after all of the nodes that use it.
+        for node in order.into_iter().rev() {
+            if let Some(delta) = deltas.nodes.get(&node).cloned() {
+                for (input, input_delta) in self.input_deltas(node, delta) {
+                    deltas.add(self, input, input_delta);
+                }
+            }
+        }
+
+        wrt.iter()
+            .map(|input| match input {
+                Input::Feed(name) => deltas.feeds.get(name).cloned(),
+                Input::Internal(internal) => deltas.nodes.get(&internal.node).cloned(),
+            })
+            .collect()
+    }
+
+    /// Appends an op and returns its output.
+    pub(crate) fn append_input(&mut self, op: Op) -> Input {
+        Input::Internal(Internal {
+            node: self.append(op),
+            output: 0,
+        })
+    }
+
+    /// Appends an op that sums the delta of a binary op's output over the axes that `operand`
+    /// was broadcast along, which gives the delta of `operand`.
+    fn unbroadcast(&mut self, delta: Input, operand: &Input) -> Input {
+        self.append_input(Op::Unbroadcast(delta, operand.clone()))
+    }
+
+    /// Adds the nodes that `input` depends on to `order` after their own dependencies.
+    fn visit(&self, input: &Input, visited: &mut [bool], order: &mut Vec<usize>) {
+        if let Input::Internal(internal) = input {
+            if !visited[internal.node] {
+                visited[internal.node] = true;
+                for input in self.ops[internal.node].delta_inputs() {
+                    self.visit(input, visited, order);
+                }
+                order.push(internal.node);
+            }
+        }
+    }
+
+    /// Appends the ops that turn the delta of a node into the deltas of its inputs.
+    fn input_deltas(&mut self, node: usize, delta: Input) -> Vec<(Input, Input)> {
+        match self.ops[node].clone() {
+            Op::Add(a, b) => {
+                let da = self.unbroadcast(delta.clone(), &a);
+                let db = self.unbroadcast(delta, &b);
+                vec![(a, da), (b, db)]
+            }
+            Op::Sub(a, b) => {
+                let negated = self.append_input(Op::Scale(delta.clone(), -1.0));
+                let da = self.unbroadcast(delta, &a);
+                let db = self.unbroadcast(negated, &b);
+                vec![(a, da), (b, db)]
+            }
+            Op::Square(a) => {
+                let doubled = self.append_input(Op::Scale(a.clone(), 2.0));
+                vec![(a, self.append_input(Op::Mul(delta, doubled)))]
+            }
+            Op::Mul(a, b) => {
+                let da = self.append_input(Op::Mul(delta.clone(), b.clone()));
+                let db = self.append_input(Op::Mul(delta, a.clone()));
+                let (da, db) = (self.unbroadcast(da, &a), self.unbroadcast(db, &b));
+                vec![(a, da), (b, db)]
+            }
+            Op::Scale(a, scale) => vec![(a, self.append_input(Op::Scale(delta, scale)))],
+            Op::Sum(a) => {
+                let ones = self.append_input(Op::OnesLike(a.clone()));
+                vec![(a, self.append_input(Op::Mul(ones, delta)))]
+            }
+            Op::Abs(a) => {
+                let sign = self.append_input(Op::Sign(a.clone()));
+                vec![(a, self.append_input(Op::Mul(delta, sign)))]
+            }
+            Op::MatMul(a, b) => {
+                let b_t = self.append_input(Op::Transpose(b.clone()));
+                let a_t = self.append_input(Op::Transpose(a.clone()));
+                let da = self.append_input(Op::MatMul(delta.clone(), b_t));
+                let db = self.append_input(Op::MatMul(a_t, delta));
+                vec![(a, da), (b, db)]
+            }
+            Op::Transpose(a) => vec![(a, self.append_input(Op::Transpose(delta)))],
+            // The delta is broadcast back out to the shape of the input.
+            Op::Unbroadcast(a, _) => {
+                let ones = self.append_input(Op::OnesLike(a.clone()));
+                vec![(a, self.append_input(Op::Mul(ones, delta)))]
+            }
+            // Each of these is linear in both of its inputs, and their deltas are made of the
+            // same three ops.
+            Op::Conv2d(input, kernel) => {
+                let input_delta =
+                    self.append_input(Op::Conv2dInputDelta(delta.clone(), kernel.clone()));
+                let kernel_delta = self.append_input(Op::Conv2dKernelDelta(input.clone(), delta));
+                vec![(input, input_delta), (kernel, kernel_delta)]
+            }
+            Op::Conv2dInputDelta(output_delta, kernel) => {
+                let dd = self.append_input(Op::Conv2d(delta.clone(), kernel.clone()));
+                let dk = self.append_input(Op::Conv2dKernelDelta(delta, output_delta.clone()));
+                vec![(output_delta, dd), (kernel, dk)]
+            }
+            Op::Conv2dKernelDelta(input, output_delta) => {
+                let di = self.append_input(Op::Conv2dInputDelta(output_delta.clone(), delta.clone()));
+                let dd = self.append_input(Op::Conv2d(input.clone(), delta));
+                vec![(input, di), (output_delta, dd)]
+            }
+            Op::OnesLike(_) | Op::Sign(_) | Op::TrainConst(..) | Op::StopGradient(_) => vec![],
+            Op::CustomGradient(_, _, name) => panic!(
+                "custom gradient \"{}\" can't be built into the graph, since it only exists in the backend",
+                name
+            ),
+        }
+    }
+}
+
+/// The deltas of every node and feed found so far.
+#[derive(Default)]
+struct Deltas {
+    nodes: HashMap<usize, Input>,
+    feeds: HashMap<String, Input>,
+}
+
+impl Deltas {
+    /// Adds a delta to `input`, summing it with any delta it already has.
+    fn add(&mut self, graph: &mut Graph, input: Input, delta: Input) {
+        let existing = match &input {
+            Input::Feed(name) => self.feeds.remove(name),
+            Input::Internal(internal) => self.nodes.remove(&internal.node),
+        };
+        let delta = match existing {
+            Some(existing) => graph.append_input(Op::Add(existing, delta)),
+            None => delta,
+        };
+        match input {
+            Input::Feed(name) => {
+                self.feeds.insert(name, delta);
+            }
+            Input::Internal(internal) => {
+                self.nodes.insert(internal.node, delta);
+            }
+        }
+    }
+}
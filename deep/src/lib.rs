@@ -1,11 +1,15 @@
 #[macro_use]
 extern crate strum_macros;
 
+mod grad;
 mod tensor;
 
 pub use tensor::Tensor;
 
 use rand_core::RngCore;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Internal {
@@ -15,9 +19,41 @@ pub struct Internal {
     pub output: usize,
 }
 
+/// The identity of a node, which is unique across every graph in the process.
+///
+/// A node keeps its identity when it is copied into another graph by a merge, so copies of the
+/// same node are recognised as one node when their graphs are merged again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u64);
+
+impl NodeId {
+    fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        NodeId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Two different nodes of graphs that were merged have the same name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameCollision {
+    pub name: String,
+}
+
+impl fmt::Display for NameCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "name \"{}\" is used by different nodes in the merged graphs",
+            self.name
+        )
+    }
+}
+
+impl std::error::Error for NameCollision {}
+
 impl Internal {
-    fn shift_inputs(&mut self, shift: usize) {
-        self.node += shift;
+    fn remap_inputs(&mut self, nodes: &[usize]) {
+        self.node = nodes[self.node];
     }
 }
 
@@ -27,29 +63,111 @@ pub enum Op {
     Add(Input, Input),
     Sub(Input, Input),
     Square(Input),
+    Mul(Input, Input),
+    /// Multiplies the input by a constant.
+    Scale(Input, f64),
+    /// Sums all the elements of the input into a scalar.
+    Sum(Input),
+    /// A tensor of ones with the same shape as the input.
+    OnesLike(Input),
+    /// The absolute value of every element.
+    Abs(Input),
+    /// The sign of every element, which is -1, 0 or 1.
+    Sign(Input),
+    /// The matrix product of two matrices.
+    MatMul(Input, Input),
+    /// Swaps the two axes of a matrix.
+    Transpose(Input),
+    /// The 2D convolution (as a cross-correlation) of a batch of images with shape
+    /// `[batch, channels, height, width]` and a kernel with shape
+    /// `[filters, channels, kernel_height, kernel_width]`.
+    ///
+    /// The stride is one and there is no padding, so the output has the shape
+    /// `[batch, filters, height - kernel_height + 1, width - kernel_width + 1]`.
+    Conv2d(Input, Input),
+    /// The delta of the images of an `Op::Conv2d` from the delta of its output and its kernel.
+    Conv2dInputDelta(Input, Input),
+    /// The delta of the kernel of an `Op::Conv2d` from its images and the delta of its output.
+    Conv2dKernelDelta(Input, Input),
+    /// Sums the first input over the axes that were broadcast to turn a tensor with the shape
+    /// of the second input into its shape, which gives the delta of a broadcast operand.
+    Unbroadcast(Input, Input),
     TrainConst(Vec<usize>, f64),
+    /// Passes its input through unchanged, but no delta is propogated back through it.
+    StopGradient(Input),
+    /// Passes the first input through unchanged, but instead of propogating the delta back
+    /// through it, the gradient registered with the backend under the given name computes
+    /// the deltas of the other inputs.
+    CustomGradient(Input, Vec<Input>, String),
 }
 
 impl Op {
-    fn shift_inputs(&mut self, shift: usize) {
+    /// Gets all the inputs of the op in order.
+    pub fn inputs(&self) -> Vec<&Input> {
         match self {
-            Self::Add(a, b) => {
-                a.shift_inputs(shift);
-                b.shift_inputs(shift);
+            Self::Add(a, b) => vec![a, b],
+            Self::Sub(a, b) => vec![a, b],
+            Self::Square(a) => vec![a],
+            Self::Mul(a, b) => vec![a, b],
+            Self::Scale(a, _) => vec![a],
+            Self::Sum(a) => vec![a],
+            Self::OnesLike(a) => vec![a],
+            Self::Abs(a) => vec![a],
+            Self::Sign(a) => vec![a],
+            Self::MatMul(a, b) => vec![a, b],
+            Self::Transpose(a) => vec![a],
+            Self::Conv2d(a, b) | Self::Conv2dInputDelta(a, b) | Self::Conv2dKernelDelta(a, b) => {
+                vec![a, b]
             }
-            Self::Sub(a, b) => {
-                a.shift_inputs(shift);
-                b.shift_inputs(shift);
-            }
-            Self::Square(a) => {
-                a.shift_inputs(shift);
+            Self::Unbroadcast(a, like) => vec![a, like],
+            Self::TrainConst(..) => vec![],
+            Self::StopGradient(a) => vec![a],
+            Self::CustomGradient(a, inputs, _) => std::iter::once(a).chain(inputs).collect(),
+        }
+    }
+
+    /// Gets all the inputs of the op in order.
+    pub fn inputs_mut(&mut self) -> Vec<&mut Input> {
+        match self {
+            Self::Add(a, b) => vec![a, b],
+            Self::Sub(a, b) => vec![a, b],
+            Self::Square(a) => vec![a],
+            Self::Mul(a, b) => vec![a, b],
+            Self::Scale(a, _) => vec![a],
+            Self::Sum(a) => vec![a],
+            Self::OnesLike(a) => vec![a],
+            Self::Abs(a) => vec![a],
+            Self::Sign(a) => vec![a],
+            Self::MatMul(a, b) => vec![a, b],
+            Self::Transpose(a) => vec![a],
+            Self::Conv2d(a, b) | Self::Conv2dInputDelta(a, b) | Self::Conv2dKernelDelta(a, b) => {
+                vec![a, b]
             }
-            Self::TrainConst(..) => {}
+            Self::Unbroadcast(a, like) => vec![a, like],
+            Self::TrainConst(..) => vec![],
+            Self::StopGradient(a) => vec![a],
+            Self::CustomGradient(a, inputs, _) => std::iter::once(a).chain(inputs).collect(),
+        }
+    }
+
+    /// Gets the inputs that a delta is propogated to during backprop.
+    pub fn delta_inputs(&self) -> Vec<&Input> {
+        match self {
+            Self::OnesLike(_) | Self::Sign(_) | Self::StopGradient(_) => vec![],
+            Self::CustomGradient(_, inputs, _) => inputs.iter().collect(),
+            Self::Unbroadcast(a, _) => vec![a],
+            op => op.inputs(),
+        }
+    }
+
+    fn remap_inputs(&mut self, nodes: &[usize]) {
+        for input in self.inputs_mut() {
+            input.remap_inputs(nodes);
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Input {
     // An input from the feed dict.
     Feed(String),
@@ -58,9 +176,9 @@ pub enum Input {
 }
 
 impl Input {
-    fn shift_inputs(&mut self, shift: usize) {
+    fn remap_inputs(&mut self, nodes: &[usize]) {
         if let Self::Internal(n) = self {
-            n.shift_inputs(shift);
+            n.remap_inputs(nodes);
         }
     }
 }
@@ -75,6 +193,24 @@ impl From<&str> for Input {
 pub struct Graph {
     /// A series of ops refering to each other's outputs for their input.
     pub ops: Vec<Op>,
+    /// The identity of each node in `ops`.
+    ///
+    /// Nodes pushed onto `ops` directly rather than with `append` get an identity when the
+    /// graph is next merged.
+    pub ids: Vec<NodeId>,
+    /// Stable hierarchical names (such as `encoder/layer1/weight`) of nodes in the graph.
+    ///
+    /// Unlike node indices, names survive edits to the graph, so they are used to identify
+    /// parameters when state is transferred between graphs.
+    pub names: BTreeMap<String, usize>,
+    /// Nodes whose state is not trained.
+    ///
+    /// Frozen nodes never recieve a delta, and backprop does not descend into parts of the
+    /// graph that only contain frozen state.
+    pub frozen: BTreeSet<usize>,
+    /// Nodes whose values aren't kept after the forward pass, but are recomputed from the
+    /// values they depend on when backprop needs them.
+    pub checkpointed: BTreeSet<usize>,
 }
 
 impl Graph {
@@ -82,26 +218,215 @@ impl Graph {
         Self::default()
     }
 
-    pub fn merge(&mut self, other: Graph) {
-        let current = self.ops.len();
-        self.ops.extend(other.ops);
-        for op in &mut self.ops[current..] {
-            op.shift_inputs(current);
+    /// Appends all the nodes of `other` to this graph.
+    ///
+    /// A node of `other` that is a copy of a node already in this graph (because both came
+    /// from the same `Tensor`) is not appended, and everything that used it refers to the
+    /// existing node instead. This is how parameters are shared between graphs.
+    ///
+    /// Returns an error, leaving this graph unchanged, if a name of `other` is already used by
+    /// a different node of this graph.
+    pub fn merge(&mut self, other: Graph) -> Result<(), NameCollision> {
+        self.merge_nodes(other).map(|_| ())
+    }
+
+    /// Merges `other` into this graph as per `merge` and gives back where `input` of `other`
+    /// is in this graph.
+    pub fn merge_input(&mut self, other: Graph, mut input: Input) -> Result<Input, NameCollision> {
+        let nodes = self.merge_nodes(other)?;
+        input.remap_inputs(&nodes);
+        Ok(input)
+    }
+
+    /// Merges `other` into this graph and returns the new index of each node in `other`.
+    fn merge_nodes(&mut self, mut other: Graph) -> Result<Vec<usize>, NameCollision> {
+        self.assign_ids();
+        other.assign_ids();
+        let existing: HashMap<NodeId, usize> = self
+            .ids
+            .iter()
+            .enumerate()
+            .map(|(node, &id)| (id, node))
+            .collect();
+        let shared: Vec<Option<usize>> = other
+            .ids
+            .iter()
+            .map(|id| existing.get(id).cloned())
+            .collect();
+
+        let mut next = self.ops.len();
+        let nodes: Vec<usize> = shared
+            .iter()
+            .map(|shared| {
+                shared.unwrap_or_else(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect();
+        for (name, &node) in &other.names {
+            if self
+                .node(name)
+                .is_some_and(|existing| existing != nodes[node])
+            {
+                return Err(NameCollision { name: name.clone() });
+            }
+        }
+
+        for ((mut op, id), shared) in other.ops.into_iter().zip(other.ids).zip(shared) {
+            if shared.is_none() {
+                op.remap_inputs(&nodes);
+                self.ops.push(op);
+                self.ids.push(id);
+            }
+        }
+        for (name, node) in other.names {
+            self.names.insert(name, nodes[node]);
+        }
+        self.frozen
+            .extend(other.frozen.into_iter().map(|node| nodes[node]));
+        self.checkpointed
+            .extend(other.checkpointed.into_iter().map(|node| nodes[node]));
+        Ok(nodes)
+    }
+
+    /// Gives an identity to every node that doesn't have one yet.
+    fn assign_ids(&mut self) {
+        while self.ids.len() < self.ops.len() {
+            self.ids.push(NodeId::new());
         }
     }
 
-    pub fn merge_input(&mut self, other: Graph, mut input: Input) -> Input {
-        let current = self.ops.len();
-        self.merge(other);
-        input.shift_inputs(current);
-        input
+    /// Finds the node of this graph that is `id` or a copy of it.
+    pub fn node_by_id(&self, id: NodeId) -> Option<usize> {
+        self.ids.iter().position(|&n| n == id)
     }
 
     /// Returns the node index of the appended op.
     pub fn append(&mut self, op: Op) -> usize {
+        self.assign_ids();
         self.ops.push(op);
+        self.ids.push(NodeId::new());
         self.ops.len() - 1
     }
+
+    /// Gives a node a name.
+    ///
+    /// Panics if the name is already used by a different node.
+    pub fn set_name(&mut self, name: String, node: usize) {
+        if let Some(&existing) = self.names.get(&name) {
+            assert_eq!(
+                existing, node,
+                "name \"{}\" is already used by node {}",
+                name, existing
+            );
+        }
+        self.names.insert(name, node);
+    }
+
+    /// Gets the node with the given name.
+    pub fn node(&self, name: &str) -> Option<usize> {
+        self.names.get(name).cloned()
+    }
+
+    /// Gets the name of a node if it has one.
+    pub fn node_name(&self, node: usize) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|&(_, &n)| n == node)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Stops a node's state from being trained.
+    pub fn freeze(&mut self, node: usize) {
+        self.frozen.insert(node);
+    }
+
+    /// Allows a frozen node's state to be trained again.
+    pub fn unfreeze(&mut self, node: usize) {
+        self.frozen.remove(&node);
+    }
+
+    /// Freezes the node named `scope` and every node named under it (such as `scope/weight`).
+    pub fn freeze_scope(&mut self, scope: &str) {
+        let nodes: Vec<usize> = self.scope_nodes(scope).collect();
+        self.frozen.extend(nodes);
+    }
+
+    /// Unfreezes the node named `scope` and every node named under it (such as `scope/weight`).
+    pub fn unfreeze_scope(&mut self, scope: &str) {
+        let nodes: Vec<usize> = self.scope_nodes(scope).collect();
+        for node in nodes {
+            self.frozen.remove(&node);
+        }
+    }
+
+    /// Checks if a node's state is frozen.
+    pub fn is_frozen(&self, node: usize) -> bool {
+        self.frozen.contains(&node)
+    }
+
+    /// Checkpoints the nodes that `output` depends on up to (but not including) `inputs`, so
+    /// that only `inputs` and `output` are kept from the segment between them.
+    ///
+    /// This trades compute for memory, since the segment is computed again during backprop.
+    pub fn checkpoint(&mut self, output: &Input, inputs: &[Input]) {
+        let boundary: BTreeSet<usize> = inputs
+            .iter()
+            .filter_map(|input| match input {
+                Input::Internal(internal) => Some(internal.node),
+                Input::Feed(_) => None,
+            })
+            .collect();
+        let mut stack = match output {
+            Input::Internal(internal) => self.ops[internal.node]
+                .inputs()
+                .into_iter()
+                .cloned()
+                .collect(),
+            Input::Feed(_) => vec![],
+        };
+        while let Some(input) = stack.pop() {
+            if let Input::Internal(internal) = input {
+                if !boundary.contains(&internal.node) && self.checkpointed.insert(internal.node) {
+                    stack.extend(self.ops[internal.node].inputs().into_iter().cloned());
+                }
+            }
+        }
+    }
+
+    /// Checks if a node is recomputed during backprop rather than kept.
+    pub fn is_checkpointed(&self, node: usize) -> bool {
+        self.checkpointed.contains(&node)
+    }
+
+    /// Gets the parameters of the graph, which are the `Op::TrainConst` nodes that aren't frozen.
+    pub fn parameters(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ops
+            .iter()
+            .enumerate()
+            .filter(move |&(node, op)| matches!(op, Op::TrainConst(..)) && !self.is_frozen(node))
+            .map(|(node, _)| node)
+    }
+
+    /// Gets the nodes named `scope` or named under `scope`.
+    pub fn scope_nodes<'a>(&'a self, scope: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.names
+            .iter()
+            .filter(move |(name, _)| {
+                name.starts_with(scope)
+                    && (name.len() == scope.len() || name[scope.len()..].starts_with('/'))
+            })
+            .map(|(_, &node)| node)
+    }
+
+    /// Places every name in the graph under `scope`, so `weight` becomes `scope/weight`.
+    pub fn scope(&mut self, scope: &str) {
+        self.names = std::mem::take(&mut self.names)
+            .into_iter()
+            .map(|(name, node)| (format!("{}/{}", scope, name), node))
+            .collect();
+    }
 }
 
 pub trait Backend {
@@ -126,6 +451,18 @@ pub trait Backend {
         tensor: Input,
     ) -> Result<(Self::Tensor, Self::Internal), Self::Error>;
 
+    /// Gets the outputs of solving each of the requested tensors in one forward pass, so the
+    /// values they depend on are only computed once. The `Internal` can be used to backprop
+    /// from any of them.
+    #[allow(clippy::type_complexity)]
+    fn forward_many(
+        &self,
+        graph: &Graph,
+        state: &Self::State,
+        inputs: &Self::Inputs,
+        tensors: &[Input],
+    ) -> Result<(Vec<Self::Tensor>, Self::Internal), Self::Error>;
+
     /// Propogates a delta from the output back to the input via chain rule
     /// and produces a `Delta` that can be used to update the graph
     /// with an optimizer. The `Delta` contains all the dE/dx of all internal
@@ -140,6 +477,38 @@ pub trait Backend {
         output_delta: Self::Tensor,
     ) -> Result<Self::Delta, Self::Error>;
 
+    /// This is the same as `backward`, but the deltas of several outputs from `forward_many`
+    /// are propogated at once and their contributions are summed into one `Delta`.
+    fn backward_many(
+        &self,
+        graph: &Graph,
+        state: &Self::State,
+        internal: &Self::Internal,
+        inputs: &Self::Inputs,
+        output_deltas: Vec<(Input, Self::Tensor)>,
+    ) -> Result<Self::Delta, Self::Error>;
+
+    /// This is the same as `backward`, but it also produces the delta of every input from the
+    /// feed dict that contributed to the output, such as for saliency maps or adversarial
+    /// examples. The input deltas are given in the same form as the `Inputs`.
+    fn backward_inputs(
+        &self,
+        graph: &Graph,
+        state: &Self::State,
+        internal: &Self::Internal,
+        inputs: &Self::Inputs,
+        tensor: Input,
+        output_delta: Self::Tensor,
+    ) -> Result<(Self::Delta, Self::Inputs), Self::Error>;
+
     /// Applies a delta to the graph's state.
-    fn train(&self, state: &mut Self::State, delta: &Self::Delta) -> Result<(), Self::Error>;
+    ///
+    /// The deltas of frozen nodes are ignored, even if the `Delta` was built by hand or merged
+    /// from other deltas.
+    fn train(
+        &self,
+        graph: &Graph,
+        state: &mut Self::State,
+        delta: &Self::Delta,
+    ) -> Result<(), Self::Error>;
 }
@@ -1,9 +1,18 @@
 use crate::{Backend, Graph, Input, Internal, Op};
 use rand_core::RngCore;
-use std::cell::RefCell;
-use std::ops::{Add, Sub};
+use std::cell::{Ref, RefCell};
+use std::ops::{Add, Mul, Neg, Sub};
 use std::rc::Rc;
 
+/// A handle to the output of a node in a graph.
+///
+/// Cloning a `Tensor` refers to the same node, so a parameter can be declared once and used
+/// from many places, even in separate graphs that are combined later. Its gradients from every
+/// use are summed in the `Delta`.
+///
+/// Combining tensors from different graphs panics if a name is used by different nodes of the
+/// graphs, since operators can't return an error. Use `Graph::merge` to get the error instead.
+#[derive(Clone)]
 pub struct Tensor {
     graph: Rc<RefCell<Graph>>,
     input: Input,
@@ -12,16 +21,193 @@ pub struct Tensor {
 impl Tensor {
     pub fn train_const(shape: Vec<usize>, value: f64) -> Self {
         let mut graph: Graph = Default::default();
-        graph.ops.push(Op::TrainConst(shape, value));
+        graph.append(Op::TrainConst(shape, value));
         Tensor {
             graph: Rc::new(RefCell::new(graph)),
             input: Input::Internal(Internal { node: 0, output: 0 }),
         }
     }
 
+    /// Names the node this tensor refers to, such as `encoder/layer1/weight`.
+    ///
+    /// Panics if the tensor comes directly from the feed dict, since those are already named.
+    pub fn named(self, name: impl Into<String>) -> Self {
+        match &self.input {
+            Input::Internal(internal) => {
+                self.graph.borrow_mut().set_name(name.into(), internal.node)
+            }
+            Input::Feed(feed) => panic!("cannot name feed \"{}\"", feed),
+        }
+        self
+    }
+
+    /// Places every name in this tensor's graph under `scope`.
+    pub fn scoped(self, scope: &str) -> Self {
+        self.graph.borrow_mut().scope(scope);
+        self
+    }
+
+    /// Freezes the node this tensor refers to so that its state is not trained.
+    pub fn frozen(self) -> Self {
+        if let Input::Internal(internal) = &self.input {
+            self.graph.borrow_mut().freeze(internal.node);
+        }
+        self
+    }
+
+    /// Freezes every node in this tensor's graph named `scope` or named under `scope`.
+    pub fn freeze(&self, scope: &str) {
+        self.graph.borrow_mut().freeze_scope(scope);
+    }
+
+    /// Unfreezes every node in this tensor's graph named `scope` or named under `scope`.
+    pub fn unfreeze(&self, scope: &str) {
+        self.graph.borrow_mut().unfreeze_scope(scope);
+    }
+
+    /// Checkpoints the segment of the graph between `inputs` and this tensor, as per
+    /// `Graph::checkpoint`.
+    pub fn checkpoint(self, inputs: &[Tensor]) -> Self {
+        let inputs: Vec<Input> = inputs.iter().map(|tensor| self.locate(tensor)).collect();
+        self.graph.borrow_mut().checkpoint(&self.input, &inputs);
+        self
+    }
+
+    /// Gets the input that refers to this tensor in its graph.
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// Borrows the graph that computes this tensor.
+    pub fn graph(&self) -> Ref<'_, Graph> {
+        self.graph.borrow()
+    }
+
     pub fn squared(&self) -> Self {
+        self.unary(Op::Square)
+    }
+
+    /// Multiplies every element by `scale`.
+    pub fn scale(&self, scale: f64) -> Self {
+        self.unary(|a| Op::Scale(a, scale))
+    }
+
+    /// Sums every element into a scalar.
+    pub fn sum(&self) -> Self {
+        self.unary(Op::Sum)
+    }
+
+    /// A tensor of ones with the same shape as this tensor.
+    pub fn ones_like(&self) -> Self {
+        self.unary(Op::OnesLike)
+    }
+
+    /// The absolute value of every element.
+    pub fn abs(&self) -> Self {
+        self.unary(Op::Abs)
+    }
+
+    /// The sign of every element, which is -1, 0 or 1.
+    pub fn sign(&self) -> Self {
+        self.unary(Op::Sign)
+    }
+
+    /// The matrix product of this matrix and `rhs`.
+    pub fn matmul(&self, rhs: &Tensor) -> Self {
+        merge2_1(self.clone(), rhs.clone(), Op::MatMul)
+    }
+
+    /// Swaps the two axes of this matrix.
+    pub fn transpose(&self) -> Self {
+        self.unary(Op::Transpose)
+    }
+
+    /// Convolves this batch of images with `kernel`, as per `Op::Conv2d`.
+    pub fn conv2d(&self, kernel: &Tensor) -> Self {
+        merge2_1(self.clone(), kernel.clone(), Op::Conv2d)
+    }
+
+    /// Builds the L2 penalty `scale * sum(w^2)` of the parameters in this tensor's graph, which
+    /// can be added to a loss to regularize it.
+    ///
+    /// `include` is given the name of each parameter from `Graph::parameters` (if it has one),
+    /// and can exclude it by returning false, such as to not penalize biases.
+    pub fn l2_penalty(&self, scale: f64, include: impl Fn(Option<&str>) -> bool) -> Self {
+        self.penalty(scale, include, Op::Square)
+    }
+
+    /// Builds the L1 penalty `scale * sum(|w|)` of the parameters in this tensor's graph.
+    ///
+    /// Parameters are included as per `l2_penalty`.
+    pub fn l1_penalty(&self, scale: f64, include: impl Fn(Option<&str>) -> bool) -> Self {
+        self.penalty(scale, include, Op::Abs)
+    }
+
+    /// Builds the gradient of this tensor (summed over all of its elements) with respect to `wrt`
+    /// into the graph.
+    ///
+    /// `wrt` can be an input from the feed dict or any tensor from this tensor's graph, including
+    /// parameters that were shared into it. The gradient is a normal tensor, so it can
+    /// be differentiated again for higher-order derivatives.
+    pub fn grad(&self, wrt: &Tensor) -> Self {
+        self.grads(std::slice::from_ref(wrt)).pop().unwrap()
+    }
+
+    /// Builds the gradients of this tensor with respect to each of `wrt` at once.
+    ///
+    /// This is cheaper than calling `grad` for each one, since the work is shared.
+    pub fn grads(&self, wrt: &[Tensor]) -> Vec<Self> {
+        let inputs: Vec<Input> = wrt.iter().map(|tensor| self.locate(tensor)).collect();
+        let gradients = self
+            .graph
+            .borrow_mut()
+            .gradients(self.input.clone(), &inputs);
+        gradients
+            .into_iter()
+            .zip(inputs)
+            .map(|(gradient, input)| {
+                let mut graph = self.graph.borrow_mut();
+                // Nothing depends on `wrt`, so its gradient is zero.
+                let input = gradient.unwrap_or_else(|| {
+                    let ones = graph.append_input(Op::OnesLike(input));
+                    graph.append_input(Op::Scale(ones, 0.0))
+                });
+                Self {
+                    graph: self.graph.clone(),
+                    input,
+                }
+            })
+            .collect()
+    }
+
+    /// Passes this tensor through unchanged, but stops any delta from propogating back through it.
+    pub fn stop_gradient(&self) -> Self {
+        self.unary(Op::StopGradient)
+    }
+
+    /// Passes this tensor through unchanged, but replaces the gradient of the subgraph that
+    /// computes it with the gradient registered with the backend under `name`.
+    ///
+    /// The custom gradient recieves the values of `inputs` and produces their deltas.
+    pub fn custom_gradient(&self, name: impl Into<String>, inputs: &[Tensor]) -> Self {
         let graph = self.graph.clone();
-        let node = graph.borrow_mut().append(Op::Square(self.input.clone()));
+        let inputs = inputs
+            .iter()
+            .map(|tensor| {
+                if Rc::ptr_eq(&graph, &tensor.graph) {
+                    tensor.input.clone()
+                } else {
+                    graph
+                        .borrow_mut()
+                        .merge_input(tensor.graph.borrow().clone(), tensor.input.clone())
+                        .unwrap_or_else(|e| panic!("{}", e))
+                }
+            })
+            .collect();
+        let node =
+            graph
+                .borrow_mut()
+                .append(Op::CustomGradient(self.input.clone(), inputs, name.into()));
         Self {
             graph,
             input: Input::Internal(Internal { node, output: 0 }),
@@ -51,34 +237,33 @@ impl Tensor {
             .map(|(output, _)| output)
     }
 
-    /// Train the graph with this tensor as a loss function using gradient descent.
+    /// Computes the delta of the graph's state with this tensor as the output.
     ///
-    /// Must be provided a way to convert the loss tensor into a `f32` and a `f32` to a tensor.
+    /// `output_delta` is given the value of this tensor and must give back anything it wants
+    /// to extract from it, such as the loss, along with the delta of this tensor.
     ///
-    /// Returns the loss before training.
-    pub fn gradient_descent<B>(
+    /// Returns what was extracted along with the `Delta`, which isn't applied to the state.
+    pub fn delta<B, L>(
         &self,
         backend: &B,
-        state: &mut B::State,
+        state: &B::State,
         inputs: &B::Inputs,
-        learning_rate: f32,
-        tensor_loss: fn(B::Tensor) -> f32,
-        delta_tensor: fn(f32) -> B::Tensor,
-    ) -> Result<f32, B::Error>
+        output_delta: impl FnOnce(B::Tensor) -> (L, B::Tensor),
+    ) -> Result<(L, B::Delta), B::Error>
     where
         B: Backend,
     {
+        let graph = self.graph.borrow();
+
         // Perform the forward pass.
-        let (output, internal) =
-            backend.forward(&self.graph.borrow(), state, inputs, self.input.clone())?;
+        let (output, internal) = backend.forward(&graph, state, inputs, self.input.clone())?;
 
         // Extract the loss and compute the output delta.
-        let loss = tensor_loss(output);
-        let output_delta = delta_tensor(-learning_rate * loss);
+        let (extracted, output_delta) = output_delta(output);
 
         // Propogate the output delta back through the network.
         let delta = backend.backward(
-            &self.graph.borrow(),
+            &graph,
             state,
             &internal,
             inputs,
@@ -86,11 +271,176 @@ impl Tensor {
             output_delta,
         )?;
 
+        Ok((extracted, delta))
+    }
+
+    /// Train the graph with this tensor as a loss function using gradient descent.
+    ///
+    /// This computes the `Delta` as per `delta` and applies it to the state. Deltas are added to
+    /// the state, so the delta of the loss should be scaled by the negative learning rate.
+    ///
+    /// Returns what was extracted by `output_delta`, such as the loss before training, along with
+    /// the `Delta` that was applied.
+    pub fn gradient_descent<B, L>(
+        &self,
+        backend: &B,
+        state: &mut B::State,
+        inputs: &B::Inputs,
+        output_delta: impl FnOnce(B::Tensor) -> (L, B::Tensor),
+    ) -> Result<(L, B::Delta), B::Error>
+    where
+        B: Backend,
+    {
+        let (extracted, delta) = self.delta(backend, state, inputs, output_delta)?;
+
         // Train the network.
-        backend.train(state, &delta)?;
+        backend.train(&self.graph.borrow(), state, &delta)?;
+
+        Ok((extracted, delta))
+    }
+
+    /// Computes the values of several tensors from the same graph in one forward pass, so
+    /// anything they share is only computed once.
+    ///
+    /// Panics if `tensors` is empty.
+    pub fn eval_many<B>(
+        tensors: &[Tensor],
+        backend: &B,
+        state: &B::State,
+        inputs: &B::Inputs,
+    ) -> Result<Vec<B::Tensor>, B::Error>
+    where
+        B: Backend,
+    {
+        let (first, outputs) = Self::outputs(tensors);
+        let graph = first.graph.borrow();
+        backend
+            .forward_many(&graph, state, inputs, &outputs)
+            .map(|(values, _)| values)
+    }
+
+    /// Computes the delta of the graph's state with several tensors as outputs, such as the
+    /// losses of a multi-task model.
+    ///
+    /// `output_delta` is given the value of each tensor and must give back anything it wants
+    /// to extract from them along with the delta of each tensor. A tensor with no delta (such
+    /// as a prediction that is only evaluated) doesn't contribute to the `Delta`.
+    ///
+    /// Panics if `tensors` is empty.
+    pub fn delta_many<B, L>(
+        tensors: &[Tensor],
+        backend: &B,
+        state: &B::State,
+        inputs: &B::Inputs,
+        output_delta: impl FnOnce(Vec<B::Tensor>) -> (L, Vec<Option<B::Tensor>>),
+    ) -> Result<(L, B::Delta), B::Error>
+    where
+        B: Backend,
+    {
+        let (first, outputs) = Self::outputs(tensors);
+        let graph = first.graph.borrow();
+        let (values, internal) = backend.forward_many(&graph, state, inputs, &outputs)?;
+        let (extracted, output_deltas) = output_delta(values);
+        assert_eq!(
+            output_deltas.len(),
+            outputs.len(),
+            "there must be one output delta per tensor"
+        );
+        let output_deltas = outputs
+            .into_iter()
+            .zip(output_deltas)
+            .filter_map(|(output, delta)| delta.map(|delta| (output, delta)))
+            .collect();
+        let delta = backend.backward_many(&graph, state, &internal, inputs, output_deltas)?;
+        Ok((extracted, delta))
+    }
+
+    /// Trains the graph with several tensors as outputs using gradient descent.
+    ///
+    /// This computes the `Delta` as per `delta_many` and applies it to the state.
+    ///
+    /// Panics if `tensors` is empty.
+    pub fn gradient_descent_many<B, L>(
+        tensors: &[Tensor],
+        backend: &B,
+        state: &mut B::State,
+        inputs: &B::Inputs,
+        output_delta: impl FnOnce(Vec<B::Tensor>) -> (L, Vec<Option<B::Tensor>>),
+    ) -> Result<(L, B::Delta), B::Error>
+    where
+        B: Backend,
+    {
+        let (extracted, delta) = Self::delta_many(tensors, backend, state, inputs, output_delta)?;
+        backend.train(&tensors[0].graph.borrow(), state, &delta)?;
+        Ok((extracted, delta))
+    }
+}
 
-        // Return the loss.
-        Ok(loss)
+impl Tensor {
+    /// Appends an op on this tensor to its graph.
+    fn unary(&self, make_op: impl FnOnce(Input) -> Op) -> Self {
+        let graph = self.graph.clone();
+        let input = graph.borrow_mut().append_input(make_op(self.input.clone()));
+        Self { graph, input }
+    }
+
+    /// Sums `elementwise` of each included parameter and scales the total.
+    fn penalty(
+        &self,
+        scale: f64,
+        include: impl Fn(Option<&str>) -> bool,
+        elementwise: fn(Input) -> Op,
+    ) -> Self {
+        let mut graph = self.graph.borrow_mut();
+        let parameters: Vec<usize> = graph
+            .parameters()
+            .filter(|&node| include(graph.node_name(node)))
+            .collect();
+        let mut total = None;
+        for node in parameters {
+            let parameter = Input::Internal(Internal { node, output: 0 });
+            let penalty = graph.append_input(elementwise(parameter));
+            let penalty = graph.append_input(Op::Sum(penalty));
+            total = Some(match total {
+                Some(total) => graph.append_input(Op::Add(total, penalty)),
+                None => penalty,
+            });
+        }
+        // Without any parameters, the penalty is a scalar zero.
+        let total = total.unwrap_or_else(|| {
+            let ones = graph.append_input(Op::OnesLike(self.input.clone()));
+            let zeros = graph.append_input(Op::Scale(ones, 0.0));
+            graph.append_input(Op::Sum(zeros))
+        });
+        let input = graph.append_input(Op::Scale(total, scale));
+        Self {
+            graph: self.graph.clone(),
+            input,
+        }
+    }
+
+    /// Gets the first of `tensors`, whose graph is evaluated, and where each of them is in it.
+    fn outputs(tensors: &[Tensor]) -> (&Tensor, Vec<Input>) {
+        let first = tensors.first().expect("there must be at least one tensor");
+        (first, tensors.iter().map(|t| first.locate(t)).collect())
+    }
+
+    /// Finds the input that refers to `other` in this tensor's graph.
+    fn locate(&self, other: &Tensor) -> Input {
+        match &other.input {
+            Input::Feed(_) => other.input.clone(),
+            _ if Rc::ptr_eq(&self.graph, &other.graph) => other.input.clone(),
+            Input::Internal(internal) => {
+                let id = other.graph.borrow().ids.get(internal.node).cloned();
+                let node = id
+                    .and_then(|id| self.graph.borrow().node_by_id(id))
+                    .expect("tensor is not part of this graph");
+                Input::Internal(Internal {
+                    node,
+                    output: internal.output,
+                })
+            }
+        }
     }
 }
 
@@ -106,9 +456,15 @@ impl From<&str> for Tensor {
 fn merge2_1(a: Tensor, b: Tensor, make_op: impl Fn(Input, Input) -> Op) -> Tensor {
     let graph = a.graph;
     let a = a.input;
-    let b = graph
-        .borrow_mut()
-        .merge_input(b.graph.borrow().clone(), b.input);
+    // Tensors from the same graph already share all of their nodes.
+    let b = if Rc::ptr_eq(&graph, &b.graph) {
+        b.input
+    } else {
+        graph
+            .borrow_mut()
+            .merge_input(b.graph.borrow().clone(), b.input)
+            .unwrap_or_else(|e| panic!("{}", e))
+    };
     let node = graph.borrow_mut().append(make_op(a, b));
     Tensor {
         graph,
@@ -131,3 +487,19 @@ impl Sub for Tensor {
         merge2_1(self, rhs, Op::Sub)
     }
 }
+
+impl Mul for Tensor {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        merge2_1(self, rhs, Op::Mul)
+    }
+}
+
+impl Neg for Tensor {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self.scale(-1.0)
+    }
+}